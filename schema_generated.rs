@@ -37,6 +37,17 @@ impl<'a> State<'a> {
   pub const VT_BONDS: flatbuffers::VOffsetT = 22;
   pub const VT_BOND_INFO: flatbuffers::VOffsetT = 24;
   pub const VT_MATERIAL_POINTERS: flatbuffers::VOffsetT = 26;
+  pub const VT_WALLS: flatbuffers::VOffsetT = 28;
+  pub const VT_DAMPING_MODEL: flatbuffers::VOffsetT = 30;
+  pub const VT_LOCAL_DAMPING: flatbuffers::VOffsetT = 32;
+  pub const VT_CONTACT_RESTITUTION: flatbuffers::VOffsetT = 34;
+  pub const VT_CLUMP_POINTERS: flatbuffers::VOffsetT = 36;
+  pub const VT_POLYGON_INFO: flatbuffers::VOffsetT = 38;
+  pub const VT_POLYGON_VERTICES: flatbuffers::VOffsetT = 40;
+  pub const VT_TEMPERATURE: flatbuffers::VOffsetT = 42;
+  pub const VT_MATERIALS: flatbuffers::VOffsetT = 44;
+  pub const VT_MATERIAL_PAIRS: flatbuffers::VOffsetT = 46;
+  pub const VT_MATERIAL_NAMES: flatbuffers::VOffsetT = 48;
 
   #[inline]
   pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -48,6 +59,17 @@ impl<'a> State<'a> {
     args: &'args StateArgs<'args>
   ) -> flatbuffers::WIPOffset<State<'bldr>> {
     let mut builder = StateBuilder::new(_fbb);
+    if let Some(x) = args.material_names { builder.add_material_names(x); }
+    if let Some(x) = args.material_pairs { builder.add_material_pairs(x); }
+    if let Some(x) = args.materials { builder.add_materials(x); }
+    if let Some(x) = args.temperature { builder.add_temperature(x); }
+    if let Some(x) = args.polygon_vertices { builder.add_polygon_vertices(x); }
+    if let Some(x) = args.polygon_info { builder.add_polygon_info(x); }
+    if let Some(x) = args.clump_pointers { builder.add_clump_pointers(x); }
+    builder.add_contact_restitution(args.contact_restitution);
+    builder.add_local_damping(args.local_damping);
+    builder.add_damping_model(args.damping_model);
+    if let Some(x) = args.walls { builder.add_walls(x); }
     if let Some(x) = args.material_pointers { builder.add_material_pointers(x); }
     if let Some(x) = args.bond_info { builder.add_bond_info(x); }
     if let Some(x) = args.bonds { builder.add_bonds(x); }
@@ -148,6 +170,83 @@ impl<'a> State<'a> {
     // which contains a valid value in this slot
     unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, i32>>>(State::VT_MATERIAL_POINTERS, None)}
   }
+  #[inline]
+  pub fn walls(&self) -> Option<flatbuffers::Vector<'a, f32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, f32>>>(State::VT_WALLS, None)}
+  }
+  #[inline]
+  pub fn damping_model(&self) -> i32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<i32>(State::VT_DAMPING_MODEL, Some(0)).unwrap()}
+  }
+  #[inline]
+  pub fn local_damping(&self) -> f32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<f32>(State::VT_LOCAL_DAMPING, Some(0.7)).unwrap()}
+  }
+  #[inline]
+  pub fn contact_restitution(&self) -> f32 {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<f32>(State::VT_CONTACT_RESTITUTION, Some(0.5)).unwrap()}
+  }
+  #[inline]
+  pub fn clump_pointers(&self) -> Option<flatbuffers::Vector<'a, i32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, i32>>>(State::VT_CLUMP_POINTERS, None)}
+  }
+  #[inline]
+  pub fn polygon_info(&self) -> Option<flatbuffers::Vector<'a, i32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, i32>>>(State::VT_POLYGON_INFO, None)}
+  }
+  #[inline]
+  pub fn polygon_vertices(&self) -> Option<flatbuffers::Vector<'a, f32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, f32>>>(State::VT_POLYGON_VERTICES, None)}
+  }
+  #[inline]
+  pub fn temperature(&self) -> Option<flatbuffers::Vector<'a, f32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, f32>>>(State::VT_TEMPERATURE, None)}
+  }
+  #[inline]
+  pub fn materials(&self) -> Option<flatbuffers::Vector<'a, f32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, f32>>>(State::VT_MATERIALS, None)}
+  }
+  #[inline]
+  pub fn material_pairs(&self) -> Option<flatbuffers::Vector<'a, f32>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, f32>>>(State::VT_MATERIAL_PAIRS, None)}
+  }
+  #[inline]
+  pub fn material_names(&self) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>> {
+    // Safety:
+    // Created from valid Table for this object
+    // which contains a valid value in this slot
+    unsafe { self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>>>(State::VT_MATERIAL_NAMES, None)}
+  }
 }
 
 impl flatbuffers::Verifiable for State<'_> {
@@ -169,6 +268,17 @@ impl flatbuffers::Verifiable for State<'_> {
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, i32>>>("bonds", Self::VT_BONDS, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, i32>>>("bond_info", Self::VT_BOND_INFO, false)?
      .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, i32>>>("material_pointers", Self::VT_MATERIAL_POINTERS, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, f32>>>("walls", Self::VT_WALLS, false)?
+     .visit_field::<i32>("damping_model", Self::VT_DAMPING_MODEL, false)?
+     .visit_field::<f32>("local_damping", Self::VT_LOCAL_DAMPING, false)?
+     .visit_field::<f32>("contact_restitution", Self::VT_CONTACT_RESTITUTION, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, i32>>>("clump_pointers", Self::VT_CLUMP_POINTERS, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, i32>>>("polygon_info", Self::VT_POLYGON_INFO, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, f32>>>("polygon_vertices", Self::VT_POLYGON_VERTICES, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, f32>>>("temperature", Self::VT_TEMPERATURE, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, f32>>>("materials", Self::VT_MATERIALS, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, f32>>>("material_pairs", Self::VT_MATERIAL_PAIRS, false)?
+     .visit_field::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<&'_ str>>>>("material_names", Self::VT_MATERIAL_NAMES, false)?
      .finish();
     Ok(())
   }
@@ -186,6 +296,17 @@ pub struct StateArgs<'a> {
     pub bonds: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, i32>>>,
     pub bond_info: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, i32>>>,
     pub material_pointers: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, i32>>>,
+    pub walls: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, f32>>>,
+    pub damping_model: i32,
+    pub local_damping: f32,
+    pub contact_restitution: f32,
+    pub clump_pointers: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, i32>>>,
+    pub polygon_info: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, i32>>>,
+    pub polygon_vertices: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, f32>>>,
+    pub temperature: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, f32>>>,
+    pub materials: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, f32>>>,
+    pub material_pairs: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, f32>>>,
+    pub material_names: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>>>,
 }
 impl<'a> Default for StateArgs<'a> {
   #[inline]
@@ -203,6 +324,17 @@ impl<'a> Default for StateArgs<'a> {
       bonds: None,
       bond_info: None,
       material_pointers: None,
+      walls: None,
+      damping_model: 0,
+      local_damping: 0.7,
+      contact_restitution: 0.5,
+      clump_pointers: None,
+      polygon_info: None,
+      polygon_vertices: None,
+      temperature: None,
+      materials: None,
+      material_pairs: None,
+      material_names: None,
     }
   }
 }
@@ -261,6 +393,50 @@ impl<'a: 'b, 'b> StateBuilder<'a, 'b> {
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_MATERIAL_POINTERS, material_pointers);
   }
   #[inline]
+  pub fn add_walls(&mut self, walls: flatbuffers::WIPOffset<flatbuffers::Vector<'b , f32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_WALLS, walls);
+  }
+  #[inline]
+  pub fn add_damping_model(&mut self, damping_model: i32) {
+    self.fbb_.push_slot::<i32>(State::VT_DAMPING_MODEL, damping_model, 0);
+  }
+  #[inline]
+  pub fn add_local_damping(&mut self, local_damping: f32) {
+    self.fbb_.push_slot::<f32>(State::VT_LOCAL_DAMPING, local_damping, 0.7);
+  }
+  #[inline]
+  pub fn add_contact_restitution(&mut self, contact_restitution: f32) {
+    self.fbb_.push_slot::<f32>(State::VT_CONTACT_RESTITUTION, contact_restitution, 0.5);
+  }
+  #[inline]
+  pub fn add_clump_pointers(&mut self, clump_pointers: flatbuffers::WIPOffset<flatbuffers::Vector<'b , i32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_CLUMP_POINTERS, clump_pointers);
+  }
+  #[inline]
+  pub fn add_polygon_info(&mut self, polygon_info: flatbuffers::WIPOffset<flatbuffers::Vector<'b , i32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_POLYGON_INFO, polygon_info);
+  }
+  #[inline]
+  pub fn add_polygon_vertices(&mut self, polygon_vertices: flatbuffers::WIPOffset<flatbuffers::Vector<'b , f32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_POLYGON_VERTICES, polygon_vertices);
+  }
+  #[inline]
+  pub fn add_temperature(&mut self, temperature: flatbuffers::WIPOffset<flatbuffers::Vector<'b , f32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_TEMPERATURE, temperature);
+  }
+  #[inline]
+  pub fn add_materials(&mut self, materials: flatbuffers::WIPOffset<flatbuffers::Vector<'b , f32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_MATERIALS, materials);
+  }
+  #[inline]
+  pub fn add_material_pairs(&mut self, material_pairs: flatbuffers::WIPOffset<flatbuffers::Vector<'b , f32>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_MATERIAL_PAIRS, material_pairs);
+  }
+  #[inline]
+  pub fn add_material_names(&mut self, material_names: flatbuffers::WIPOffset<flatbuffers::Vector<'b , flatbuffers::ForwardsUOffset<&'b  str>>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(State::VT_MATERIAL_NAMES, material_names);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> StateBuilder<'a, 'b> {
     let start = _fbb.start_table();
     StateBuilder {
@@ -290,6 +466,17 @@ impl core::fmt::Debug for State<'_> {
       ds.field("bonds", &self.bonds());
       ds.field("bond_info", &self.bond_info());
       ds.field("material_pointers", &self.material_pointers());
+      ds.field("walls", &self.walls());
+      ds.field("damping_model", &self.damping_model());
+      ds.field("local_damping", &self.local_damping());
+      ds.field("contact_restitution", &self.contact_restitution());
+      ds.field("clump_pointers", &self.clump_pointers());
+      ds.field("polygon_info", &self.polygon_info());
+      ds.field("polygon_vertices", &self.polygon_vertices());
+      ds.field("temperature", &self.temperature());
+      ds.field("materials", &self.materials());
+      ds.field("material_pairs", &self.material_pairs());
+      ds.field("material_names", &self.material_names());
       ds.finish()
   }
 }
@@ -0,0 +1,284 @@
+// Barnes-Hut quadtree for self gravity between particles.
+// Built on the CPU every step by the CPU stepper, and from the read back positions for the GPU every hold_steps (see WGPUComputeProg::update_self_gravity).
+// Far away nodes act as a point mass at their center of mass once size/distance drops below theta, Plummer softening keeps close pairs finite.
+
+// below this the cells are smaller than any sensible particle, whatever is left gets lumped together
+const MAX_DEPTH: usize = 48;
+// the usual eta*sqrt(length/acceleration) limit from N-body codes, a particle falls about eta^2/2 of the length on a held field
+const HOLD_ETA: f32 = 0.2;
+const EMPTY: i32 = -1;
+const INTERNAL: i32 = -2;
+
+struct Node {
+    center: (f32, f32),
+    half: f32,
+    mass: f32,
+    // mass weighted position sum, divided out into the center of mass once the tree is built
+    com: (f32, f32),
+    children: [usize; 4],
+    // the particle in a leaf, or EMPTY/INTERNAL
+    body: i32,
+}
+
+impl Node {
+    fn new(center: (f32, f32), half: f32) -> Self {
+        return Node { center, half, mass: 0.0, com: (0.0, 0.0), children: [0; 4], body: EMPTY };
+    }
+}
+
+pub struct QuadTree {
+    nodes: Vec<Node>,
+}
+
+impl QuadTree {
+    // Particles with a NaN position or no mass are left out.
+    pub fn new(pos: &[f32], mass: &[f32]) -> Self {
+        let mut tree = QuadTree { nodes: vec![] };
+        let (mut min, mut max) = ((f32::INFINITY, f32::INFINITY), (f32::NEG_INFINITY, f32::NEG_INFINITY));
+        for i in 0..mass.len() {
+            if QuadTree::skip(pos, mass, i) {
+                continue;
+            }
+            min = (min.0.min(pos[i*2]), min.1.min(pos[i*2+1]));
+            max = (max.0.max(pos[i*2]), max.1.max(pos[i*2+1]));
+        }
+        if min.0 > max.0 {
+            return tree;
+        }
+        // square root cell, a touch bigger so nothing sits on the far edge
+        let half = 0.5*f32::max(max.0 - min.0, max.1 - min.1)*1.001 + f32::EPSILON;
+        tree.nodes.push(Node::new((0.5*(min.0 + max.0), 0.5*(min.1 + max.1)), half));
+        for i in 0..mass.len() {
+            if !QuadTree::skip(pos, mass, i) {
+                tree.insert(i, (pos[i*2], pos[i*2+1]), mass[i]);
+            }
+        }
+        for node in tree.nodes.iter_mut() {
+            if node.mass > 0.0 {
+                node.com = (node.com.0/node.mass, node.com.1/node.mass);
+            }
+        }
+        return tree;
+    }
+
+    fn skip(pos: &[f32], mass: &[f32], i: usize) -> bool {
+        return !(mass[i] > 0.0) || !pos[i*2].is_finite() || !pos[i*2+1].is_finite();
+    }
+
+    fn quadrant(node: &Node, p: (f32, f32)) -> usize {
+        return (p.0 >= node.center.0) as usize + 2*((p.1 >= node.center.1) as usize);
+    }
+
+    // index of the child in quadrant q, made if it isn't there yet
+    fn child(&mut self, n: usize, q: usize) -> usize {
+        if self.nodes[n].children[q] == 0 {
+            let half = 0.5*self.nodes[n].half;
+            let center = self.nodes[n].center;
+            let offset = (if q & 1 == 1 { half } else { -half }, if q & 2 == 2 { half } else { -half });
+            self.nodes.push(Node::new((center.0 + offset.0, center.1 + offset.1), half));
+            let index = self.nodes.len() - 1;
+            self.nodes[n].children[q] = index;
+        }
+        return self.nodes[n].children[q];
+    }
+
+    fn insert(&mut self, id: usize, p: (f32, f32), m: f32) {
+        let mut n = 0;
+        let mut depth = 0;
+        loop {
+            let node = &mut self.nodes[n];
+            if node.body == EMPTY && node.mass == 0.0 {
+                node.body = id as i32;
+                node.mass = m;
+                node.com = (p.0*m, p.1*m);
+                return;
+            }
+            if depth == MAX_DEPTH {
+                node.body = INTERNAL;
+                node.mass += m;
+                node.com = (node.com.0 + p.0*m, node.com.1 + p.1*m);
+                return;
+            }
+            // push the resident particle down a level before this one goes past
+            if node.body >= 0 {
+                let other = node.body as usize;
+                let other_mass = node.mass;
+                let other_pos = (node.com.0/other_mass, node.com.1/other_mass);
+                node.body = INTERNAL;
+                let q = QuadTree::quadrant(&self.nodes[n], other_pos);
+                let c = self.child(n, q);
+                let child = &mut self.nodes[c];
+                child.body = other as i32;
+                child.mass = other_mass;
+                child.com = (other_pos.0*other_mass, other_pos.1*other_mass);
+            }
+            let node = &mut self.nodes[n];
+            node.mass += m;
+            node.com = (node.com.0 + p.0*m, node.com.1 + p.1*m);
+            let q = QuadTree::quadrant(&self.nodes[n], p);
+            n = self.child(n, q);
+            depth += 1;
+        }
+    }
+
+    // Acceleration and potential per unit G at p, leaving out particle id.
+    pub fn field(&self, id: usize, p: (f32, f32), theta: f32, softening: f32) -> (f32, f32, f32) {
+        let mut field = (0.0, 0.0, 0.0);
+        if self.nodes.is_empty() {
+            return field;
+        }
+        let eps2 = softening*softening;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.mass == 0.0 || node.body == id as i32 {
+                continue;
+            }
+            let d = (node.com.0 - p.0, node.com.1 - p.1);
+            let r2 = d.0*d.0 + d.1*d.1;
+            let size = 2.0*node.half;
+            let leaf = node.children.iter().all(|&c| c == 0);
+            if leaf || size*size < theta*theta*r2 {
+                let inv_r = 1.0/(r2 + eps2).sqrt();
+                let inv_r3 = inv_r*inv_r*inv_r;
+                field.0 += node.mass*d.0*inv_r3;
+                field.1 += node.mass*d.1*inv_r3;
+                field.2 -= node.mass*inv_r;
+            } else {
+                for &c in node.children.iter() {
+                    if c != 0 {
+                        stack.push(c);
+                    }
+                }
+            }
+        }
+        return field;
+    }
+}
+
+// Acceleration x, y and potential per unit mass for every particle, 3 floats each (the layout of Self_Gravity in 2D_Simulation.wgsl).
+pub fn self_gravity(pos: &[f32], mass: &[f32], g: f32, theta: f32, softening: f32) -> Vec<f32> {
+    let tree = QuadTree::new(pos, mass);
+    let mut gravity = vec![0.0; mass.len()*3];
+    for i in 0..mass.len() {
+        if QuadTree::skip(pos, mass, i) {
+            continue;
+        }
+        let field = tree.field(i, (pos[i*2], pos[i*2+1]), theta, softening);
+        gravity[i*3] = g*field.0;
+        gravity[i*3+1] = g*field.1;
+        gravity[i*3+2] = g*field.2;
+    }
+    return gravity;
+}
+
+// How many steps of dt a field from self_gravity can be held for before it's rebuilt.
+// The length is the softening, or the smallest particle if that's bigger since contacts keep them apart, at least 1 step.
+pub fn hold_steps(gravity: &[f32], radii: &[f32], softening: f32, dt: f32) -> u32 {
+    let mut max_accel: f32 = 0.0;
+    let mut length = f32::MAX;
+    for i in 0..radii.len() {
+        let accel = (gravity[i*3]*gravity[i*3] + gravity[i*3+1]*gravity[i*3+1]).sqrt();
+        if accel > 0.0 && accel.is_finite() {
+            max_accel = max_accel.max(accel);
+            length = length.min(radii[i]);
+        }
+    }
+    if max_accel == 0.0 {
+        return u32::MAX;
+    }
+    let steps = HOLD_ETA*(length.max(softening)/max_accel).sqrt()/dt;
+    return (steps as u32).max(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a lumpy cloud, the same every run
+    fn cloud(count: usize) -> (Vec<f32>, Vec<f32>) {
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            return (seed >> 8) as f32/(1 << 24) as f32;
+        };
+        let mut pos = vec![];
+        let mut mass = vec![];
+        for i in 0..count {
+            let spread = if i % 3 == 0 { 0.1 } else { 1.0 };
+            pos.extend_from_slice(&[spread*(next() - 0.5), spread*(next() - 0.5)]);
+            mass.push(0.5 + next());
+        }
+        return (pos, mass);
+    }
+
+    fn direct(pos: &[f32], mass: &[f32], g: f32, softening: f32) -> Vec<f32> {
+        let mut gravity = vec![0.0; mass.len()*3];
+        for i in 0..mass.len() {
+            for j in 0..mass.len() {
+                if i == j {
+                    continue;
+                }
+                let d = ((pos[j*2] - pos[i*2]) as f64, (pos[j*2+1] - pos[i*2+1]) as f64);
+                let inv_r = 1.0/(d.0*d.0 + d.1*d.1 + (softening*softening) as f64).sqrt();
+                let m = (g*mass[j]) as f64;
+                gravity[i*3] += (m*d.0*inv_r*inv_r*inv_r) as f32;
+                gravity[i*3+1] += (m*d.1*inv_r*inv_r*inv_r) as f32;
+                gravity[i*3+2] -= (m*inv_r) as f32;
+            }
+        }
+        return gravity;
+    }
+
+    // RMS of the acceleration error over the RMS acceleration, and the same for the potential
+    fn errors(tree: &[f32], exact: &[f32]) -> (f32, f32) {
+        let (mut acc_err, mut acc, mut pot_err, mut pot) = (0.0, 0.0, 0.0, 0.0);
+        for i in 0..exact.len()/3 {
+            acc_err += (tree[i*3] - exact[i*3]).powi(2) + (tree[i*3+1] - exact[i*3+1]).powi(2);
+            acc += exact[i*3].powi(2) + exact[i*3+1].powi(2);
+            pot_err += (tree[i*3+2] - exact[i*3+2]).powi(2);
+            pot += exact[i*3+2].powi(2);
+        }
+        return ((acc_err/acc).sqrt(), (pot_err/pot).sqrt());
+    }
+
+    #[test]
+    fn opening_everything_is_the_direct_sum() {
+        let (pos, mass) = cloud(300);
+        let exact = direct(&pos, &mass, 2.0, 0.01);
+        let (acc, pot) = errors(&self_gravity(&pos, &mass, 2.0, 0.0, 0.01), &exact);
+        assert!(acc < 1e-4 && pot < 1e-5, "acceleration off by {acc}, potential by {pot}");
+    }
+
+    // monopole only, so around a percent in the acceleration at 0.5
+    #[test]
+    fn typical_opening_angle_stays_close() {
+        let (pos, mass) = cloud(300);
+        let exact = direct(&pos, &mass, 2.0, 0.01);
+        let (acc, pot) = errors(&self_gravity(&pos, &mass, 2.0, 0.5, 0.01), &exact);
+        assert!(acc < 2e-2 && pot < 5e-3, "acceleration off by {acc}, potential by {pot}");
+    }
+
+    #[test]
+    fn left_out_particles_feel_nothing() {
+        let (mut pos, mut mass) = cloud(10);
+        pos[0] = f32::NAN;
+        mass[1] = 0.0;
+        let gravity = self_gravity(&pos, &mass, 1.0, 0.5, 0.01);
+        assert_eq!(&gravity[0..6], &[0.0; 6]);
+        assert!(gravity[6..].iter().all(|g| g.is_finite()));
+    }
+
+    #[test]
+    fn stronger_pull_holds_the_field_for_fewer_steps() {
+        let (pos, mass) = cloud(50);
+        let radii = vec![0.01; 50];
+        let weak = hold_steps(&self_gravity(&pos, &mass, 0.01, 0.5, 0.01), &radii, 0.01, 1e-6);
+        let strong = hold_steps(&self_gravity(&pos, &mass, 1.0, 0.5, 0.01), &radii, 0.01, 1e-6);
+        // a hundred times the pull is a tenth of the steps
+        assert!(strong > 1 && (strong as f32 - weak as f32/10.0).abs() <= 1.0, "weak {weak}, strong {strong}");
+        // anything that would fall further than the length in one step is rebuilt every step
+        assert_eq!(hold_steps(&self_gravity(&pos, &mass, 1e9, 0.5, 0.01), &radii, 0.01, 1e-4), 1);
+        assert_eq!(hold_steps(&vec![0.0; 150], &radii, 0.01, 1e-4), u32::MAX);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::settings::Data;
+use crate::settings::{Data, FluidFlow};
 use crate::wgpu_prog::WGPUComputeProg;
 use crate::wgpu_structs::DepthBuffer;
 use crate::wgpu_structs::Texture;
@@ -44,6 +44,7 @@ pub struct Client {
     HL: bool,
     prevGen: i32,
     generation: i32,
+    sim_time: f64,
     xOff: f32,
     yOff: f32,
     middle: bool,
@@ -90,6 +91,7 @@ impl Client {
         let HL = false;
         let prevGen = 0;
         let generation = 0;
+        let sim_time = 0.0;
         let xOff = 0.0;
         let yOff = 0.0;
         let middle = false;
@@ -139,6 +141,7 @@ impl Client {
             minimized,
             HL,
             generation,
+            sim_time,
             xOff,
             yOff,
             middle,
@@ -263,7 +266,7 @@ impl Client {
                             2.0*(self.canvas.size.width/self.canvas.size.height) as f32 * (self.cursor_delta.0) as f32/self.canvas.size.width as f32 / self.wgpu_config.prog_settings.scale,
                             -2.0 as f32 * (self.cursor_delta.1) as f32/self.canvas.size.height as f32 / self.wgpu_config.prog_settings.scale,
                             bytemuck::cast::<_, f32>(self.wgpu_config.prog_settings.genPerFrame),
-                            0.0 as f32,
+                            self.wgpu_config.prog_settings.timestep(),
                             0.0 as f32,
                             0.0 as f32
                             ]
@@ -343,7 +346,7 @@ impl Client {
                         ..
                     } => {
                             self.wgpu_prog.shader_prog.update_state(&mut self.wgpu_config);
-                            self.wgpu_prog.shader_prog.state.save(&mut self.wgpu_config);   
+                            self.wgpu_prog.shader_prog.state.save();   
                             // self.data_length_backup = self.wgpu_config.prog_settings.data.len();
                             return true;
                         },
@@ -618,7 +621,12 @@ impl Client {
         self.wgpu_prog.shader_prog = WGPUComputeProg::new(&mut self.wgpu_config, (self.canvas.size.width as u32, self.canvas.size.height as u32));
         self.toggle = false;
         self.generation = 0;
+        self.sim_time = 0.0;
         self.wgpu_config.prog_settings.data = Data::new();
+        self.wgpu_config.prog_settings.bond_breaks = 0;
+        self.wgpu_config.prog_settings.overflows = [0, 0];
+        self.wgpu_config.prog_settings.excitation.carried = [0.0; 2];
+        self.wgpu_config.prog_settings.excitation.wall_step = [0.0; 2];
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -629,11 +637,39 @@ impl Client {
 
         if self.toggle {
             if self.wgpu_config.prog_settings.changed_collision_settings {
-                self.wgpu_prog.shader_prog.buffers.collision_settings.updateUniform(&self.wgpu_config.device, bytemuck::cast_slice(&self.wgpu_config.prog_settings.collison_settings()));
+                self.wgpu_prog.shader_prog.update_collision_settings(&mut self.wgpu_config);
+            }
+            // particles come and go between frames, so the drag and self gravity below see this frame's set
+            if self.wgpu_config.prog_settings.has_emitters() {
+                let elapsed = self.wgpu_config.prog_settings.genPerFrame as f32*self.wgpu_config.prog_settings.timestep();
+                self.wgpu_prog.shader_prog.update_emitters(&mut self.wgpu_config, elapsed);
+            }
+            if self.wgpu_config.prog_settings.fluid.flow != FluidFlow::None {
+                self.wgpu_prog.shader_prog.update_fluid(&mut self.wgpu_config);
+            }
+            // the excitation follows the clock, its base motion is held for the generations in a frame
+            // and walls left carried off by one that's been switched off still have to come back
+            let excitation = &self.wgpu_config.prog_settings.excitation;
+            if excitation.target != 0 || excitation.carried != [0.0; 2] || excitation.wall_step != [0.0; 2] {
+                self.wgpu_config.prog_settings.sim_time = self.sim_time as f32;
+                let steps = self.wgpu_config.prog_settings.genPerFrame as usize;
+                self.wgpu_config.prog_settings.excitation.carry_walls(self.sim_time as f32, steps);
+                // same size every frame, so it's written in place rather than made again with a new bind group
+                self.wgpu_config.queue.write_buffer(&self.wgpu_prog.shader_prog.buffers.collision_settings.buffer, 0, bytemuck::cast_slice(&self.wgpu_config.prog_settings.collison_settings()));
             }
             // for i in 0..self.wgpu_config.prog_settings.genPerFrame {
-            self.wgpu_prog.shader_prog.compute(&mut self.wgpu_config);
+            // self gravity is held for as many generations as the pull allows, then rebuilt for the rest of the frame
+            let mut remaining = self.wgpu_config.prog_settings.genPerFrame;
+            while remaining > 0 {
+                let mut generations = remaining;
+                if self.wgpu_config.prog_settings.gravity && self.wgpu_config.prog_settings.self_gravity {
+                    generations = generations.min(self.wgpu_prog.shader_prog.update_self_gravity(&mut self.wgpu_config));
+                }
+                self.wgpu_prog.shader_prog.compute(&mut self.wgpu_config, generations);
+                remaining -= generations;
+            }
             self.generation += self.wgpu_config.prog_settings.genPerFrame;
+            self.sim_time += self.wgpu_config.prog_settings.genPerFrame as f64*self.wgpu_config.prog_settings.timestep() as f64;
             // }
         }
 
@@ -660,12 +696,35 @@ impl Client {
                 self.wgpu_prog.shader_prog.restore(&mut self.wgpu_config);
             }
 
+            //Export bond breakages
+            if self.wgpu_config.prog_settings.export_bond_events {
+                self.wgpu_config.prog_settings.export_bond_events = false;
+                self.wgpu_prog.shader_prog.update_state(&mut self.wgpu_config);
+                if let Err(e) = self.wgpu_prog.shader_prog.state.export_bond_events(self.wgpu_config.prog_settings.bond_event_file.clone()) {
+                    eprintln!("Couldn't export bond breakages: {:?}", e);
+                }
+            }
+
             //Bond Regen
             if self.wgpu_config.prog_settings.regen_bonds {
                 self.wgpu_config.prog_settings.regen_bonds = false;
                 self.wgpu_prog.shader_prog.update_state(&mut self.wgpu_config);
                 self.wgpu_prog.shader_prog.state.regen_bonds(&mut self.wgpu_config);
-                self.wgpu_prog.shader_prog.state.save(&mut self.wgpu_config);
+                self.wgpu_prog.shader_prog.state.save();
+                self.wgpu_prog.shader_prog.restore(&mut self.wgpu_config);
+            }
+
+            //Clumps
+            if self.wgpu_config.prog_settings.clump_selection || self.wgpu_config.prog_settings.release_clumps {
+                self.wgpu_prog.shader_prog.update_state(&mut self.wgpu_config);
+                if self.wgpu_config.prog_settings.clump_selection {
+                    self.wgpu_prog.shader_prog.state.clump_selected();
+                } else {
+                    self.wgpu_prog.shader_prog.state.release_selected();
+                }
+                self.wgpu_config.prog_settings.clump_selection = false;
+                self.wgpu_config.prog_settings.release_clumps = false;
+                self.wgpu_prog.shader_prog.state.save();
                 self.wgpu_prog.shader_prog.restore(&mut self.wgpu_config);
             }
 
@@ -674,6 +733,14 @@ impl Client {
                 self.wgpu_config.prog_settings.set_properties = false;
                 self.wgpu_prog.shader_prog.buffers.set_prop_input.updateUniform(&self.wgpu_config.device, bytemuck::cast_slice(&self.wgpu_config.prog_settings.properties()));
                 self.wgpu_prog.shader_prog.set_properties(&mut self.wgpu_config);
+                if self.wgpu_config.prog_settings.properties.set_radius {
+                    // bigger particles may need bigger grid cells
+                    self.wgpu_prog.shader_prog.update_state(&mut self.wgpu_config);
+                    self.wgpu_prog.shader_prog.update_collision_settings(&mut self.wgpu_config);
+                    // and polygons grow or shrink with their radius
+                    self.wgpu_prog.shader_prog.state.fit_polygons();
+                    self.wgpu_prog.shader_prog.update_polygons(&mut self.wgpu_config);
+                }
             }
 
             // Begin to draw the UI frame.
@@ -700,8 +767,22 @@ impl Client {
                 ]
             ));   
             
+            if !self.wgpu_config.prog_settings.removed_materials.is_empty() {
+                let removed = std::mem::take(&mut self.wgpu_config.prog_settings.removed_materials);
+                self.wgpu_prog.shader_prog.remove_materials(&mut self.wgpu_config, &removed);
+            }
             if self.wgpu_config.prog_settings.materials_changed {
-                self.wgpu_prog.shader_prog.buffers.material_buffer.updateUniform(&self.wgpu_config.device, bytemuck::cast_slice(&self.wgpu_config.prog_settings.materials));
+                self.wgpu_prog.shader_prog.buffers.material_buffer.updateBuffer(&self.wgpu_config.device, bytemuck::cast_slice(&self.wgpu_config.prog_settings.materials), 0);
+                self.wgpu_prog.shader_prog.buffers.material_buffer.updateBuffer(&self.wgpu_config.device, bytemuck::cast_slice(&self.wgpu_config.prog_settings.material_pairs), 1);
+                self.wgpu_config.prog_settings.materials_changed = false;
+                // density and stiffness both feed the critical timestep
+                self.wgpu_config.prog_settings.changed_collision_settings = true;
+            }
+            if self.wgpu_config.prog_settings.walls_changed {
+                self.wgpu_prog.shader_prog.buffers.wall_buffer.updateUniform(&self.wgpu_config.device, bytemuck::cast_slice(&self.wgpu_config.prog_settings.wall_buffer()));
+                self.wgpu_config.prog_settings.walls_changed = false;
+                // a fixed temperature wall conducts, which can shorten the critical timestep
+                self.wgpu_config.prog_settings.changed_collision_settings = true;
             }
 
             let full_output = self.platform.end_frame(Some(&self.canvas.window));
@@ -849,7 +930,21 @@ impl Client {
                 render_pass.set_bind_group(7, &self.wgpu_prog.shader_prog.buffers.selections.bind_group, &[]);
                 render_pass.set_vertex_buffer(0, self.wgpu_prog.vertex_buffer.slice(..));
                 render_pass.set_index_buffer(self.wgpu_prog.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                render_pass.draw_indexed(0..6 as u32, 0, 0..self.wgpu_config.prog_settings.particles as u32);
+                render_pass.draw_indexed(0..6 as u32, 0, 0..self.wgpu_config.prog_settings.particles as u32*self.wgpu_config.prog_settings.ghost_images());
+
+                if self.wgpu_config.prog_settings.wall_count() > 0 {
+                    render_pass.set_pipeline(&self.wgpu_prog.wall_render_pipeline);
+                    render_pass.set_bind_group(0, &self.wgpu_prog.dim_uniform.bind_group, &[]);
+                    render_pass.set_bind_group(1, &self.wgpu_prog.shader_prog.buffers.wall_buffer.bind_group, &[]);
+                    render_pass.draw_indexed(0..6 as u32, 0, 0..self.wgpu_config.prog_settings.wall_count() as u32);
+                }
+
+                if self.wgpu_config.prog_settings.fluid.flow != FluidFlow::None && self.wgpu_config.prog_settings.fluid.overlay && self.wgpu_prog.shader_prog.fluid_cells > 0 {
+                    render_pass.set_pipeline(&self.wgpu_prog.fluid_render_pipeline);
+                    render_pass.set_bind_group(0, &self.wgpu_prog.dim_uniform.bind_group, &[]);
+                    render_pass.set_bind_group(1, &self.wgpu_prog.shader_prog.buffers.fluid_buffer.bind_group, &[]);
+                    render_pass.draw_indexed(0..6 as u32, 0, 0..self.wgpu_prog.shader_prog.fluid_cells as u32);
+                }
                 
             }
 
@@ -885,14 +980,15 @@ impl Client {
     }
 
     let now = Local::now();
-    let sim_time_passed = 0.0000390625*self.generation as f32;    
+    let sim_time_passed = self.sim_time as f32;    
 
     if self.toggle {
         self.wgpu_prog.shader_prog.update_state(&mut self.wgpu_config);
         match self.wgpu_prog.shader_prog.state.get_datum(&self.wgpu_config.prog_settings.plotted_prop) {
             Some(datum) => {self.wgpu_config.prog_settings.data.push(sim_time_passed as f64, datum);},
-            None => {self.wgpu_config.prog_settings.data = Data::new();}
+            None => {self.wgpu_config.prog_settings.data.clear_selection();}
         }
+        self.wgpu_config.prog_settings.data.push_energy(sim_time_passed as f64, &self.wgpu_prog.shader_prog.state.energy);
 }
 
     if(self.log_framerate){
@@ -909,7 +1005,7 @@ impl Client {
         let mut time_passed = (Local::now().timestamp_millis() - self.start_time.timestamp_millis()) as f32/1000.0;
         if !self.toggle { time_passed = 0.0; }
         let genPerSec = (self.generation - self.prevGen) as f32/time_since;
-                let sim_speed = 100.0*genPerSec*0.0000390625;
+                let sim_speed = 100.0*genPerSec*self.wgpu_config.prog_settings.timestep();
                 let twsp = 100.0*20.0/sim_speed;
                 println!("Generations/s: {}, Total Generations: {}", genPerSec, self.generation);
                 println!("Elapsed Time: {} seconds", time_passed);
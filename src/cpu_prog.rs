@@ -0,0 +1,1581 @@
+use std::f32::consts::PI;
+
+use crate::barnes_hut;
+use crate::emitter::Emitters;
+use crate::grid::{self, Grid};
+use crate::settings::{Settings, FluidFlow};
+use crate::state::*;
+
+// CPU port of 2D_LOM.wgsl, Grid.wgsl and 2D_Simulation.wgsl.
+// Works on the same State vectors as WGPUComputeProg so a run can be stepped (and saved) on a machine without a GPU.
+// Keep this in sync with the shaders, every function below has a twin in one of them.
+
+const TENSION: i32 = 1;
+const SHEAR: i32 = 2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Contact {
+    pub a: i32,
+    pub b: i32,
+    pub tangent_force: f32,
+    pub bonded: i32,
+    pub rolling_moment: f32,
+    pub touched: i32,
+}
+
+// shape_contact() in 2D_Simulation.wgsl, normal points from b to a and point is the middle of the overlap relative to a
+pub struct ShapeContact {
+    pub normal: (f32, f32),
+    pub point: (f32, f32),
+    pub overlap: f32,
+}
+
+fn cross(a: (f32, f32), b: (f32, f32)) -> f32 {
+    return a.0*b.1 - a.1*b.0;
+}
+
+fn dot(a: (f32, f32), b: (f32, f32)) -> f32 {
+    return a.0*b.0 + a.1*b.1;
+}
+
+impl Contact {
+    pub fn empty() -> Self {
+        return Contact { a: -1, b: -1, tangent_force: 0.0, bonded: -1, rolling_moment: 0.0, touched: -1 };
+    }
+}
+
+pub struct CPUComputeProg {
+    pub state: State,
+    pub contacts: Vec<Contact>,
+    pub vel_buf: Vec<f32>,
+    pub rot_vel_buf: Vec<f32>,
+    pub temperature_buf: Vec<f32>,
+    pub grid: Grid,
+    pub generation: i32,
+    stress: (f32, f32, f32),
+    particle_energy: [f32; ENERGY_SIZE],
+    energy_sums: [f32; ENERGY_SIZE],
+    // heat flowing into the particle this step, and how deep the last wall_contact pressed in
+    heat: f32,
+    wall_overlap: f32,
+    // the uniform's base acceleration, velocity and displacement, see Excitation::base_motion
+    base: [f32; 6],
+    // how far the walls have been carried and this step's share, see Excitation::carry_walls
+    carried: [f32; 2],
+    wall_step: [f32; 2],
+    // acceleration x, y and potential per particle, see barnes_hut::self_gravity
+    self_gravity: Vec<f32>,
+    // fluid velocity x, y and drag rate per particle, see fluid::drag
+    fluid_drag: Vec<f32>,
+    // start and count per clump, then id, x, y and rot (as f32 bits) per member, see State::clump_buffers
+    clumps: Vec<i32>,
+    clump_members: Vec<i32>,
+    emitters: Emitters,
+    // Settings::max_contacts and max_bonds when this was made, the shader has them built in the same way
+    max_contacts: usize,
+    max_bonds: usize,
+    // particles that hit the contact and bond caps this step, and the most in any step since overflows was last cleared
+    overflow: [usize; 2],
+    pub overflows: [usize; 2],
+}
+
+// outward normal of the edge v to w of a counterclockwise polygon
+fn outward(v: (f32, f32), w: (f32, f32)) -> (f32, f32) {
+    let edge = (w.0 - v.0, w.1 - v.1);
+    let length = dot(edge, edge).sqrt();
+    return (edge.1/length, -edge.0/length);
+}
+
+// jkr_radius() in 2D_Simulation.wgsl, the JKR contact radius at this overlap, c = sqrt(2*pi*adhesion/E)
+pub fn jkr_radius(overlap: f32, eff_radius: f32, c: f32) -> f32 {
+    let neck = (eff_radius*eff_radius*c*c/16.0).cbrt();
+    let mut contact_radius = (4.5*eff_radius*eff_radius*c*c).cbrt() + (eff_radius*overlap.max(0.0)).sqrt();
+    for _ in 0..16 {
+        let slope = 2.0*contact_radius/eff_radius - 0.5*c/contact_radius.sqrt();
+        if slope <= 0.0 {
+            break;
+        }
+        let miss = contact_radius*contact_radius/eff_radius - c*contact_radius.sqrt() - overlap;
+        contact_radius = f32::max(contact_radius - miss/slope, neck);
+    }
+    return contact_radius;
+}
+
+// jkr_work() in 2D_Simulation.wgsl
+fn jkr_work(contact_radius: f32, eff_radius: f32, c: f32, eff_youngs: f32) -> f32 {
+    let a = contact_radius;
+    return eff_youngs*(8.0*a.powi(5)/(15.0*eff_radius*eff_radius) - 4.0*c*a.powf(3.5)/(3.0*eff_radius) + 0.5*c*c*a*a);
+}
+
+// WGSL's sign(), f32::signum gives 1 for 0
+fn sign(x: f32) -> f32 {
+    if x == 0.0 {
+        return 0.0;
+    }
+    return x.signum();
+}
+
+impl CPUComputeProg {
+    pub fn new(settings: &mut Settings) -> Self {
+        let state = State::from_settings(settings);
+        return CPUComputeProg::from_state(state, settings);
+    }
+
+    pub fn from_state(mut state: State, settings: &mut Settings) -> Self {
+        settings.set_particles(state.p_count);
+        state.selections.resize(state.p_count, 0);
+        state.data.resize(state.p_count*4, 0.0);
+        state.temperature.resize(state.p_count, settings.initial_temperature);
+        let contacts = vec![Contact::empty(); settings.max_contacts*state.p_count];
+        let vel_buf = state.vel.clone();
+        let rot_vel_buf = state.rot_vel.clone();
+        let temperature_buf = state.temperature.clone();
+        settings.walls = state.walls.clone();
+        settings.set_damping(state.damping_model, state.local_damping, state.contact_restitution);
+        settings.set_materials(&state.materials, &state.material_pairs, &state.material_names, state.material_pointers.iter().copied().max().unwrap_or(-1));
+        state.copy_materials(settings);
+        settings.set_coll_grid(state.largest_radius());
+        settings.critical_delta_time = state.critical_delta_time(settings);
+        let grid = Grid::around(&state.pos, settings.coll_cell_size);
+        let (clumps, clump_members) = state.clump_buffers(settings);
+
+        return CPUComputeProg {
+            state,
+            contacts,
+            vel_buf,
+            rot_vel_buf,
+            temperature_buf,
+            grid,
+            generation: 0,
+            stress: (0.0, 0.0, 0.0),
+            particle_energy: [0.0; ENERGY_SIZE],
+            energy_sums: [0.0; ENERGY_SIZE],
+            heat: 0.0,
+            wall_overlap: 0.0,
+            base: [0.0; 6],
+            carried: [0.0; 2],
+            wall_step: [0.0; 2],
+            self_gravity: vec![],
+            fluid_drag: vec![],
+            clumps,
+            clump_members,
+            emitters: Emitters::new(),
+            max_contacts: settings.max_contacts,
+            max_bonds: settings.max_bonds,
+            overflow: [0, 0],
+            overflows: [0, 0],
+        };
+    }
+
+    pub fn compute(&mut self, settings: &Settings) {
+        for _ in 0..settings.genPerFrame {
+            self.step(settings);
+        }
+    }
+
+    pub fn step(&mut self, settings: &Settings) {
+        self.base = settings.excitation.base_motion(self.generation as f32*settings.timestep());
+        for k in 0..2 {
+            self.wall_step[k] = self.base[4 + k] - self.carried[k];
+            self.carried[k] = self.base[4 + k];
+        }
+
+        // EMITTERS/SINKS
+        if settings.has_emitters() {
+            if let Some(keep) = self.emitters.pending(settings, &self.state, settings.timestep()) {
+                let map = self.emitters.apply(settings, &mut self.state, &keep);
+                self.remap(&map, settings);
+            }
+        }
+
+        // LAWS OF MOTION
+        for id in 0..self.state.p_count {
+            self.laws_of_motion(id, settings.timestep());
+            self.wrap(id, settings);
+        }
+
+        // RIGID CLUMPS
+        for c in 0..self.state.clump_count() {
+            self.clump(c, settings);
+        }
+
+        // SELF GRAVITY
+        if settings.gravity && settings.self_gravity {
+            let masses = self.state.masses(settings);
+            self.self_gravity = barnes_hut::self_gravity(&self.state.pos, &masses, settings.gravitational_constant, settings.barnes_hut_theta, settings.gravity_softening);
+        }
+
+        // FLUID DRAG
+        if settings.fluid.flow != FluidFlow::None {
+            self.fluid_drag = self.state.fluid_drag(settings);
+        }
+
+        // BROADPHASE GRID
+        // emitters can bring in particles bigger than the grid was sized for
+        let (mut cell_size, mut width, mut height) = (settings.coll_cell_size, settings.coll_grid_w, settings.coll_grid_h);
+        if settings.has_emitters() {
+            let largest = self.state.largest_radius();
+            if largest > settings.coll_radius {
+                (cell_size, width, height) = grid::dimensions(settings.hor_bound, settings.vert_bound, 2.0*largest + settings.max_cohesion_range(largest));
+            }
+        }
+        self.grid = Grid::new(&self.state.pos, cell_size, (-settings.hor_bound, -settings.vert_bound), width, height);
+
+        // SIMULATION/COLLISIONS/BONDS
+        self.energy_sums = [0.0; ENERGY_SIZE];
+        self.overflow = [0, 0];
+        for id in 0..self.state.p_count {
+            self.simulate(id, settings);
+        }
+
+        // PLATENS/WALL REACTIONS
+        self.platens(settings);
+
+        // LEES-EDWARDS OFFSET
+        if settings.boundary_y == 2 {
+            let width = 2.0*settings.hor_bound;
+            self.state.shear_offset += settings.shear_velocity*settings.timestep();
+            self.state.shear_offset -= (self.state.shear_offset/width).round()*width;
+        }
+
+        // ENERGY BUDGET
+        for i in 0..ENERGY_SIZE {
+            if i < 4 {
+                self.state.energy[i] = self.energy_sums[i];
+            } else {
+                self.state.energy[i] += self.energy_sums[i];
+            }
+        }
+        for k in 0..2 {
+            self.overflows[k] = self.overflows[k].max(self.overflow[k]);
+        }
+        self.generation += 1;
+    }
+
+    // Moves everything the stepper holds per particle after the emitters or sinks changed the count, map is from Emitters::apply.
+    // The new particles on the end start from their State values with no contacts.
+    fn remap(&mut self, map: &[i32], settings: &Settings) {
+        let count = self.state.p_count;
+        let max_contacts = self.max_contacts;
+        let mut contacts = vec![Contact::empty(); max_contacts*count];
+        for old in 0..map.len() {
+            if map[old] == -1 {
+                continue;
+            }
+            let new = map[old] as usize;
+            for k in 0..max_contacts {
+                let mut contact = self.contacts[old*max_contacts + k];
+                if contact.b == -1 || map[contact.b as usize] == -1 {
+                    continue;
+                }
+                contact.a = new as i32;
+                contact.b = map[contact.b as usize];
+                contacts[new*max_contacts + k] = contact;
+            }
+        }
+        self.contacts = contacts;
+        self.vel_buf = CPUComputeProg::remap_values(&self.vel_buf, map, 2, &self.state.vel);
+        self.rot_vel_buf = CPUComputeProg::remap_values(&self.rot_vel_buf, map, 1, &self.state.rot_vel);
+        self.temperature_buf = CPUComputeProg::remap_values(&self.temperature_buf, map, 1, &self.state.temperature);
+        (self.clumps, self.clump_members) = self.state.clump_buffers(settings);
+    }
+
+    // fresh with each old particle's values moved to where map puts it
+    fn remap_values(values: &[f32], map: &[i32], stride: usize, fresh: &[f32]) -> Vec<f32> {
+        let mut remapped = fresh.to_vec();
+        for old in 0..map.len() {
+            if map[old] != -1 {
+                let new = map[old] as usize;
+                remapped[new*stride..(new + 1)*stride].copy_from_slice(&values[old*stride..(old + 1)*stride]);
+            }
+        }
+        return remapped;
+    }
+
+    // 2D_LOM.wgsl
+    fn laws_of_motion(&mut self, id: usize, delta_time: f32) {
+        let state = &mut self.state;
+
+        if state.fixity[id*3] == 0 {
+            state.acc[id*3] = self.vel_buf[id*2] - state.vel[id*2];
+            state.vel[id*2] = self.vel_buf[id*2];
+        } else {
+            state.acc[id*3] = 0.0;
+        }
+
+        if state.fixity[id*3+1] == 0 {
+            state.acc[id*3+1] = self.vel_buf[id*2+1] - state.vel[id*2+1];
+            state.vel[id*2+1] = self.vel_buf[id*2+1];
+        } else {
+            state.acc[id*3+1] = 0.0;
+        }
+
+        state.vel[id*2] += state.forces[id*6]*delta_time;
+        state.vel[id*2+1] += state.forces[id*6+1]*delta_time;
+
+        state.pos[id*2] += state.vel[id*2]*delta_time;
+        state.pos[id*2+1] += state.vel[id*2+1]*delta_time;
+
+        if state.fixity[id*3+2] == 0 {
+            state.acc[id*3+2] = self.rot_vel_buf[id] - state.rot_vel[id]/delta_time;
+            state.rot_vel[id] = self.rot_vel_buf[id];
+        }
+
+        state.rot_vel[id] += state.forces[id*6+2]*delta_time;
+        self.rot_vel_buf[id] = state.rot_vel[id];
+        state.rot[id] = (state.rot[id] + state.rot_vel[id]*delta_time)%(2.0*PI);
+
+        state.temperature[id] = self.temperature_buf[id];
+
+        state.forces[id*6] += state.forces[id*6+3]*delta_time;
+        state.forces[id*6+1] += state.forces[id*6+4]*delta_time;
+        state.forces[id*6+2] += state.forces[id*6+5]*delta_time;
+    }
+
+    // wrap() in 2D_LOM.wgsl
+    fn wrap(&mut self, id: usize, settings: &Settings) {
+        let state = &mut self.state;
+        if settings.boundary_y != 0 {
+            let height = 2.0*settings.vert_bound;
+            let shift = ((state.pos[id*2+1] + settings.vert_bound)/height).floor();
+            if shift != 0.0 {
+                state.pos[id*2+1] -= shift*height;
+                if settings.boundary_y == 2 {
+                    state.pos[id*2] -= shift*state.shear_offset;
+                    state.vel[id*2] -= shift*settings.shear_velocity;
+                }
+            }
+        }
+        if settings.boundary_x != 0 {
+            let width = 2.0*settings.hor_bound;
+            state.pos[id*2] -= ((state.pos[id*2] + settings.hor_bound)/width).floor()*width;
+        }
+    }
+
+    // clump() in 2D_Simulation.wgsl
+    fn clump(&mut self, c: usize, settings: &Settings) {
+        let (start, count) = (self.clumps[c*2] as usize, self.clumps[c*2+1] as usize);
+        if count < 2 {
+            return;
+        }
+        let delta_time = settings.timestep();
+        let members = self.clump_members[start*4..(start+count)*4].to_vec();
+        let member = |k: usize| -> (usize, f32, f32, f32) {
+            let m = &members[(k-start)*4..(k-start)*4+4];
+            return (m[0] as usize, f32::from_bits(m[1] as u32), f32::from_bits(m[2] as u32), f32::from_bits(m[3] as u32));
+        };
+        let first = member(start).0;
+        let origin = (self.state.pos[first*2], self.state.pos[first*2+1]);
+        let mut mass = 0.0;
+        let mut center = (0.0, 0.0);
+        let mut momentum = (0.0, 0.0);
+        for k in start..start+count {
+            let id = member(k).0;
+            let m = self.particle_mass(id, settings);
+            let delta = self.separation(first, id, settings);
+            let vel = self.image_velocity(first, id, settings);
+            mass += m;
+            center = (center.0 + m*delta.0, center.1 + m*delta.1);
+            momentum = (momentum.0 + m*vel.0, momentum.1 + m*vel.1);
+        }
+        center = (center.0/mass, center.1/mass);
+        let velocity = (momentum.0/mass, momentum.1/mass);
+        let mut angular_momentum = 0.0;
+        let mut inertia = 0.0;
+        for k in start..start+count {
+            let (id, x, y, _) = member(k);
+            let m = self.particle_mass(id, settings);
+            let delta = self.separation(first, id, settings);
+            let arm = (delta.0 - center.0, delta.1 - center.1);
+            let vel = self.image_velocity(first, id, settings);
+            angular_momentum += self.rot_inertia(id, settings)*self.state.rot_vel[id] + m*(arm.0*(vel.1 - velocity.1) - arm.1*(vel.0 - velocity.0));
+            inertia += self.rot_inertia(id, settings) + m*(x*x + y*y);
+        }
+        let spin = angular_momentum/inertia;
+        let angle = self.state.rot[first] - self.state.rot_vel[first]*delta_time - member(start).3 + spin*delta_time;
+        for k in start..start+count {
+            let (id, x, y, rot) = member(k);
+            let arm = (x*angle.cos() - y*angle.sin(), x*angle.sin() + y*angle.cos());
+            self.state.pos[id*2] = origin.0 + center.0 + arm.0;
+            self.state.pos[id*2+1] = origin.1 + center.1 + arm.1;
+            self.state.vel[id*2] = velocity.0 - spin*arm.1;
+            self.state.vel[id*2+1] = velocity.1 + spin*arm.0;
+            self.state.rot[id] = (angle + rot)%(2.0*PI);
+            self.state.rot_vel[id] = spin;
+            self.rot_vel_buf[id] = spin;
+            self.wrap(id, settings);
+            self.vel_buf[id*2] = self.state.vel[id*2];
+            self.vel_buf[id*2+1] = self.state.vel[id*2+1];
+        }
+    }
+
+    // 2D_Simulation.wgsl
+    fn simulate(&mut self, id: usize, settings: &Settings) {
+        let mat_id = self.state.material_pointers[id];
+        let delta_time = settings.timestep();
+
+        let mut net_force = (0.0, 0.0);
+        let mut net_moment = 0.0;
+        self.stress = (0.0, 0.0, 0.0);
+        self.particle_energy = [0.0; ENERGY_SIZE];
+        self.heat = 0.0;
+
+        //Bonds
+        let mut bonded_particles = vec![-1; self.max_bonds];
+        if settings.bonds != 0 {
+            let start = self.state.bond_info[id*2];
+            let length = self.state.bond_info[id*2+1];
+            if start != -1 {
+                if length as usize > self.max_bonds {
+                    self.overflow[1] += 1;
+                }
+                for i in start as usize..(start+length) as usize {
+                    let bond_id = self.state.bonds[i*BOND_SIZE];
+                    if bond_id < 0 || self.state.same_clump(id, bond_id as usize) {
+                        continue;
+                    }
+                    if (settings.bonds == 2 || settings.bonds == 3) && i-(start as usize) < self.max_bonds {
+                        bonded_particles[i-start as usize] = bond_id;
+                    }
+                    // an intact bond conducts through its cement
+                    self.conduct(id, bond_id as usize, settings.bond_radius_multiplier*f32::min(self.state.radii[id], self.state.radii[bond_id as usize]), settings);
+                    if settings.bonds == 3 {
+                        let bond_forces = self.parallel_bond(i, id, bond_id as usize, settings);
+                        net_force.0 += bond_forces.0;
+                        net_force.1 += bond_forces.1;
+                        net_moment += bond_forces.2;
+                        let dir = self.normal(bond_id as usize, id, settings);
+                        self.add_stress((dir.0*self.state.radii[id], dir.1*self.state.radii[id]), (bond_forces.0, bond_forces.1));
+                        continue;
+                    }
+                    let displacement = -self.distance(id, bond_id as usize, settings);
+                    let dir = self.normal(bond_id as usize, id, settings);
+                    let spring_force = settings.stiffness * displacement;
+                    net_force.0 -= spring_force*dir.0*settings.force_scale;
+                    net_force.1 -= spring_force*dir.1*settings.force_scale;
+                    self.add_stress((dir.0*self.state.radii[id], dir.1*self.state.radii[id]), (-spring_force*dir.0*settings.force_scale, -spring_force*dir.1*settings.force_scale));
+                    self.particle_energy[2] += 0.25*settings.force_scale*settings.stiffness*displacement*displacement;
+                    if settings.bond_tearing && displacement < -settings.bond_force_limit {
+                        self.break_bond(i, id, bond_id as usize, TENSION, settings);
+                    }
+                }
+            }
+        }
+
+        // Grid Collisions
+        if settings.collisions {
+            let max_contacts = self.max_contacts;
+            let mut collisions = vec![-1; max_contacts];
+            let mut count = 0;
+            let mut overflowed = false;
+            // make a list of particles that we're colliding with, around the particle and any of its images across a periodic seam
+            for image in 0..4 {
+                if overflowed {
+                    break;
+                }
+                let point = match self.image_point(id, image, settings) {
+                    Some(point) => point,
+                    None => continue,
+                };
+                for i in self.grid.neighbors(point.0, point.1) {
+                    if i != id && !self.state.same_clump(i, id) && self.touching(i, id, self.cohesion_range(i, id, settings), settings) && !collisions[0..count].contains(&(i as i32)) {
+                        // one more than there's room for
+                        if count == max_contacts {
+                            overflowed = true;
+                            break;
+                        }
+                        collisions[count] = i as i32;
+                        count += 1;
+                    }
+                }
+            }
+
+            // delete contacts that don't exist
+            for j in id*max_contacts..(id+1)*max_contacts {
+                if self.contacts[j].b == -1 {
+                    continue;
+                }
+                let found_contact = collisions[0..count].contains(&self.contacts[j].b);
+                if !found_contact && self.contacts[j].bonded == -1 {
+                    self.contacts[j].a = -1;
+                    self.contacts[j].b = -1;
+                }
+            }
+
+            // create new contacts
+            for i in 0..count {
+                let mut existing_index = -1;
+                let mut empty_index = -1;
+                for j in id*max_contacts..(id+1)*max_contacts {
+                    if self.contacts[j].b == collisions[i] {
+                        existing_index = j as i32;
+                        break;
+                    } else if self.contacts[j].b == -1 {
+                        empty_index = j as i32;
+                    }
+                }
+
+                if existing_index == -1 && empty_index == -1 {
+                    // every slot is held, bonded contacts outlast the touch that made them
+                    overflowed = true;
+                } else if existing_index == -1 { // initialize completely new contact
+                    let b = collisions[i];
+                    let contact = &mut self.contacts[empty_index as usize];
+                    if bonded_particles.contains(&b) {
+                        contact.bonded = 1;
+                    }
+                    contact.a = id as i32;
+                    contact.b = b;
+                    contact.tangent_force = 0.0;
+                    contact.rolling_moment = 0.0;
+                    contact.touched = -1;
+                }
+            }
+            if overflowed {
+                self.overflow[0] += 1;
+            }
+
+            for i in id*max_contacts..(id+1)*max_contacts {
+                if self.contacts[i].b == -1 {
+                    continue;
+                }
+                let bonded = bonded_particles.contains(&self.contacts[i].b);
+                if !bonded {
+                    self.contacts[i].bonded = -1;
+                }
+                let a = self.contacts[i].a as usize;
+                let b = self.contacts[i].b as usize;
+                // contacts within the cohesion range can have a gap
+                let mut gap = self.distance(a, b, settings);
+                let mut overlap = f32::max(-gap, 0.0);
+                let mut normal = self.normal(a, b, settings);
+                // where the contact is from a's center, and how fast b's side of it moves past a's
+                let mut arm_a = (-normal.0*self.state.radii[a], -normal.1*self.state.radii[a]);
+                let vel_b = self.image_velocity(a, b, settings);
+                let mut rel_vel = (vel_b.0 - self.state.vel[a*2], vel_b.1 - self.state.vel[a*2+1]);
+                // polygons touch wherever their corners say, discs along the line between their centers
+                let polygons = self.state.is_polygon(a) || self.state.is_polygon(b);
+                if polygons {
+                    let shape = self.shape_contact(a, b, settings);
+                    gap = -shape.overlap;
+                    overlap = f32::max(shape.overlap, 0.0);
+                    normal = shape.normal;
+                    arm_a = shape.point;
+                    let offset = self.separation(a, b, settings);
+                    let arm_b = (shape.point.0 - offset.0, shape.point.1 - offset.1);
+                    let (spin_a, spin_b) = (self.state.rot_vel[a], self.state.rot_vel[b]);
+                    rel_vel = (rel_vel.0 - spin_b*arm_b.1 + spin_a*arm_a.1, rel_vel.1 + spin_b*arm_b.0 - spin_a*arm_a.0);
+                }
+
+                let mut normal_stiffness = 10.0; // MTIF
+                let mut shear_stiffness = 0.25; // MTIF
+                let mut friction_coefficient = settings.friction_coefficient; // MTIF
+                let mut restitution = settings.contact_restitution;
+                let mut cohesion_scale = 1.0;
+                // the pair table and Hertz-Mindlin need both materials, a particle without one keeps the defaults above
+                let paired = mat_id != -1 && self.state.material_pointers[b] != -1;
+                if paired {
+                    let pair = settings.pair(self.state.material_pointers[a] as usize, self.state.material_pointers[b] as usize);
+                    normal_stiffness = pair[0];
+                    shear_stiffness = pair[1];
+                    friction_coefficient = pair[2];
+                    restitution = pair[3];
+                    cohesion_scale = pair[4];
+                }
+                let mut normal_force = overlap*normal_stiffness;
+                // Hertz-Mindlin is for spheres, polygons stay linear
+                if settings.contact_model == 1 && paired && !polygons {
+                    (normal_force, shear_stiffness) = self.hertz_mindlin(a, b, overlap, settings);
+                    if overlap > 0.0 {
+                        normal_stiffness = 1.5*normal_force/overlap;
+                    }
+                }
+                let tangent = (-normal.1, normal.0);
+
+                let del_pos_a = (self.state.vel[a*2]*delta_time, self.state.vel[a*2+1]*delta_time);
+                let del_pos_b = (vel_b.0*delta_time, vel_b.1*delta_time);
+                let del_rot_a = self.state.rot_vel[a]*delta_time*self.state.radii[a];
+                let del_rot_b = self.state.rot_vel[b]*delta_time*self.state.radii[b];
+
+                let rel_trans = (del_pos_b.0 - del_pos_a.0, del_pos_b.1 - del_pos_a.1);
+                let rel_rot = del_rot_b + del_rot_a;
+
+                let mut rel_tangent = rel_trans.0*tangent.0 + rel_trans.1*tangent.1 + rel_rot;
+                if polygons {
+                    rel_tangent = dot(rel_vel, tangent)*delta_time;
+                }
+
+                let mut friction_limit = normal_force.abs()*friction_coefficient;
+                if !settings.friction {
+                    friction_limit = 0.0;
+                }
+                let mut moment = true;
+                if bonded && settings.bonds == 2 {
+                    normal_force = 0.0;
+                    friction_limit = settings.bond_shear_limit;
+                    moment = false;
+                }
+                let trial_force = self.contacts[i].tangent_force + rel_tangent*shear_stiffness;
+                self.contacts[i].tangent_force = trial_force.max(-friction_limit).min(friction_limit);
+                let tangent_force = self.contacts[i].tangent_force;
+                // both particles see the contact, so each books half of its energy
+                if trial_force.abs() > friction_limit && shear_stiffness > 0.0 {
+                    self.particle_energy[4] += 0.5*settings.force_scale*friction_limit*(trial_force.abs() - friction_limit)/shear_stiffness;
+                }
+                let mut normal_energy = 0.5*normal_force*overlap;
+                if settings.contact_model == 1 && mat_id != -1 && !polygons {
+                    normal_energy = 0.4*normal_force*overlap;
+                }
+                self.particle_energy[2] += 0.5*settings.force_scale*normal_energy;
+                if shear_stiffness > 0.0 {
+                    self.particle_energy[2] += 0.25*settings.force_scale*tangent_force*tangent_force/shear_stiffness;
+                }
+                // viscous dashpot along the normal, tuned to the pair's restitution
+                let mut dashpot_force = 0.0;
+                if settings.damping_model & 2 != 0 && normal_force > 0.0 {
+                    let closing = rel_vel.0*normal.0 + rel_vel.1*normal.1;
+                    let mass_a = self.particle_mass(a, settings);
+                    let mass_b = self.particle_mass(b, settings);
+                    let dashpot = 2.0*settings.damping_ratio(restitution)*(settings.force_scale*normal_stiffness*mass_a*mass_b/(mass_a + mass_b)).sqrt();
+                    // it can cancel the spring but never pull the particles together
+                    dashpot_force = f32::max(dashpot*closing, -settings.force_scale*normal_force);
+                    self.particle_energy[5] += 0.5*dashpot_force*closing*delta_time;
+                }
+                // cohesion pulls the pair together, friction above only sees the elastic part of the normal force
+                let touching_now = gap <= 0.0 && self.contacts[i].touched != 1;
+                if gap <= 0.0 {
+                    self.contacts[i].touched = 1;
+                }
+                let (cohesive_force, cohesive_energy) = self.cohesion(a, b, gap, self.contacts[i].touched == 1, settings);
+                let (cohesive_force, cohesive_energy) = (cohesion_scale*cohesive_force, cohesion_scale*cohesive_energy);
+                // the neck or bridge snaps on with the energy it already has, that's booked as lost so the budget still adds up
+                if touching_now {
+                    self.particle_energy[5] -= 0.5*settings.force_scale*cohesive_energy;
+                }
+                // heat goes through the contact patch, bonded pairs already conduct through the bond
+                if !bonded && overlap > 0.0 {
+                    let (ra, rb) = (self.state.radii[a], self.state.radii[b]);
+                    self.conduct(a, b, (ra*rb/(ra + rb)*overlap).sqrt(), settings);
+                }
+                self.particle_energy[2] += 0.5*settings.force_scale*cohesive_energy;
+                let contact_force = (settings.force_scale * (normal.0*(normal_force - cohesive_force) + tangent.0*tangent_force) + normal.0*dashpot_force, settings.force_scale * (normal.1*(normal_force - cohesive_force) + tangent.1*tangent_force) + normal.1*dashpot_force);
+                net_force.0 += contact_force.0;
+                net_force.1 += contact_force.1;
+                self.add_stress(arm_a, contact_force);
+                if polygons {
+                    // off center the normal force turns it too, the full moment of the force keeps the strain energy honest
+                    net_moment += cross(arm_a, contact_force);
+                } else {
+                    net_moment -= self.state.radii[a]*tangent_force;
+                }
+                // a polygon's corners already resist rolling
+                if moment && settings.rolling_model != 0 && !polygons {
+                    net_moment += self.rolling_resistance(i, a, b, normal_force, normal_stiffness, settings);
+                }
+            }
+        }
+
+        let wall_forces = self.segment_walls(id, settings);
+        net_force.0 += wall_forces.0;
+        net_force.1 += wall_forces.1;
+        net_moment += wall_forces.2;
+
+        self.store_forces(id, net_force, net_moment, settings);
+        self.store_stress(id);
+        self.store_temperature(id, settings);
+
+        self.box_walls(id, settings);
+        self.store_energy(id, settings);
+    }
+
+    // store_energy() in 2D_Simulation.wgsl, except the sums are kept here instead of a separate reduction
+    fn store_energy(&mut self, id: usize, settings: &Settings) {
+        let pos = (self.state.pos[id*2], self.state.pos[id*2+1]);
+        let mass = self.particle_mass(id, settings);
+        let inertia = self.rot_inertia(id, settings);
+        let vel = (self.state.vel[id*2], self.state.vel[id*2+1]);
+        let rot_vel = self.state.rot_vel[id];
+        let forces = &self.state.forces[id*6..id*6+3];
+        self.particle_energy[0] = 0.5*mass*(vel.0*vel.0 + vel.1*vel.1);
+        self.particle_energy[1] = 0.5*inertia*rot_vel*rot_vel;
+        let gravity = (9.81*settings.gravity_x, 9.81*settings.gravity_y);
+        if settings.gravity && settings.self_gravity {
+            // every pair shows up at both ends
+            self.particle_energy[3] = 0.5*mass*self.self_gravity[id*3+2];
+        } else if settings.gravity && settings.planet_mode {
+            self.particle_energy[3] = mass*(gravity.0*gravity.0 + gravity.1*gravity.1).sqrt()*(pos.0*pos.0 + pos.1*pos.1).sqrt();
+        } else if settings.gravity {
+            let corner = (sign(gravity.0)*settings.hor_bound, sign(gravity.1)*settings.vert_bound);
+            self.particle_energy[3] = -mass*(gravity.0*(pos.0 - corner.0) + gravity.1*(pos.1 - corner.1));
+        }
+        self.particle_energy[6] += (mass*(forces[0]*vel.0 + forces[1]*vel.1) + inertia*forces[2]*rot_vel)*settings.timestep();
+        self.particle_energy[6] -= mass*(self.base[0]*vel.0 + self.base[1]*vel.1)*settings.timestep();
+        // NaN particles would poison the sums
+        if self.particle_energy.iter().any(|e| e.is_nan()) {
+            return;
+        }
+        for i in 0..ENERGY_SIZE {
+            self.energy_sums[i] += self.particle_energy[i];
+        }
+    }
+
+    fn add_stress(&mut self, branch: (f32, f32), force: (f32, f32)) {
+        self.stress.0 += branch.0*force.0;
+        self.stress.1 += branch.1*force.1;
+        self.stress.2 += 0.5*(branch.0*force.1 + branch.1*force.0);
+    }
+
+    fn store_stress(&mut self, id: usize) {
+        let mut area = PI*self.state.radii[id]*self.state.radii[id];
+        if self.state.is_polygon(id) {
+            area = self.state.polygon_area(id);
+        }
+        let tensor = (self.stress.0/area, self.stress.1/area, self.stress.2/area);
+        self.state.data[id*4] = tensor.0;
+        self.state.data[id*4+1] = tensor.1;
+        self.state.data[id*4+2] = tensor.2;
+        self.state.data[id*4+3] = 0.5*(tensor.0 + tensor.1);
+    }
+
+    // separation() in 2D_Simulation.wgsl, shortest vector from a to b counting the periodic images
+    fn separation(&self, a: usize, b: usize, settings: &Settings) -> (f32, f32) {
+        return self.state.separation(a, b, settings);
+    }
+
+    // image_point() in 2D_Simulation.wgsl
+    fn image_point(&self, id: usize, image: usize, settings: &Settings) -> Option<(f32, f32)> {
+        let mut pos = (self.state.pos[id*2], self.state.pos[id*2+1]);
+        if image & 2 != 0 {
+            if settings.boundary_y == 0 || pos.1.abs() + settings.coll_cell_size < settings.vert_bound {
+                return None;
+            }
+            let side = sign(pos.1);
+            pos.1 -= side*2.0*settings.vert_bound;
+            if settings.boundary_y == 2 {
+                pos.0 -= side*self.state.shear_offset;
+                pos.0 -= (pos.0/(2.0*settings.hor_bound)).round()*2.0*settings.hor_bound;
+            }
+        }
+        if image & 1 != 0 {
+            if settings.boundary_x == 0 || pos.0.abs() + settings.coll_cell_size < settings.hor_bound {
+                return None;
+            }
+            pos.0 -= sign(pos.0)*2.0*settings.hor_bound;
+        }
+        return Some(pos);
+    }
+
+    fn image_velocity(&self, a: usize, b: usize, settings: &Settings) -> (f32, f32) {
+        let vel = (self.state.vel[b*2], self.state.vel[b*2+1]);
+        if settings.boundary_y == 2 {
+            let shift = ((self.state.pos[b*2+1] - self.state.pos[a*2+1])/(2.0*settings.vert_bound)).round();
+            return (vel.0 - shift*settings.shear_velocity, vel.1);
+        }
+        return vel;
+    }
+
+    fn distance(&self, a: usize, b: usize, settings: &Settings) -> f32 {
+        let delta = self.separation(a, b, settings);
+        return (delta.0*delta.0 + delta.1*delta.1).sqrt() - (self.state.radii[a] + self.state.radii[b]);
+    }
+
+    // -normalize(separation(a, b))
+    fn normal(&self, a: usize, b: usize, settings: &Settings) -> (f32, f32) {
+        let delta = self.separation(a, b, settings);
+        let magnitude = (delta.0*delta.0 + delta.1*delta.1).sqrt();
+        return (-delta.0/magnitude, -delta.1/magnitude);
+    }
+
+    // The bond forces are stored as f32 bits in the i32 bonds vector, same as on the GPU
+    fn parallel_bond(&mut self, i: usize, a: usize, b: usize, settings: &Settings) -> (f32, f32, f32) {
+        let delta_time = settings.timestep();
+        let (rad_a, rad_b) = (self.state.radii[a], self.state.radii[b]);
+        let bond_radius = settings.bond_radius_multiplier*f32::min(rad_a, rad_b);
+        let area = 2.0*bond_radius;
+        let inertia = 2.0*bond_radius*bond_radius*bond_radius/3.0;
+
+        let normal = self.normal(b, a, settings);
+        let tangent = (-normal.1, normal.0);
+        let rot = self.state.rot_vel[a]*rad_a + self.state.rot_vel[b]*rad_b;
+        let vel_b = self.image_velocity(a, b, settings);
+        let rel_vel = (
+            vel_b.0 - self.state.vel[a*2] - rot*tangent.0,
+            vel_b.1 - self.state.vel[a*2+1] - rot*tangent.1,
+        );
+
+        let bond = &mut self.state.bonds[i*BOND_SIZE+3..(i+1)*BOND_SIZE];
+        let mut normal_force = f32::from_bits(bond[0] as u32);
+        let mut shear_force = f32::from_bits(bond[1] as u32);
+        let mut moment = f32::from_bits(bond[2] as u32);
+        normal_force += settings.force_scale*settings.bond_normal_stiffness*area*(rel_vel.0*normal.0 + rel_vel.1*normal.1)*delta_time;
+        shear_force += settings.force_scale*settings.bond_shear_stiffness*area*(rel_vel.0*tangent.0 + rel_vel.1*tangent.1)*delta_time;
+        moment += settings.force_scale*settings.bond_normal_stiffness*inertia*(self.state.rot_vel[b] - self.state.rot_vel[a])*delta_time;
+        bond[0] = normal_force.to_bits() as i32;
+        bond[1] = shear_force.to_bits() as i32;
+        bond[2] = moment.to_bits() as i32;
+
+        if settings.bond_tearing {
+            let tensile_stress = normal_force/area + moment.abs()*bond_radius/inertia;
+            let shear_stress = shear_force.abs()/area;
+            let shear_strength = settings.bond_cohesion - normal_force/area*settings.bond_friction_angle.to_radians().tan();
+            if tensile_stress >= settings.bond_tensile_strength {
+                self.break_bond(i, a, b, TENSION, settings);
+                return (0.0, 0.0, 0.0);
+            }
+            if shear_stress >= shear_strength {
+                self.break_bond(i, a, b, SHEAR, settings);
+                return (0.0, 0.0, 0.0);
+            }
+        }
+
+        // the stored forces already include damping, and each side books half
+        let normal_spring = settings.force_scale*settings.bond_normal_stiffness;
+        let shear_spring = settings.force_scale*settings.bond_shear_stiffness;
+        if normal_spring > 0.0 {
+            self.particle_energy[2] += 0.25*(normal_force*normal_force/(normal_spring*area) + moment*moment/(normal_spring*inertia));
+        }
+        if shear_spring > 0.0 {
+            self.particle_energy[2] += 0.25*shear_force*shear_force/(shear_spring*area);
+        }
+
+        return (normal.0*normal_force + tangent.0*shear_force, normal.1*normal_force + tangent.1*shear_force, rad_a*shear_force + moment);
+    }
+
+    // Same as the shader, only the lower particle of the pair logs it
+    fn break_bond(&mut self, i: usize, a: usize, b: usize, mode: i32, settings: &Settings) {
+        self.state.bonds[i*BOND_SIZE] = -self.state.bonds[i*BOND_SIZE];
+        if a > b {
+            return;
+        }
+        let normal = self.normal(b, a, settings);
+        let offset = self.state.radii[a] + 0.5*self.distance(a, b, settings);
+        self.state.bond_events.push(BondEvent {
+            step: self.generation as u32 + 1,
+            a: a as i32,
+            b: b as i32,
+            x: self.state.pos[a*2] + normal.0*offset,
+            y: self.state.pos[a*2+1] + normal.1*offset,
+            mode,
+        });
+        self.state.bond_breaks += 1;
+    }
+
+    fn rolling_resistance(&mut self, i: usize, a: usize, b: usize, normal_force: f32, normal_stiffness: f32, settings: &Settings) -> f32 {
+        let (rad_a, rad_b) = (self.state.radii[a], self.state.radii[b]);
+        let eff_radius = rad_a*rad_b/(rad_a + rad_b);
+        let limit = settings.rolling_coefficient*eff_radius*normal_force.abs();
+        let rel_rot_vel = self.state.rot_vel[a] - self.state.rot_vel[b];
+        if settings.rolling_model == 1 {
+            if rel_rot_vel == 0.0 {
+                return 0.0;
+            }
+            self.particle_energy[4] += 0.5*limit*rel_rot_vel.abs()*settings.timestep();
+            return -limit*rel_rot_vel.signum();
+        }
+        let rolling_stiffness = 2.25*normal_stiffness*settings.rolling_coefficient*settings.rolling_coefficient*eff_radius*eff_radius;
+        let contact = &mut self.contacts[i];
+        let trial_moment = contact.rolling_moment - rolling_stiffness*rel_rot_vel*settings.timestep();
+        contact.rolling_moment = trial_moment.max(-limit).min(limit);
+        let rolling_moment = contact.rolling_moment;
+        if rolling_stiffness > 0.0 {
+            self.particle_energy[4] += 0.5*limit*f32::max(trial_moment.abs() - limit, 0.0)/rolling_stiffness;
+            self.particle_energy[2] += 0.25*rolling_moment*rolling_moment/rolling_stiffness;
+        }
+        return rolling_moment;
+    }
+
+    fn hertz_mindlin(&self, a: usize, b: usize, overlap: f32, settings: &Settings) -> (f32, f32) {
+        let mat_a = self.state.material_pointers[a] as usize*settings.material_size;
+        let mat_b = self.state.material_pointers[b] as usize*settings.material_size;
+        let (youngs_a, poisson_a) = (settings.materials[mat_a + 6], settings.materials[mat_a + 7]);
+        let (youngs_b, poisson_b) = (settings.materials[mat_b + 6], settings.materials[mat_b + 7]);
+        let eff_youngs = 1.0/((1.0 - poisson_a*poisson_a)/youngs_a + (1.0 - poisson_b*poisson_b)/youngs_b);
+        let shear_a = youngs_a/(2.0*(1.0 + poisson_a));
+        let shear_b = youngs_b/(2.0*(1.0 + poisson_b));
+        let eff_shear = 1.0/((2.0 - poisson_a)/shear_a + (2.0 - poisson_b)/shear_b);
+        let (rad_a, rad_b) = (self.state.radii[a], self.state.radii[b]);
+        let eff_radius = rad_a*rad_b/(rad_a + rad_b);
+        let contact_radius = (eff_radius*overlap).sqrt();
+        return (4.0/3.0*eff_youngs*contact_radius*overlap, 8.0*eff_shear*contact_radius);
+    }
+
+    fn store_forces(&mut self, id: usize, net_force: (f32, f32), net_moment: f32, settings: &Settings) {
+        let delta_time = settings.timestep();
+        let mass = self.particle_mass(id, settings);
+        let gravity = self.gravity_acceleration(id, settings);
+        let vel = (self.state.vel[id*2], self.state.vel[id*2+1]);
+        let rot_vel = self.state.rot_vel[id];
+        let mut force = net_force;
+        let mut moment = net_moment;
+        // Cundall's local damping, each component of the resultant loses local_damping of its size against the velocity
+        if settings.damping_model & 1 != 0 {
+            let resultant = (net_force.0 + mass*gravity.0, net_force.1 + mass*gravity.1);
+            let damping_force = (-settings.local_damping*resultant.0.abs()*sign(vel.0), -settings.local_damping*resultant.1.abs()*sign(vel.1));
+            let damping_moment = -settings.local_damping*net_moment.abs()*sign(rot_vel);
+            force = (force.0 + damping_force.0, force.1 + damping_force.1);
+            moment += damping_moment;
+            self.particle_energy[5] -= (damping_force.0*vel.0 + damping_force.1*vel.1 + damping_moment*rot_vel)*delta_time;
+        }
+        if settings.fluid.flow != FluidFlow::None {
+            let fluid_force = self.fluid_force(id, mass, gravity, settings);
+            force = (force.0 + fluid_force.0, force.1 + fluid_force.1);
+            self.particle_energy[6] += (fluid_force.0*vel.0 + fluid_force.1*vel.1)*delta_time;
+        }
+        self.vel_buf[id*2] = vel.0 + force.0/mass * delta_time;
+        self.vel_buf[id*2+1] = vel.1 + force.1/mass * delta_time;
+        self.rot_vel_buf[id] = rot_vel + moment/self.rot_inertia(id, settings) * delta_time;
+
+        self.vel_buf[id*2] += gravity.0 * delta_time;
+        self.vel_buf[id*2+1] += gravity.1 * delta_time;
+    }
+
+    // fluid_force() in 2D_Simulation.wgsl
+    fn fluid_force(&self, id: usize, mass: f32, gravity: (f32, f32), settings: &Settings) -> (f32, f32) {
+        let vel = (self.state.vel[id*2], self.state.vel[id*2+1]);
+        let rate = f32::min(self.fluid_drag[id*3+2], 1.0/settings.timestep());
+        let buoyancy = settings.fluid.density/self.particle_density(id, settings);
+        return (mass*(rate*(self.fluid_drag[id*3] - vel.0) - buoyancy*gravity.0), mass*(rate*(self.fluid_drag[id*3+1] - vel.1) - buoyancy*gravity.1));
+    }
+
+    // Gravity, plus the pseudo force of a shaking base when the excitation drives gravity
+    fn gravity_acceleration(&self, id: usize, settings: &Settings) -> (f32, f32) {
+        let base = (-self.base[0], -self.base[1]);
+        if settings.gravity && settings.self_gravity {
+            return (self.self_gravity[id*3] + base.0, self.self_gravity[id*3+1] + base.1);
+        } else if settings.gravity && settings.planet_mode {
+            let delta = (-self.state.pos[id*2], -self.state.pos[id*2+1]);
+            let magnitude = (delta.0*delta.0 + delta.1*delta.1).sqrt();
+            let g = (settings.gravity_x*settings.gravity_x + settings.gravity_y*settings.gravity_y).sqrt();
+            return (delta.0/magnitude * 9.81 * g + base.0, delta.1/magnitude * 9.81 * g + base.1);
+        } else if settings.gravity {
+            return (9.81 * settings.gravity_x + base.0, 9.81 * settings.gravity_y + base.1); // MTIF (9.81)
+        }
+        return base;
+    }
+
+    fn box_walls(&mut self, id: usize, settings: &Settings) {
+        // BS Walls
+        // a shaking base moves the box with it, the bounce happens in the box's frame
+        let (base_vel, base_offset) = ((self.base[2], self.base[3]), (self.base[4], self.base[5]));
+        let before = (self.vel_buf[id*2], self.vel_buf[id*2+1]);
+        let mut rel_vel = (before.0 - base_vel.0, before.1 - base_vel.1);
+        let speed2 = rel_vel.0*rel_vel.0 + rel_vel.1*rel_vel.1;
+        let spin = self.rot_vel_buf[id];
+        let pos = (self.state.pos[id*2] - base_offset.0, self.state.pos[id*2+1] - base_offset.1);
+        let elasticity = settings.wall_elasticity;
+        let anti_stick_coating = 0.01; // MTIF
+        let y_h = settings.vert_bound;
+        let x_w = settings.hor_bound;
+
+        // how far the particle reaches right, left, up and down
+        let right = self.extent(id, (1.0, 0.0));
+        let left = self.extent(id, (-1.0, 0.0));
+        let up = self.extent(id, (0.0, 1.0));
+        let down = self.extent(id, (0.0, -1.0));
+
+        // periodic axes don't have walls, wrap() handles them instead
+        let x_walls = settings.boundary_x == 0;
+        let y_walls = settings.boundary_y == 0;
+        if x_walls && pos.0+right > x_w {
+            rel_vel = (-rel_vel.0*elasticity, rel_vel.1*elasticity);
+            self.rot_vel_buf[id] *= settings.wall_rot_damping;
+            self.set_pos(id, (x_w-right + base_offset.0, pos.1 + base_offset.1));
+        } else if x_walls && pos.0-left < -x_w {
+            rel_vel = (-rel_vel.0*elasticity, rel_vel.1*elasticity);
+            self.rot_vel_buf[id] *= settings.wall_rot_damping;
+            self.set_pos(id, (-x_w+left + base_offset.0, pos.1 + base_offset.1));
+        }
+        if y_walls && pos.1+up > y_h {
+            rel_vel = (rel_vel.0*elasticity, -rel_vel.1*elasticity);
+            self.rot_vel_buf[id] *= settings.wall_rot_damping;
+            self.set_pos(id, (pos.0 + base_offset.0, y_h-up - anti_stick_coating + base_offset.1));
+        } else if y_walls && pos.1-down < -y_h {
+            rel_vel = (rel_vel.0*elasticity, -rel_vel.1*elasticity);
+            self.rot_vel_buf[id] *= settings.wall_rot_damping;
+            self.set_pos(id, (pos.0 + base_offset.0, -y_h+down + base_offset.1));
+        }
+        self.vel_buf[id*2] = rel_vel.0 + base_vel.0;
+        self.vel_buf[id*2+1] = rel_vel.1 + base_vel.1;
+        // whatever the bounce took off in the box's frame goes to damping, a moving box can put some back as work
+        let mass = self.particle_mass(id, settings);
+        let after2 = rel_vel.0*rel_vel.0 + rel_vel.1*rel_vel.1;
+        let lost = 0.5*mass*(speed2 - after2);
+        self.particle_energy[5] += lost + 0.5*self.rot_inertia(id, settings)*(spin*spin - self.rot_vel_buf[id]*self.rot_vel_buf[id]);
+        let vel = (self.vel_buf[id*2], self.vel_buf[id*2+1]);
+        self.particle_energy[6] += 0.5*mass*((vel.0*vel.0 + vel.1*vel.1 - after2) - (before.0*before.0 + before.1*before.1 - speed2));
+    }
+
+    fn particle_density(&self, id: usize, settings: &Settings) -> f32 {
+        let mut density = 1.0; // MTIF
+        let mat_id = self.state.material_pointers[id];
+        if mat_id != -1 {
+            density = settings.materials[mat_id as usize*settings.material_size + 3];
+        }
+        return density;
+    }
+
+    fn conductivity(&self, id: usize, settings: &Settings) -> f32 {
+        let mut conductivity = 1.0; // MTIF
+        let mat_id = self.state.material_pointers[id];
+        if mat_id != -1 {
+            conductivity = settings.materials[mat_id as usize*settings.material_size + 14];
+        }
+        return conductivity;
+    }
+
+    fn specific_heat(&self, id: usize, settings: &Settings) -> f32 {
+        let mut specific_heat = 1.0; // MTIF
+        let mat_id = self.state.material_pointers[id];
+        if mat_id != -1 {
+            specific_heat = settings.materials[mat_id as usize*settings.material_size + 15];
+        }
+        return specific_heat;
+    }
+
+    // conduct() in 2D_Simulation.wgsl
+    fn conduct(&mut self, a: usize, b: usize, half_width: f32, settings: &Settings) {
+        if !settings.heat_conduction {
+            return;
+        }
+        let (k_a, k_b) = (self.conductivity(a, settings), self.conductivity(b, settings));
+        if k_a + k_b <= 0.0 {
+            return;
+        }
+        let k = 2.0*k_a*k_b/(k_a + k_b);
+        self.heat += 2.0*k*half_width*(self.state.temperature[b] - self.state.temperature[a]);
+    }
+
+    // store_temperature() in 2D_Simulation.wgsl
+    fn store_temperature(&mut self, id: usize, settings: &Settings) {
+        self.temperature_buf[id] = self.state.temperature[id];
+        if !settings.heat_conduction {
+            return;
+        }
+        let capacity = self.particle_mass(id, settings)*self.specific_heat(id, settings);
+        if capacity > 0.0 {
+            self.temperature_buf[id] += self.heat*settings.timestep()/capacity;
+        }
+    }
+
+    fn particle_mass(&self, id: usize, settings: &Settings) -> f32 {
+        if self.state.is_polygon(id) {
+            return self.particle_density(id, settings) * self.state.polygon_area(id);
+        }
+        return self.particle_density(id, settings) * PI * self.state.radii[id] * self.state.radii[id];
+    }
+
+    fn rot_inertia(&self, id: usize, settings: &Settings) -> f32 {
+        if self.state.is_polygon(id) {
+            return self.particle_density(id, settings) * self.state.polygon_inertia(id);
+        }
+        return 0.5 * self.particle_mass(id, settings) * self.state.radii[id] * self.state.radii[id];
+    }
+
+    // support() in 2D_Simulation.wgsl, the point of the particle farthest along dir
+    fn support(&self, id: usize, dir: (f32, f32)) -> (f32, f32) {
+        if !self.state.is_polygon(id) {
+            return (dir.0*self.state.radii[id], dir.1*self.state.radii[id]);
+        }
+        let mut best = self.state.polygon_vertex(id, 0);
+        for k in 1..self.state.polygon_info[id*2+1] as usize {
+            let corner = self.state.polygon_vertex(id, k);
+            if dot(corner, dir) > dot(best, dir) {
+                best = corner;
+            }
+        }
+        return best;
+    }
+
+    fn extent(&self, id: usize, dir: (f32, f32)) -> f32 {
+        if !self.state.is_polygon(id) {
+            return self.state.radii[id];
+        }
+        return dot(self.support(id, dir), dir);
+    }
+
+    fn touching(&self, a: usize, b: usize, range: f32, settings: &Settings) -> bool {
+        if self.distance(a, b, settings) >= range {
+            return false;
+        }
+        return !(self.state.is_polygon(a) || self.state.is_polygon(b)) || self.shape_contact(a, b, settings).overlap > -range;
+    }
+
+    fn cohesion_range(&self, a: usize, b: usize, settings: &Settings) -> f32 {
+        let (mat_a, mat_b) = (self.state.material_pointers[a], self.state.material_pointers[b]);
+        if mat_a == -1 || mat_b == -1 {
+            return 0.0;
+        }
+        let (rad_a, rad_b) = (self.state.radii[a], self.state.radii[b]);
+        return settings.cohesion_range(mat_a as usize, mat_b as usize, rad_a*rad_b/(rad_a + rad_b));
+    }
+
+    // cohesion() in 2D_Simulation.wgsl, the attraction at this gap and its potential energy
+    fn cohesion(&self, a: usize, b: usize, gap: f32, touched: bool, settings: &Settings) -> (f32, f32) {
+        let range = self.cohesion_range(a, b, settings);
+        if self.state.material_pointers[a] == -1 || self.state.material_pointers[b] == -1 || gap >= range {
+            return (0.0, 0.0);
+        }
+        let mat_a = &settings.materials[self.state.material_pointers[a] as usize*settings.material_size..];
+        let mat_b = &settings.materials[self.state.material_pointers[b] as usize*settings.material_size..];
+        let pair = settings.pair(self.state.material_pointers[a] as usize, self.state.material_pointers[b] as usize);
+        let (rad_a, rad_b) = (self.state.radii[a], self.state.radii[b]);
+        let eff_radius = rad_a*rad_b/(rad_a + rad_b);
+        let adhesion = pair[6];
+        if pair[5] == 1.0 {
+            if !touched {
+                return (0.0, 0.0);
+            }
+            let eff_youngs = 1.0/((1.0 - mat_a[7]*mat_a[7])/mat_a[6] + (1.0 - mat_b[7]*mat_b[7])/mat_b[6]);
+            let overlap = -gap;
+            let c = (2.0*PI*adhesion/eff_youngs).sqrt();
+            let contact_radius = jkr_radius(overlap, eff_radius, c);
+            let jkr = 4.0*eff_youngs*contact_radius.powi(3)/(3.0*eff_radius) - 2.0*eff_youngs*c*contact_radius.powf(1.5);
+            let squeeze = overlap.max(0.0);
+            let hertz = 4.0/3.0*eff_youngs*(eff_radius*squeeze).sqrt()*squeeze;
+            // the work done squeezing it in from the neck, less the Hertz part the contact model already has
+            let neck = (PI*adhesion*eff_radius*eff_radius/(8.0*eff_youngs)).cbrt();
+            let work = jkr_work(contact_radius, eff_radius, c, eff_youngs) - jkr_work(neck, eff_radius, c, eff_youngs);
+            return (hertz - jkr, work - 0.4*hertz*squeeze);
+        } else if pair[5] == 2.0 {
+            let pull_off = 2.0*PI*adhesion*eff_radius;
+            return (pull_off, pull_off*gap);
+        } else if pair[5] == 3.0 {
+            let volume = pair[7];
+            if volume <= 0.0 || !touched {
+                return (0.0, 0.0);
+            }
+            let angle = pair[9].to_radians();
+            let bridge = 2.0*PI*eff_radius*pair[8]*angle.cos();
+            let k = (eff_radius/volume).sqrt();
+            let s = k*gap.max(0.0);
+            let q = (10.0 - 1.05*1.05 as f32).sqrt();
+            let potential = -bridge*2.0/(k*q)*(((5.0*k*range + 1.05)/q).atan() - ((5.0*s + 1.05)/q).atan());
+            return (bridge/(1.0 + 1.05*s + 2.5*s*s), potential + bridge*gap.min(0.0));
+        }
+        return (0.0, 0.0);
+    }
+
+    fn shape_contact(&self, a: usize, b: usize, settings: &Settings) -> ShapeContact {
+        let offset = self.separation(a, b, settings);
+        if self.state.is_polygon(a) && self.state.is_polygon(b) {
+            return self.polygon_polygon(a, b, offset);
+        } else if self.state.is_polygon(a) {
+            return self.polygon_disc(a, b, offset);
+        } else if self.state.is_polygon(b) {
+            let flipped = self.polygon_disc(b, a, (-offset.0, -offset.1));
+            return ShapeContact { normal: (-flipped.normal.0, -flipped.normal.1), point: (flipped.point.0 + offset.0, flipped.point.1 + offset.1), overlap: flipped.overlap };
+        }
+        let length = dot(offset, offset).sqrt();
+        let normal = (-offset.0/length, -offset.1/length);
+        let overlap = self.state.radii[a] + self.state.radii[b] - length;
+        let reach = self.state.radii[a] - 0.5*overlap;
+        return ShapeContact { normal, point: (-normal.0*reach, -normal.1*reach), overlap };
+    }
+
+    // polygon_disc() in 2D_Simulation.wgsl
+    fn polygon_disc(&self, p: usize, d: usize, offset: (f32, f32)) -> ShapeContact {
+        let count = self.state.polygon_info[p*2+1] as usize;
+        let mut face_distance = -1e30;
+        let mut face_normal = (0.0, 0.0);
+        for k in 0..count {
+            let v = self.state.polygon_vertex(p, k);
+            let w = self.state.polygon_vertex(p, (k + 1)%count);
+            let n = outward(v, w);
+            let s = dot((offset.0 - v.0, offset.1 - v.1), n);
+            if s > face_distance {
+                face_distance = s;
+                face_normal = n;
+            }
+        }
+        let mut n = face_normal;
+        let mut closest = (offset.0 - face_normal.0*face_distance, offset.1 - face_normal.1*face_distance);
+        let mut overlap = self.state.radii[d] - face_distance;
+        if face_distance > 0.0 {
+            let mut distance = 1e30;
+            for k in 0..count {
+                let v = self.state.polygon_vertex(p, k);
+                let w = self.state.polygon_vertex(p, (k + 1)%count);
+                let edge = (w.0 - v.0, w.1 - v.1);
+                let t = (dot((offset.0 - v.0, offset.1 - v.1), edge)/dot(edge, edge)).max(0.0).min(1.0);
+                let q = (v.0 + edge.0*t, v.1 + edge.1*t);
+                let gap = (offset.0 - q.0, offset.1 - q.1);
+                if dot(gap, gap).sqrt() < distance {
+                    distance = dot(gap, gap).sqrt();
+                    closest = q;
+                }
+            }
+            n = ((offset.0 - closest.0)/distance, (offset.1 - closest.1)/distance);
+            overlap = self.state.radii[d] - distance;
+        }
+        return ShapeContact { normal: (-n.0, -n.1), point: (closest.0 - 0.5*overlap*n.0, closest.1 - 0.5*overlap*n.1), overlap };
+    }
+
+    // polygon_polygon() in 2D_Simulation.wgsl
+    fn polygon_polygon(&self, a: usize, b: usize, offset: (f32, f32)) -> ShapeContact {
+        let corners = |id: usize, shift: (f32, f32)| -> Vec<(f32, f32)> {
+            return (0..self.state.polygon_info[id*2+1] as usize).map(|k| {
+                let v = self.state.polygon_vertex(id, k);
+                return (v.0 + shift.0, v.1 + shift.1);
+            }).collect();
+        };
+        let (corners_a, corners_b) = (corners(a, (0.0, 0.0)), corners(b, offset));
+        let mut overlap = 1e30;
+        let mut axis = (0.0, 0.0);
+        let mut reference_a = true;
+        for (reference, other, is_a) in [(&corners_a, &corners_b, true), (&corners_b, &corners_a, false)] {
+            for k in 0..reference.len() {
+                let v = reference[k];
+                let n = outward(v, reference[(k + 1)%reference.len()]);
+                let deepest = other.iter().fold(1e30, |m: f32, &c| m.min(dot(c, n)));
+                if dot(v, n) - deepest < overlap {
+                    overlap = dot(v, n) - deepest;
+                    axis = n;
+                    reference_a = is_a;
+                }
+            }
+        }
+        // the corners on the other side of the reference face
+        let (incident, incident_id) = if reference_a { (&corners_b, b) } else { (&corners_a, a) };
+        let deepest = incident.iter().fold(1e30, |m: f32, &c| m.min(dot(c, axis)));
+        let tolerance = 0.05*self.state.radii[incident_id]; // MTIF
+        let mut point = (0.0, 0.0);
+        let mut count = 0.0;
+        for &c in incident.iter() {
+            if dot(c, axis) <= deepest + tolerance {
+                point = (point.0 + c.0, point.1 + c.1);
+                count += 1.0;
+            }
+        }
+        point = (point.0/count + 0.5*overlap*axis.0, point.1/count + 0.5*overlap*axis.1);
+        // axis points out of the reference polygon
+        if reference_a {
+            return ShapeContact { normal: (-axis.0, -axis.1), point, overlap };
+        }
+        return ShapeContact { normal: axis, point, overlap };
+    }
+
+    fn segment_walls(&mut self, id: usize, settings: &Settings) -> (f32, f32, f32) {
+        let mass = self.particle_mass(id, settings);
+        let mut wall_force = (0.0, 0.0, 0.0);
+        for w in 0..self.state.walls.len()/settings.wall_size {
+            let contact = self.wall_contact(id, w, mass, settings);
+            if contact.0 != 0.0 || contact.1 != 0.0 {
+                let wall = &self.state.walls[w*settings.wall_size..(w + 1)*settings.wall_size];
+                let t = CPUComputeProg::wall_t(wall, (self.state.pos[id*2], self.state.pos[id*2+1]));
+                let delta = (wall[0] + (wall[2] - wall[0])*t - self.state.pos[id*2], wall[1] + (wall[3] - wall[1])*t - self.state.pos[id*2+1]);
+                let scale = self.state.radii[id]/(delta.0*delta.0 + delta.1*delta.1).sqrt();
+                self.add_stress((delta.0*scale, delta.1*scale), (contact.0, contact.1));
+            }
+            wall_force.0 += contact.0;
+            wall_force.1 += contact.1;
+            wall_force.2 += contact.2;
+            self.wall_heat(id, w, settings);
+        }
+        return wall_force;
+    }
+
+    // wall_heat() in 2D_Simulation.wgsl
+    fn wall_heat(&mut self, id: usize, w: usize, settings: &Settings) {
+        if !settings.heat_conduction || self.wall_overlap <= 0.0 {
+            return;
+        }
+        let (thermal, heat) = (self.state.walls[w*settings.wall_size + 14], self.state.walls[w*settings.wall_size + 15]);
+        let half_width = (self.state.radii[id]*self.wall_overlap).sqrt();
+        if thermal == 1.0 {
+            self.heat += 2.0*self.conductivity(id, settings)*half_width*(heat - self.state.temperature[id]);
+        } else if thermal == 2.0 {
+            self.heat += 2.0*half_width*heat;
+        }
+    }
+
+    fn wall_t(wall: &[f32], pos: (f32, f32)) -> f32 {
+        let seg = (wall[2] - wall[0], wall[3] - wall[1]);
+        let seg_len2 = seg.0*seg.0 + seg.1*seg.1;
+        if seg_len2 > 0.0 {
+            return (((pos.0 - wall[0])*seg.0 + (pos.1 - wall[1])*seg.1)/seg_len2).max(0.0).min(1.0);
+        }
+        return 0.0;
+    }
+
+    fn wall_contact(&mut self, id: usize, w: usize, mass: f32, settings: &Settings) -> (f32, f32, f32) {
+        let pos = (self.state.pos[id*2], self.state.pos[id*2+1]);
+        let mut rad = self.state.radii[id];
+        let wall = &self.state.walls[w*settings.wall_size..(w + 1)*settings.wall_size];
+        let (stiffness, friction, restitution, joined, speed) = (wall[4], wall[5], wall[6], wall[7], wall[11]);
+        let seg = (wall[2] - wall[0], wall[3] - wall[1]);
+        let seg_len2 = seg.0*seg.0 + seg.1*seg.1;
+        let t = CPUComputeProg::wall_t(wall, pos);
+        self.wall_overlap = 0.0;
+        if (joined == 1.0 && t == 0.0) || wall[8] == 3.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let delta = (pos.0 - (wall[0] + seg.0*t), pos.1 - (wall[1] + seg.1*t));
+        let dist = (delta.0*delta.0 + delta.1*delta.1).sqrt();
+        // a polygon reaches the wall with whichever corner is farthest toward it
+        let mut arm = (0.0, 0.0);
+        if self.state.is_polygon(id) && dist > 0.0 {
+            arm = self.support(id, (-delta.0/dist, -delta.1/dist));
+            rad = dot(arm, (-delta.0/dist, -delta.1/dist));
+        }
+        let overlap = rad - dist;
+        if !(overlap > 0.0) || dist == 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        self.wall_overlap = overlap;
+        let normal = (delta.0/dist, delta.1/dist);
+        let tangent = (-normal.1, normal.0);
+        let base_vel = (self.base[2], self.base[3]);
+        let mut vel = (self.state.vel[id*2] - base_vel.0, self.state.vel[id*2+1] - base_vel.1);
+        if speed != 0.0 {
+            let seg_len = seg_len2.sqrt();
+            vel.0 -= -seg.1/seg_len*speed;
+            vel.1 -= seg.0/seg_len*speed;
+        }
+
+        let mut damping_ratio = 1.0;
+        if restitution > 0.0 {
+            let log_e = restitution.ln();
+            damping_ratio = -log_e/(PI*PI + log_e*log_e).sqrt();
+        }
+        let dashpot = 2.0*damping_ratio*(stiffness*mass).sqrt();
+        let normal_force = f32::max(stiffness*overlap - dashpot*(vel.0*normal.0 + vel.1*normal.1), 0.0);
+        // walls don't move in response, so the particle books all of it (platens() just throws this away)
+        self.particle_energy[2] += 0.5*stiffness*overlap*overlap;
+        if normal_force > 0.0 {
+            self.particle_energy[5] += dashpot*(vel.0*normal.0 + vel.1*normal.1).powi(2)*settings.timestep();
+        }
+
+        let mut slip = vel.0*tangent.0 + vel.1*tangent.1 - self.state.rot_vel[id]*rad;
+        if self.state.is_polygon(id) {
+            let spin = self.state.rot_vel[id];
+            slip = dot((vel.0 - spin*arm.1, vel.1 + spin*arm.0), tangent);
+        }
+        let mut friction_limit = friction*normal_force;
+        if !settings.friction {
+            friction_limit = 0.0;
+        }
+        let mut tangent_force = 0.0;
+        if slip != 0.0 {
+            tangent_force = -slip.signum()*f32::min(friction_limit, mass*slip.abs()/(3.0*settings.timestep()));
+        }
+        self.particle_energy[4] += (tangent_force*slip).abs()*settings.timestep();
+        let force = (normal.0*normal_force + tangent.0*tangent_force, normal.1*normal_force + tangent.1*tangent_force);
+        // a shaking base does work through the wall
+        self.particle_energy[6] += (force.0*base_vel.0 + force.1*base_vel.1)*settings.timestep();
+
+        if self.state.is_polygon(id) {
+            return (force.0, force.1, cross(arm, force));
+        }
+        return (force.0, force.1, -rad*tangent_force);
+    }
+
+    // platens() in 2D_Simulation.wgsl
+    fn platens(&mut self, settings: &Settings) {
+        let ws = settings.wall_size;
+        for w in 0..self.state.walls.len()/ws {
+            let mut force = (0.0, 0.0);
+            let mut centroid = (0.0, 0.0, 0.0);
+            let ends = &self.state.walls[w*ws..w*ws + 4];
+            for id in self.grid.around_box((ends[0].min(ends[2]), ends[1].min(ends[3])), (ends[0].max(ends[2]), ends[1].max(ends[3]))) {
+                let contact = self.wall_contact(id, w, self.particle_mass(id, settings), settings);
+                force.0 += contact.0;
+                force.1 += contact.1;
+            }
+            if self.state.walls[w*ws + 7] != 1.0 && self.state.walls[w*ws + 8] == 1.0 {
+                for id in 0..self.state.p_count {
+                    let pos = (self.state.pos[id*2], self.state.pos[id*2+1]);
+                    if pos.0.abs() <= settings.hor_bound && pos.1.abs() <= settings.vert_bound {
+                        centroid = (centroid.0 + pos.0, centroid.1 + pos.1, centroid.2 + 1.0);
+                    }
+                }
+            }
+
+            let wall = &mut self.state.walls[w*ws..(w + 1)*ws];
+            // a shaking base carries every wall along with it, as far as its displacement says
+            let base_step = (self.wall_step[0], self.wall_step[1]);
+            wall[0] += base_step.0;
+            wall[1] += base_step.1;
+            wall[2] += base_step.0;
+            wall[3] += base_step.1;
+            let seg = (wall[2] - wall[0], wall[3] - wall[1]);
+            let seg_len = (seg.0*seg.0 + seg.1*seg.1).sqrt();
+            if seg_len == 0.0 {
+                continue;
+            }
+            let normal = (-seg.1/seg_len, seg.0/seg_len);
+            wall[12] = force.0*normal.0 + force.1*normal.1;
+            wall[13] = (force.0*seg.0 + force.1*seg.1)/seg_len;
+
+            let mut speed = 0.0;
+            if wall[7] != 1.0 && wall[8] == 1.0 && centroid.2 > 0.0 {
+                let middle = ((wall[0] + wall[2])/2.0, (wall[1] + wall[3])/2.0);
+                speed = wall[9]*((centroid.0/centroid.2 - middle.0)*normal.0 + (centroid.1/centroid.2 - middle.1)*normal.1);
+            } else if wall[7] != 1.0 && wall[8] == 2.0 {
+                speed = wall[10]*(wall[9] - wall[12]/seg_len);
+            }
+            wall[11] = speed;
+            let step = (normal.0*speed*settings.timestep(), normal.1*speed*settings.timestep());
+            wall[0] += step.0;
+            wall[1] += step.1;
+            wall[2] += step.0;
+            wall[3] += step.1;
+        }
+    }
+
+    fn set_pos(&mut self, id: usize, pos: (f32, f32)) {
+        self.state.pos[id*2] = pos.0;
+        self.state.pos[id*2+1] = pos.1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Structure;
+
+    // two equal discs closing head on, nothing else in the box
+    fn head_on(speed: f32) -> (CPUComputeProg, Settings) {
+        let mut settings = Settings::new();
+        settings.particles = 2;
+        settings.structure = Structure::Random;
+        settings.gravity = false;
+        let mut state = State::from_settings(&mut settings);
+        let radius = 0.01;
+        state.radii = vec![radius; 2];
+        state.pos = vec![-1.5*radius, 0.0, 1.5*radius, 0.0];
+        state.vel = vec![speed, 0.0, -speed, 0.0];
+        state.rot_vel = vec![0.0; 2];
+        let prog = CPUComputeProg::from_state(state, &mut settings);
+        return (prog, settings);
+    }
+
+    #[test]
+    fn head_on_collision_keeps_momentum_and_bounces_back() {
+        let speed = 0.5;
+        let (mut prog, settings) = head_on(speed);
+        let radius = prog.state.radii[0];
+        // long enough to close the gap, bounce and get clear
+        let steps = (4.0*radius/(speed*settings.timestep())) as usize;
+        let mut touched = false;
+        for _ in 0..steps {
+            prog.step(&settings);
+            assert!((prog.state.vel[0] + prog.state.vel[2]).abs() < 1e-6*speed, "momentum x {}", prog.state.vel[0] + prog.state.vel[2]);
+            assert!((prog.state.vel[1] + prog.state.vel[3]).abs() < 1e-6*speed, "momentum y {}", prog.state.vel[1] + prog.state.vel[3]);
+            touched = touched || prog.state.pos[2] - prog.state.pos[0] < 2.0*radius;
+        }
+        assert!(touched);
+        assert!(prog.state.pos.iter().all(|p| p.is_finite()));
+        // no damping, so they leave as fast as they came
+        assert!(prog.state.vel[0] < 0.0 && prog.state.vel[2] > 0.0, "still closing {:?}", prog.state.vel);
+        assert!((prog.state.vel[0] + speed).abs() < 0.05*speed, "came back at {}", prog.state.vel[0]);
+    }
+
+    #[test]
+    fn disc_without_a_material_bounces_off_one_with() {
+        for contact_model in [0, 1] {
+            let speed = 0.5;
+            let (mut prog, mut settings) = head_on(speed);
+            settings.contact_model = contact_model;
+            prog.state.material_pointers = vec![0, -1];
+            let masses = prog.state.masses(&settings);
+            let radius = prog.state.radii[0];
+            let steps = (4.0*radius/(speed*settings.timestep())) as usize;
+            for _ in 0..steps {
+                prog.step(&settings);
+            }
+            // both ends fall back on the same defaults, so the pair still pushes equal and opposite
+            let momentum = masses[0]*prog.state.vel[0] + masses[1]*prog.state.vel[2];
+            let before = (masses[0] - masses[1])*speed;
+            assert!(prog.state.vel.iter().all(|v| v.is_finite()));
+            assert!((momentum - before).abs() < 1e-4*(masses[0] + masses[1])*speed, "model {}: momentum {} from {}", contact_model, momentum, before);
+            assert!(prog.state.vel[0] < 0.0 && prog.state.vel[2] > 0.0, "model {}: still closing {:?}", contact_model, prog.state.vel);
+        }
+    }
+
+    #[test]
+    fn mixed_pair_sticks_with_its_own_cohesion_model() {
+        let (mut prog, mut settings) = head_on(0.0);
+        settings.add_material();
+        // DMT on material 0 only, so the pair starts out with none
+        settings.materials[9] = 2.0;
+        settings.derive_material_cohesion(0);
+        prog.state.material_pointers = vec![0, 1];
+        let gap = -1e-4;
+        assert_eq!(prog.cohesion(0, 1, gap, true, &settings), (0.0, 0.0));
+        let mut pair = settings.pair(0, 1).to_vec();
+        pair[5] = 2.0;
+        settings.set_pair(0, 1, &pair);
+        let (force, _) = prog.cohesion(0, 1, gap, true, &settings);
+        let eff_radius = 0.5*prog.state.radii[0];
+        assert!((force - 2.0*PI*pair[6]*eff_radius).abs() < 1e-6*force, "{} vs {}", force, 2.0*PI*pair[6]*eff_radius);
+    }
+
+    #[test]
+    fn apart_discs_coast_and_fall() {
+        let (mut prog, mut settings) = head_on(0.0);
+        prog.state.pos = vec![-0.2, 0.0, 0.2, 0.0];
+        for _ in 0..10 {
+            prog.step(&settings);
+        }
+        assert_eq!(prog.state.vel, vec![0.0; 4]);
+        settings.gravity = true;
+        let steps = 100;
+        for _ in 0..steps {
+            prog.step(&settings);
+        }
+        // gravity is in g, and the first step's force only lands on the next
+        let expected = 9.81*settings.gravity_y*(steps - 1) as f32*settings.timestep();
+        for id in 0..2 {
+            assert_eq!(prog.state.vel[id*2], 0.0);
+            assert!((prog.state.vel[id*2+1] - expected).abs() < 1e-4*expected.abs(), "{} vs {}", prog.state.vel[id*2+1], expected);
+        }
+    }
+
+    // JKR for a pair of 1 mm discs with E* = 1e8 and w = 0.05
+    const R: f32 = 5e-4;
+    const E: f32 = 1e8;
+    const W: f32 = 0.05;
+
+    fn c() -> f32 {
+        return (2.0*std::f32::consts::PI*W/E).sqrt();
+    }
+
+    #[test]
+    fn jkr_radius_solves_the_overlap_relation() {
+        let c = c();
+        let neck = (R*R*c*c/16.0).cbrt();
+        let neck_overlap = neck*neck/R - c*neck.sqrt();
+        for overlap in [neck_overlap*0.5, 0.0, 1e-7, 1e-6, 1e-5] {
+            let a = jkr_radius(overlap, R, c);
+            let miss = a*a/R - c*a.sqrt() - overlap;
+            assert!(miss.abs() < 1e-3*overlap.abs().max(neck_overlap.abs()), "overlap {overlap}: a {a}, off by {miss}");
+        }
+        // zero load contact radius
+        let a0 = (4.5*R*R*c*c).cbrt();
+        assert!((jkr_radius(a0*a0/R - c*a0.sqrt(), R, c) - a0).abs() < 1e-3*a0);
+        // past the neck it doesn't go below it
+        assert_eq!(jkr_radius(neck_overlap*2.0, R, c), neck);
+    }
+
+    #[test]
+    fn jkr_pulls_off_at_five_sixths_pi_w_r() {
+        let c = c();
+        let a = (R*R*c*c/16.0).cbrt();
+        let force = 4.0*E*a*a*a/(3.0*R) - 2.0*E*c*a.powf(1.5);
+        let expected = -5.0/6.0*std::f32::consts::PI*W*R;
+        assert!((force - expected).abs() < 1e-3*expected.abs(), "{force} vs {expected}");
+    }
+
+    #[test]
+    fn jkr_work_is_the_integral_of_the_force() {
+        let c = c();
+        let a0 = (4.5*R*R*c*c).cbrt();
+        let overlap = |a: f32| a*a/R - c*a.sqrt();
+        let force = |a: f32| 4.0*E*a*a*a/(3.0*R) - 2.0*E*c*a.powf(1.5);
+        for a in [a0*0.8, a0, a0*1.5, a0*3.0] {
+            let da = a*1e-3;
+            let slope = (jkr_work(a + da, R, c, E) - jkr_work(a - da, R, c, E))/(overlap(a + da) - overlap(a - da));
+            assert!((slope - force(a)).abs() < 1e-2*force(a).abs().max(E*c*a0.powf(1.5)), "a {a}: {slope} vs {}", force(a));
+        }
+    }
+}
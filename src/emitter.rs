@@ -0,0 +1,157 @@
+// Emitters and sinks, the particle count changes while running.
+// Emitters owe rate*elapsed particles and place whole ones wherever they fit, see Settings::add_emitter.
+// Sinks take out anything whose center is in their rectangle, outflow walls (control 3) anything touching or crossing them.
+// Works on a State with exactly p_count particles, the CPU stepper runs it every step and WGPUComputeProg::update_emitters once a frame.
+
+use rand::Rng;
+use crate::grid::{self, Grid};
+use crate::settings::Settings;
+use crate::state::State;
+
+// tries at finding a free spot for a new particle before the emitter gives up until the next update
+const PLACEMENT_TRIES: usize = 20;
+
+pub struct Emitters {
+    // particles each emitter still owes, the fraction carries over
+    owed: Vec<f32>,
+    // positions at the last update, to catch particles that skipped over an outflow wall in between
+    last_pos: Vec<f32>,
+}
+
+impl Emitters {
+    pub fn new() -> Self {
+        return Emitters { owed: vec![], last_pos: vec![] };
+    }
+
+    // Books elapsed seconds of emission, then works out which particles stay.
+    // None when nothing is leaving and no emitter owes a whole particle, otherwise the keep list for apply.
+    pub fn pending(&mut self, settings: &Settings, state: &State, elapsed: f32) -> Option<Vec<bool>> {
+        let e = settings.emitter_size;
+        self.owed.resize(settings.emitter_count(), 0.0);
+        let mut due = false;
+        for i in 0..settings.emitter_count() {
+            self.owed[i] += settings.emitters[i*e + 4].max(0.0)*elapsed;
+            due = due || self.owed[i] >= 1.0;
+        }
+        let keep = self.keep(settings, state);
+        if !due && !keep.contains(&false) {
+            self.remember(settings, state);
+            return None;
+        }
+        return Some(keep);
+    }
+
+    // Removes what isn't kept, then places what the emitters owe.
+    // Returns where each old particle went (see State::remove_particles), the new ones are on the end.
+    pub fn apply(&mut self, settings: &Settings, state: &mut State, keep: &[bool]) -> Vec<i32> {
+        let map = state.remove_particles(keep);
+        let e = settings.emitter_size;
+        let mut largest = state.largest_radius();
+        for i in 0..settings.emitter_count() {
+            largest = largest.max(settings.emitters[i*e + 6]);
+        }
+        let (cell_size, width, height) = grid::dimensions(settings.hor_bound, settings.vert_bound, 2.0*largest);
+        let grid = Grid::new(&state.pos, cell_size, (-settings.hor_bound, -settings.vert_bound), width, height);
+        let existing = state.p_count;
+        let materials = (settings.materials.len()/settings.material_size) as i32;
+        let mut rng = rand::thread_rng();
+        for i in 0..settings.emitter_count() {
+            let emitter = &settings.emitters[i*e..(i + 1)*e];
+            let (min_x, max_x) = (emitter[0].min(emitter[2]), emitter[0].max(emitter[2]));
+            let (min_y, max_y) = (emitter[1].min(emitter[3]), emitter[1].max(emitter[3]));
+            let (min_r, max_r) = (emitter[5].max(0.0), emitter[6].max(emitter[5]));
+            let material = (emitter[9] as i32).clamp(-1, materials - 1);
+            while self.owed[i] >= 1.0 {
+                self.owed[i] -= 1.0;
+                let radius = if max_r > min_r { rng.gen_range(min_r..=max_r) } else { min_r };
+                if !(radius > 0.0) {
+                    continue;
+                }
+                for _ in 0..PLACEMENT_TRIES {
+                    // kept inside the rectangle where it's wide enough, centered on it where it isn't
+                    let x = if max_x - min_x > 2.0*radius { rng.gen_range(min_x + radius..max_x - radius) } else { 0.5*(min_x + max_x) };
+                    let y = if max_y - min_y > 2.0*radius { rng.gen_range(min_y + radius..max_y - radius) } else { 0.5*(min_y + max_y) };
+                    let overlaps = |j: usize| (state.pos[j*2] - x).powi(2) + (state.pos[j*2+1] - y).powi(2) < (state.radii[j] + radius).powi(2);
+                    if grid.neighbors(x, y).into_iter().any(overlaps) || (existing..state.p_count).any(overlaps) {
+                        continue;
+                    }
+                    state.add_particle(settings, (x, y), (emitter[7], emitter[8]), radius, material);
+                    break;
+                }
+            }
+            // a full emitter doesn't save up a burst for when it clears
+            self.owed[i] = self.owed[i].fract();
+        }
+        self.remember(settings, state);
+        return map;
+    }
+
+    // Whether each particle stays, a clump goes out whole as soon as any of its discs does
+    fn keep(&self, settings: &Settings, state: &State) -> Vec<bool> {
+        let (k, w) = (settings.sink_size, settings.wall_size);
+        let mut keep = vec![true; state.p_count];
+        let mut gone_clumps = vec![];
+        let crossing = self.last_pos.len() == state.pos.len();
+        for id in 0..state.p_count {
+            let pos = (state.pos[id*2], state.pos[id*2+1]);
+            for s in 0..settings.sink_count() {
+                let sink = &settings.sinks[s*k..(s + 1)*k];
+                if pos.0 >= sink[0].min(sink[2]) && pos.0 <= sink[0].max(sink[2]) && pos.1 >= sink[1].min(sink[3]) && pos.1 <= sink[1].max(sink[3]) {
+                    keep[id] = false;
+                }
+            }
+            for i in 0..settings.wall_count() {
+                let wall = &state.walls[i*w..(i + 1)*w];
+                if wall[8] != 3.0 {
+                    continue;
+                }
+                let (start, end) = ((wall[0], wall[1]), (wall[2], wall[3]));
+                let last = if crossing { (self.last_pos[id*2], self.last_pos[id*2+1]) } else { pos };
+                // a jump across a periodic seam isn't a crossing
+                let wrapped = (pos.0 - last.0).abs() > settings.hor_bound || (pos.1 - last.1).abs() > settings.vert_bound;
+                if Emitters::distance(pos, start, end) < state.radii[id] || (!wrapped && Emitters::crosses(last, pos, start, end)) {
+                    keep[id] = false;
+                }
+            }
+            if !keep[id] && state.clump_pointers[id] != -1 {
+                gone_clumps.push(state.clump_pointers[id]);
+            }
+        }
+        if !gone_clumps.is_empty() {
+            for id in 0..state.p_count {
+                if gone_clumps.contains(&state.clump_pointers[id]) {
+                    keep[id] = false;
+                }
+            }
+        }
+        return keep;
+    }
+
+    fn remember(&mut self, settings: &Settings, state: &State) {
+        let w = settings.wall_size;
+        if (0..settings.wall_count()).any(|i| state.walls[i*w + 8] == 3.0) {
+            self.last_pos.clone_from(&state.pos);
+        } else {
+            self.last_pos.clear();
+        }
+    }
+
+    // from p to the closest point on the segment a to b, NaN for a NaN position so it never counts
+    fn distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+        let seg = (b.0 - a.0, b.1 - a.1);
+        let length2 = seg.0*seg.0 + seg.1*seg.1;
+        let mut t = 0.0;
+        if length2 > 0.0 {
+            t = (((p.0 - a.0)*seg.0 + (p.1 - a.1)*seg.1)/length2).clamp(0.0, 1.0);
+        }
+        return ((p.0 - a.0 - seg.0*t).powi(2) + (p.1 - a.1 - seg.1*t).powi(2)).sqrt();
+    }
+
+    // whether the move from p to q passes through the segment a to b
+    fn crosses(p: (f32, f32), q: (f32, f32), a: (f32, f32), b: (f32, f32)) -> bool {
+        let side = |o: (f32, f32), u: (f32, f32), v: (f32, f32)| (u.0 - o.0)*(v.1 - o.1) - (u.1 - o.1)*(v.0 - o.0);
+        let (d1, d2) = (side(a, b, p), side(a, b, q));
+        let (d3, d4) = (side(p, q, a), side(p, q, b));
+        return d1*d2 < 0.0 && d3*d4 < 0.0;
+    }
+}
@@ -0,0 +1,265 @@
+// Coarse Eulerian fluid grid for CFD-DEM lite drag coupling.
+// The flow is prescribed (see Fluid in settings.rs), the grid only tracks how much of each cell the particles fill and what that does to the local fluid velocity.
+// Built on the CPU every step by the CPU stepper, and once a frame from the read back positions and velocities for the GPU (see WGPUComputeProg::update_fluid).
+// The drag correlations are for spheres, each disc is treated as a sphere of the same radius and the drag goes on as an acceleration so it's the same for the disc's 2D mass.
+
+use crate::settings::{DragModel, Fluid, FluidFlow};
+
+// densest a layer of discs can pack, hexagonal
+const MAX_SOLID_FRACTION: f32 = 0.9069;
+
+pub struct FluidGrid {
+    pub width: usize,
+    pub height: usize,
+    origin: (f32, f32),
+    cell: (f32, f32),
+    // 3D equivalent porosity per cell, see FluidGrid::porosity_3d
+    pub porosity: Vec<f32>,
+    // interstitial velocity x, y per cell
+    pub velocity: Vec<f32>,
+}
+
+impl FluidGrid {
+    // Particles with a NaN position or no area are left out, see State::areas.
+    pub fn new(fluid: &Fluid, bounds: (f32, f32), pos: &[f32], area: &[f32]) -> Self {
+        let width = fluid.grid_w.max(1) as usize;
+        let height = fluid.grid_h.max(1) as usize;
+        let cell = (2.0*bounds.0/width as f32, 2.0*bounds.1/height as f32);
+        let mut grid = FluidGrid {
+            width,
+            height,
+            origin: (-bounds.0, -bounds.1),
+            cell,
+            porosity: vec![0.0; width*height],
+            velocity: vec![0.0; width*height*2],
+        };
+        // cloud in cell, each particle's area is shared between the 4 cell centers around it
+        let mut solid = vec![0.0; width*height];
+        for i in 0..area.len() {
+            if !(area[i] > 0.0) || !pos[i*2].is_finite() || !pos[i*2+1].is_finite() {
+                continue;
+            }
+            for (c, weight) in grid.stencil((pos[i*2], pos[i*2+1])) {
+                solid[c] += weight*area[i];
+            }
+        }
+        for c in 0..width*height {
+            let fraction = f32::min(solid[c]/(cell.0*cell.1), MAX_SOLID_FRACTION);
+            grid.porosity[c] = FluidGrid::porosity_3d(fraction);
+            let center = (grid.origin.0 + (c%width) as f32*cell.0 + 0.5*cell.0, grid.origin.1 + (c/width) as f32*cell.1 + 0.5*cell.1);
+            let velocity = fluid.velocity(center);
+            grid.velocity[c*2] = velocity.0;
+            grid.velocity[c*2+1] = velocity.1;
+            // the flow is a superficial velocity, it has to speed up to squeeze through the pores
+            if fluid.flow == FluidFlow::Uniform {
+                grid.velocity[c*2] /= grid.porosity[c];
+                grid.velocity[c*2+1] /= grid.porosity[c];
+            }
+        }
+        return grid;
+    }
+
+    // Discs fill a plane far better than spheres fill space, Hoomans et al. (1996) map the area fraction onto a porosity the 3D correlations make sense with.
+    fn porosity_3d(solid_fraction: f32) -> f32 {
+        let pi = std::f32::consts::PI;
+        return 1.0 - 2.0/(pi*3.0_f32.sqrt()).sqrt()*solid_fraction.powf(1.5);
+    }
+
+    // The 4 cells whose centers surround p and their bilinear weights, clamped at the edges of the grid.
+    fn stencil(&self, p: (f32, f32)) -> [(usize, f32); 4] {
+        let x = ((p.0 - self.origin.0)/self.cell.0 - 0.5).max(0.0).min((self.width - 1) as f32);
+        let y = ((p.1 - self.origin.1)/self.cell.1 - 0.5).max(0.0).min((self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        return [
+            (y0*self.width + x0, (1.0 - fx)*(1.0 - fy)),
+            (y0*self.width + x1, fx*(1.0 - fy)),
+            (y1*self.width + x0, (1.0 - fx)*fy),
+            (y1*self.width + x1, fx*fy),
+        ];
+    }
+
+    // porosity, fluid velocity x and y at p
+    pub fn sample(&self, p: (f32, f32)) -> (f32, f32, f32) {
+        let mut sample = (0.0, 0.0, 0.0);
+        for (c, weight) in self.stencil(p) {
+            sample.0 += weight*self.porosity[c];
+            sample.1 += weight*self.velocity[c*2];
+            sample.2 += weight*self.velocity[c*2+1];
+        }
+        return sample;
+    }
+
+    // Start and end of an arrow per cell for the render overlay, the fastest cell's arrow is as long as a cell is wide.
+    pub fn overlay(&self) -> Vec<f32> {
+        let mut fastest = 0.0;
+        for c in 0..self.width*self.height {
+            fastest = f32::max(fastest, (self.velocity[c*2]*self.velocity[c*2] + self.velocity[c*2+1]*self.velocity[c*2+1]).sqrt());
+        }
+        let scale = if fastest > 0.0 { f32::min(self.cell.0, self.cell.1)/fastest } else { 0.0 };
+        let mut arrows = vec![0.0; self.width*self.height*4];
+        for c in 0..self.width*self.height {
+            let center = (self.origin.0 + (c%self.width) as f32*self.cell.0 + 0.5*self.cell.0, self.origin.1 + (c/self.width) as f32*self.cell.1 + 0.5*self.cell.1);
+            arrows[c*4] = center.0;
+            arrows[c*4+1] = center.1;
+            arrows[c*4+2] = center.0 + scale*self.velocity[c*2];
+            arrows[c*4+3] = center.1 + scale*self.velocity[c*2+1];
+        }
+        return arrows;
+    }
+}
+
+// Per particle fluid velocity x, y and drag rate, the drag acceleration is rate*(fluid velocity - particle velocity).
+// The rate is per unit mass (1/s) and held over the frame, store_forces caps it at 1/dt so the drag can't push a particle past the fluid.
+pub fn drag(fluid: &Fluid, bounds: (f32, f32), pos: &[f32], vel: &[f32], area: &[f32], density: &[f32]) -> Vec<f32> {
+    let grid = FluidGrid::new(fluid, bounds, pos, area);
+    let mut drag = vec![0.0; area.len()*3];
+    for i in 0..area.len() {
+        if !(area[i] > 0.0) || !(density[i] > 0.0) || !pos[i*2].is_finite() || !pos[i*2+1].is_finite() {
+            continue;
+        }
+        let (porosity, u_x, u_y) = grid.sample((pos[i*2], pos[i*2+1]));
+        let slip = ((u_x - vel[i*2]).powi(2) + (u_y - vel[i*2+1]).powi(2)).sqrt();
+        let diameter = 2.0*(area[i]/std::f32::consts::PI).sqrt();
+        drag[i*3] = u_x;
+        drag[i*3+1] = u_y;
+        drag[i*3+2] = drag_rate(fluid, porosity, slip, diameter, density[i]);
+    }
+    return drag;
+}
+
+// Drag force over the sphere's mass and the slip speed.
+// Written with the drag coefficient times the slip speed so a particle moving with the fluid doesn't divide by zero.
+fn drag_rate(fluid: &Fluid, porosity: f32, slip: f32, diameter: f32, density: f32) -> f32 {
+    if !(fluid.viscosity > 0.0) || !(fluid.density > 0.0) || !(diameter > 0.0) {
+        return 0.0;
+    }
+    let reynolds = fluid.density*porosity*diameter*slip/fluid.viscosity;
+    return match fluid.drag {
+        // Di Felice (1994), Cd = (0.63 + 4.8/sqrt(Re))^2 and a porosity function eps^-chi
+        DragModel::Di_Felice => {
+            let cd_slip = (0.63*slip.sqrt() + 4.8*(fluid.viscosity/(fluid.density*porosity*diameter)).sqrt()).powi(2);
+            let chi = 3.7 - 0.65*(-0.5*(1.5 - reynolds.max(1e-12).log10()).powi(2)).exp();
+            0.75*cd_slip*fluid.density*porosity.powf(2.0 - chi)/(density*diameter)
+        },
+        // Gidaspow, Ergun in dense packing and Wen-Yu once it opens up past 0.8
+        DragModel::Ergun => {
+            if porosity < 0.8 {
+                (150.0*(1.0 - porosity)*fluid.viscosity/(porosity*diameter*diameter) + 1.75*fluid.density*slip/diameter)/density
+            } else {
+                let mut cd_slip = 0.44*slip;
+                if reynolds < 1000.0 {
+                    cd_slip = 24.0*fluid.viscosity/(fluid.density*porosity*diameter)*(1.0 + 0.15*reynolds.powf(0.687));
+                }
+                0.75*cd_slip*porosity*fluid.density*porosity.powf(-2.65)/(density*diameter)
+            }
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    fn uniform(drag: DragModel, speed: f32, viscosity: f32) -> Fluid {
+        let mut fluid = Settings::new().fluid;
+        fluid.flow = FluidFlow::Uniform;
+        fluid.drag = drag;
+        fluid.density = 1.0;
+        fluid.viscosity = viscosity;
+        fluid.velocity_x = speed;
+        fluid.velocity_y = 0.0;
+        return fluid;
+    }
+
+    // one small particle at rest in a big box, so it hardly fills its cells
+    fn lone_particle(fluid: &Fluid, diameter: f32, density: f32) -> Vec<f32> {
+        let area = std::f32::consts::PI*0.25*diameter*diameter;
+        return drag(fluid, (1.0, 1.0), &[0.1, -0.2], &[0.0, 0.0], &[area], &[density]);
+    }
+
+    #[test]
+    fn slow_flow_past_a_lone_particle_is_stokes_drag() {
+        let (diameter, density, viscosity) = (0.001, 2.0, 1.0);
+        // Re = 1e-3
+        let fluid = uniform(DragModel::Ergun, 1.0, viscosity);
+        let drag = lone_particle(&fluid, diameter, density);
+        let stokes = 18.0*viscosity/(density*diameter*diameter);
+        assert!((drag[0] - 1.0).abs() < 1e-3, "fluid velocity {}", drag[0]);
+        assert!((drag[2]/stokes - 1.0).abs() < 1e-2, "{} vs {}", drag[2], stokes);
+        // Di Felice's drag coefficient tends to 4.8^2/Re = 23/Re rather than 24/Re, a few percent under
+        let fluid = uniform(DragModel::Di_Felice, 1.0, viscosity);
+        let drag = lone_particle(&fluid, diameter, density);
+        assert!((drag[2]/(stokes*23.04/24.0) - 1.0).abs() < 1e-2, "{} vs {}", drag[2], stokes);
+    }
+
+    #[test]
+    fn fast_flow_past_a_lone_particle_is_newton_drag() {
+        let (diameter, density, speed) = (0.01, 2.0, 10.0);
+        // Re = 1e5, Cd = 0.44
+        let fluid = uniform(DragModel::Ergun, speed, 1e-6);
+        let drag = lone_particle(&fluid, diameter, density);
+        let newton = 0.75*0.44*speed/(density*diameter);
+        assert!((drag[2]/newton - 1.0).abs() < 1e-2, "{} vs {}", drag[2], newton);
+    }
+
+    #[test]
+    fn no_slip_stays_finite_and_no_viscosity_has_no_drag() {
+        let fluid = uniform(DragModel::Di_Felice, 1.0, 0.01);
+        let drag = drag(&fluid, (1.0, 1.0), &[0.1, -0.2], &[1.0, 0.0], &[1e-6], &[2.0]);
+        assert!(drag[2].is_finite() && drag[2] > 0.0);
+        let fluid = uniform(DragModel::Ergun, 1.0, 0.0);
+        assert_eq!(lone_particle(&fluid, 0.001, 2.0)[2], 0.0);
+    }
+
+    #[test]
+    fn porosity_stays_in_range() {
+        assert_eq!(FluidGrid::porosity_3d(0.0), 1.0);
+        assert!(FluidGrid::porosity_3d(MAX_SOLID_FRACTION) > 0.0);
+        // a heap of particles far denser than discs can pack, and some empty cells around it
+        let fluid = uniform(DragModel::Ergun, 1.0, 0.01);
+        let mut pos = vec![];
+        let mut area = vec![];
+        for i in 0..400 {
+            pos.extend_from_slice(&[0.01*(i % 20) as f32, 0.01*(i / 20) as f32]);
+            area.push(0.001);
+        }
+        let grid = FluidGrid::new(&fluid, (1.0, 1.0), &pos, &area);
+        assert!(grid.porosity.iter().all(|&p| p > 0.0 && p <= 1.0));
+        assert!(grid.porosity.iter().any(|&p| p < 0.5));
+        assert!(grid.porosity.iter().any(|&p| p == 1.0));
+        // the superficial flow speeds up through the pores
+        assert!(grid.velocity.chunks(2).zip(&grid.porosity).all(|(v, p)| (v[0]*p - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn stencil_weights_share_out_everything() {
+        let fluid = uniform(DragModel::Ergun, 1.0, 0.01);
+        let grid = FluidGrid::new(&fluid, (1.0, 0.5), &[], &[]);
+        for p in [(0.0, 0.0), (0.33, -0.21), (-1.0, -0.5), (1.0, 0.5), (0.99, -0.49), (5.0, 5.0)] {
+            let weights = grid.stencil(p);
+            let total: f32 = weights.iter().map(|w| w.1).sum();
+            assert!((total - 1.0).abs() < 1e-6, "{:?} shares out {}", p, total);
+            assert!(weights.iter().all(|&(c, w)| c < grid.width*grid.height && w >= 0.0));
+        }
+        // on a cell center it all goes to that cell
+        let center = (-1.0 + 2.5*grid.cell.0, -0.5 + 3.5*grid.cell.1);
+        let weights = grid.stencil(center);
+        let cell = 3*grid.width + 2;
+        assert!(weights.iter().all(|&(c, w)| if c == cell { (w - 1.0).abs() < 1e-5 } else { w.abs() < 1e-5 }));
+    }
+
+    #[test]
+    fn particle_area_is_conserved() {
+        let fluid = uniform(DragModel::Ergun, 1.0, 0.01);
+        let pos = [0.123, 0.456, -0.7, 0.2, 0.95, -0.95];
+        let area = [0.0004, 0.0002, 0.0003];
+        let grid = FluidGrid::new(&fluid, (1.0, 1.0), &pos, &area);
+        // undo the porosity mapping to get each cell's solid fraction back
+        let scale = 2.0/(std::f32::consts::PI*3.0_f32.sqrt()).sqrt();
+        let solid: f32 = grid.porosity.iter().map(|p| ((1.0 - p)/scale).powf(2.0/3.0)*grid.cell.0*grid.cell.1).sum();
+        assert!((solid - area.iter().sum::<f32>()).abs() < 1e-5, "{}", solid);
+    }
+}
@@ -0,0 +1,187 @@
+// Uniform-grid broadphase, built with a counting sort over cells followed by a prefix sum.
+// Grid.wgsl builds the exact same structure on the GPU every step, this copy is used for bond creation and by the CPU stepper.
+
+pub const MAX_GRID_DIM: usize = 1024;
+
+pub struct Grid {
+    pub cell_size: f32,
+    pub origin: (f32, f32),
+    pub width: usize,
+    pub height: usize,
+    pub cell_starts: Vec<usize>,
+    pub sorted: Vec<usize>,
+}
+
+// Cell size has to be at least the largest interaction distance so that every partner is in one of the 9 surrounding cells.
+// The grid is clamped to MAX_GRID_DIM cells per side, in which case the cells just get bigger.
+pub fn dimensions(hor_bound: f32, vert_bound: f32, min_cell_size: f32) -> (f32, usize, usize) {
+    let mut cell_size = min_cell_size.max(2.0*hor_bound/MAX_GRID_DIM as f32).max(2.0*vert_bound/MAX_GRID_DIM as f32);
+    if cell_size.is_nan() || cell_size <= 0.0 {
+        cell_size = 1.0;
+    }
+    let width = ((2.0*hor_bound/cell_size).ceil() as usize).clamp(1, MAX_GRID_DIM);
+    let height = ((2.0*vert_bound/cell_size).ceil() as usize).clamp(1, MAX_GRID_DIM);
+    return (cell_size, width, height);
+}
+
+impl Grid {
+    pub fn new(pos: &[f32], cell_size: f32, origin: (f32, f32), width: usize, height: usize) -> Self {
+        let mut grid = Grid {
+            cell_size,
+            origin,
+            width,
+            height,
+            cell_starts: vec![0; width*height + 1],
+            sorted: vec![0; pos.len()/2],
+        };
+
+        // Count
+        let mut counts = vec![0; width*height];
+        for i in 0..pos.len()/2 {
+            counts[grid.cell_index(pos[i*2], pos[i*2+1])] += 1;
+        }
+
+        // Scan
+        for c in 0..counts.len() {
+            grid.cell_starts[c+1] = grid.cell_starts[c] + counts[c];
+            counts[c] = grid.cell_starts[c];
+        }
+
+        // Sort
+        for i in 0..pos.len()/2 {
+            let c = grid.cell_index(pos[i*2], pos[i*2+1]);
+            grid.sorted[counts[c]] = i;
+            counts[c] += 1;
+        }
+
+        return grid;
+    }
+
+    // Grid covering the bounding box of the given positions, used during setup before the walls matter.
+    pub fn around(pos: &[f32], cell_size: f32) -> Self {
+        let mut min = (f32::MAX, f32::MAX);
+        let mut max = (f32::MIN, f32::MIN);
+        for i in 0..pos.len()/2 {
+            min = (min.0.min(pos[i*2]), min.1.min(pos[i*2+1]));
+            max = (max.0.max(pos[i*2]), max.1.max(pos[i*2+1]));
+        }
+        if pos.len() < 2 {
+            min = (0.0, 0.0);
+            max = (0.0, 0.0);
+        }
+        let half = ((max.0 - min.0)/2.0, (max.1 - min.1)/2.0);
+        let (cell_size, width, height) = dimensions(half.0, half.1, cell_size);
+        return Grid::new(pos, cell_size, min, width, height);
+    }
+
+    pub fn cell(&self, x: f32, y: f32) -> (usize, usize) {
+        let cx = ((x - self.origin.0)/self.cell_size).floor().clamp(0.0, (self.width - 1) as f32);
+        let cy = ((y - self.origin.1)/self.cell_size).floor().clamp(0.0, (self.height - 1) as f32);
+        return (cx as usize, cy as usize);
+    }
+
+    pub fn cell_index(&self, x: f32, y: f32) -> usize {
+        let (cx, cy) = self.cell(x, y);
+        return cy*self.width + cx;
+    }
+
+    // Every particle in the cells covering the box from low to high and one cell more all round, the cells platens() in 2D_Simulation.wgsl visits.
+    pub fn around_box(&self, low: (f32, f32), high: (f32, f32)) -> Vec<usize> {
+        let (first, last) = (self.cell(low.0, low.1), self.cell(high.0, high.1));
+        let mut found = vec![];
+        for ny in first.1.saturating_sub(1)..=(last.1 + 1).min(self.height - 1) {
+            for nx in first.0.saturating_sub(1)..=(last.0 + 1).min(self.width - 1) {
+                let c = ny*self.width + nx;
+                found.extend_from_slice(&self.sorted[self.cell_starts[c]..self.cell_starts[c+1]]);
+            }
+        }
+        return found;
+    }
+
+    // Every particle in the 3x3 block of cells around (x, y), in the same order the shader visits them.
+    pub fn neighbors(&self, x: f32, y: f32) -> Vec<usize> {
+        return self.around_box((x, y), (x, y));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // scattered over a box of half width 1 and half height 0.5, a few outside it to land on the clamped edge cells
+    fn scatter(count: usize) -> Vec<f32> {
+        let mut seed = 987u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            return (seed >> 8) as f32/(1 << 24) as f32;
+        };
+        let mut pos = vec![];
+        for _ in 0..count {
+            pos.extend_from_slice(&[2.4*next() - 1.2, 1.2*next() - 0.6]);
+        }
+        return pos;
+    }
+
+    // everything within reach of a particle has to be in the 3x3 cells around it
+    fn check_against_brute_force(grid: &Grid, pos: &[f32], reach: f32) {
+        let count = pos.len()/2;
+        let mut sorted = grid.sorted.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..count).collect::<Vec<_>>());
+        for i in 0..count {
+            let found = grid.neighbors(pos[i*2], pos[i*2+1]);
+            let mut unique = found.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), found.len(), "particle {i} found someone twice");
+            for j in 0..count {
+                let d = ((pos[j*2] - pos[i*2]).powi(2) + (pos[j*2+1] - pos[i*2+1]).powi(2)).sqrt();
+                if d <= reach {
+                    assert!(found.contains(&j), "particle {i} missed {j} at {d}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_cover_everything_in_reach() {
+        let pos = scatter(500);
+        let reach = 0.07;
+        let (cell_size, width, height) = dimensions(1.0, 0.5, reach);
+        assert!(cell_size >= reach);
+        let grid = Grid::new(&pos, cell_size, (-1.0, -0.5), width, height);
+        check_against_brute_force(&grid, &pos, reach);
+        let grid = Grid::around(&pos, reach);
+        check_against_brute_force(&grid, &pos, reach);
+    }
+
+    #[test]
+    fn around_box_reaches_everything_near_a_segment() {
+        let pos = scatter(500);
+        let reach = 0.07;
+        let (cell_size, width, height) = dimensions(1.0, 0.5, reach);
+        let grid = Grid::new(&pos, cell_size, (-1.0, -0.5), width, height);
+        // a diagonal, a flat one along the bottom and one sticking out of the box
+        for (start, end) in [((-0.9, -0.4), (0.3, 0.45)), ((-1.0, -0.5), (1.0, -0.5)), ((0.8, 0.0), (1.5, 0.9))] {
+            let found = grid.around_box((f32::min(start.0, end.0), f32::min(start.1, end.1)), (f32::max(start.0, end.0), f32::max(start.1, end.1)));
+            let seg: (f32, f32) = (end.0 - start.0, end.1 - start.1);
+            for i in 0..pos.len()/2 {
+                let t = (((pos[i*2] - start.0)*seg.0 + (pos[i*2+1] - start.1)*seg.1)/(seg.0*seg.0 + seg.1*seg.1)).clamp(0.0, 1.0);
+                let d = ((pos[i*2] - start.0 - t*seg.0).powi(2) + (pos[i*2+1] - start.1 - t*seg.1).powi(2)).sqrt();
+                if d <= reach {
+                    assert!(found.contains(&i), "{:?} to {:?} missed {i} at {d}", start, end);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dimensions_stay_inside_the_limit() {
+        let (cell_size, width, height) = dimensions(10.0, 1.0, 1e-6);
+        assert_eq!(width, MAX_GRID_DIM);
+        assert!(height <= MAX_GRID_DIM && cell_size*width as f32 >= 20.0);
+        assert_eq!(dimensions(0.0, 0.0, 0.0), (1.0, 1, 1));
+        let grid = Grid::around(&[], 0.1);
+        assert_eq!(grid.neighbors(0.0, 0.0), Vec::<usize>::new());
+    }
+}
@@ -7,6 +7,12 @@ pub mod wgpu_prog;
 pub mod settings;
 pub mod setup;
 pub mod state;
+pub mod cpu_prog;
+pub mod grid;
+pub mod barnes_hut;
+pub mod fluid;
+pub mod emitter;
+pub mod library;
 use std::ptr::null;
 use winit::dpi::PhysicalSize;
 use log::*;
@@ -0,0 +1,206 @@
+// Material library files, TOML with one [[material]] table per material:
+//
+//     [[material]]
+//     name = "Glass"
+//     color = [0.8, 0.9, 1.0]
+//     density = 2.5
+//     youngs_modulus = 70000.0
+//     poisson_ratio = 0.22
+//     restitution = 0.9
+//     friction = 0.4
+//
+// Only the name is needed, anything left out comes from the default material (see Settings::new).
+// The contact stiffnesses are derived from Young's modulus and the Poisson ratio (Settings::derive_stiffness)
+// unless normal_stiffness and shear_stiffness are given, save always writes them so a library comes back the same.
+// Pairs aren't kept, loading a material re-derives its pairs with every other one (Settings::derive_pair and derive_pair_cohesion).
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use crate::settings::{Settings, CohesionModel};
+
+// Where each plain number goes in a material record, see Settings::materials
+const FIELDS: [(&str, usize); 13] = [
+    ("density", 3),
+    ("youngs_modulus", 6),
+    ("poisson_ratio", 7),
+    ("friction", 8),
+    ("restitution", 16),
+    ("normal_stiffness", 4),
+    ("shear_stiffness", 5),
+    ("surface_energy", 10),
+    ("liquid_volume", 11),
+    ("surface_tension", 12),
+    ("contact_angle", 13),
+    ("conductivity", 14),
+    ("specific_heat", 15),
+];
+
+const COHESION_MODELS: [(&str, CohesionModel); 4] = [
+    ("none", CohesionModel::None),
+    ("jkr", CohesionModel::JKR),
+    ("dmt", CohesionModel::DMT),
+    ("capillary", CohesionModel::Capillary),
+];
+
+// Replaces the materials named in the library and adds the rest on the end, returns how many it read.
+// Nothing changes if any of them is malformed.
+pub fn load(settings: &mut Settings, path: PathBuf) -> io::Result<usize> {
+    let text = fs::read_to_string(&path)?;
+    let table: toml::Table = text.parse().map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    let entries = match table.get("material") {
+        Some(toml::Value::Array(entries)) => entries,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "A material library needs [[material]] tables")),
+    };
+    let mut materials = vec![];
+    for entry in entries {
+        materials.push(read_material(settings, entry)?);
+    }
+    for (name, material) in &materials {
+        let i = match settings.material_names.iter().position(|existing| existing == name) {
+            Some(i) => i,
+            None => {
+                settings.add_material();
+                settings.material_count() - 1
+            },
+        };
+        let m = settings.material_size;
+        settings.materials[i*m..(i + 1)*m].copy_from_slice(material);
+        settings.material_names[i] = name.clone();
+        settings.derive_material_pairs(i);
+        settings.derive_material_cohesion(i);
+    }
+    return Ok(materials.len());
+}
+
+pub fn save(settings: &Settings, path: PathBuf) -> io::Result<()> {
+    let m = settings.material_size;
+    let mut entries = vec![];
+    for i in 0..settings.material_count() {
+        let material = &settings.materials[i*m..(i + 1)*m];
+        let mut entry = toml::Table::new();
+        entry.insert("name".to_string(), toml::Value::String(settings.material_names[i].clone()));
+        entry.insert("color".to_string(), toml::Value::Array(material[0..3].iter().map(|&c| float(c)).collect()));
+        for (key, index) in FIELDS {
+            entry.insert(key.to_string(), float(material[index]));
+        }
+        let cohesion = CohesionModel::from_material(material[9]);
+        let (model, _) = COHESION_MODELS.iter().find(|(_, model)| *model == cohesion).unwrap();
+        entry.insert("cohesion".to_string(), toml::Value::String(model.to_string()));
+        entries.push(toml::Value::Table(entry));
+    }
+    let mut table = toml::Table::new();
+    table.insert("material".to_string(), toml::Value::Array(entries));
+    let text = toml::to_string(&table).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    fs::write(path, text)?;
+    return Ok(());
+}
+
+// A named material record, filled in from the default material where the entry leaves something out
+fn read_material(settings: &Settings, entry: &toml::Value) -> io::Result<(String, Vec<f32>)> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let name = match entry.get("name") {
+        Some(toml::Value::String(name)) => name.clone(),
+        _ => return Err(invalid("Every material needs a name".to_string())),
+    };
+    let mut material = Settings::new().materials;
+    if let Some(color) = entry.get("color") {
+        let color = color.as_array().filter(|color| color.len() == 3).ok_or(invalid(format!("{name}: color should be [red, green, blue]")))?;
+        for k in 0..3 {
+            material[k] = number(&color[k]).ok_or(invalid(format!("{name}: color should be [red, green, blue]")))?;
+        }
+    }
+    for (key, index) in FIELDS {
+        if let Some(value) = entry.get(key) {
+            material[index] = number(value).ok_or(invalid(format!("{name}: {key} should be a number")))?;
+        }
+    }
+    if let Some(value) = entry.get("cohesion") {
+        let model = value.as_str().and_then(|model| COHESION_MODELS.iter().find(|(key, _)| key.eq_ignore_ascii_case(model)));
+        let (_, model) = model.ok_or(invalid(format!("{name}: cohesion should be none, jkr, dmt or capillary")))?;
+        material[9] = *model as i32 as f32;
+    }
+    if entry.get("normal_stiffness").is_none() || entry.get("shear_stiffness").is_none() {
+        let (normal_stiffness, shear_stiffness) = Settings::stiffness(material[6], material[7]);
+        if entry.get("normal_stiffness").is_none() {
+            material[4] = normal_stiffness;
+        }
+        if entry.get("shear_stiffness").is_none() {
+            material[5] = shear_stiffness;
+        }
+    }
+    material.resize(settings.material_size, 0.0);
+    return Ok((name, material));
+}
+
+// Goes through the shortest decimal that reads back as the same f32, so 0.3 is written as 0.3 and not 0.30000001192092896
+fn float(value: f32) -> toml::Value {
+    return toml::Value::Float(value.to_string().parse().unwrap());
+}
+
+// TOML keeps 2 and 2.0 apart, either will do here
+fn number(value: &toml::Value) -> Option<f32> {
+    return match value {
+        toml::Value::Float(value) => Some(*value as f32),
+        toml::Value::Integer(value) => Some(*value as f32),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp(name: &str) -> PathBuf {
+        return std::env::temp_dir().join(format!("library_{}_{}.toml", std::process::id(), name));
+    }
+
+    #[test]
+    fn saved_library_loads_back_the_same() {
+        let mut settings = Settings::new();
+        settings.add_material();
+        let m = settings.material_size;
+        settings.material_names[1] = "Wet Sand".to_string();
+        settings.materials[m..2*m].copy_from_slice(&[0.8, 0.7, 0.3, 1.6, 1234.5, 678.9, 5000.0, 0.3, 0.6, 3.0, 0.0, 0.0001, 0.072, 20.0, 0.25, 800.0, 0.4]);
+        settings.derive_material_pairs(1);
+        settings.derive_material_cohesion(1);
+        let path = temp("round_trip");
+        save(&settings, path.clone()).unwrap();
+        let mut loaded = Settings::new();
+        let count = load(&mut loaded, path.clone()).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(loaded.material_names, settings.material_names);
+        assert_eq!(loaded.materials, settings.materials);
+        assert_eq!(loaded.pair(0, 1), settings.pair(0, 1));
+    }
+
+    #[test]
+    fn left_out_fields_come_from_the_default_material() {
+        let path = temp("defaults");
+        fs::write(&path, "[[material]]\nname = \"Rubber\"\nyoungs_modulus = 10\ncohesion = \"JKR\"\n").unwrap();
+        let mut settings = Settings::new();
+        load(&mut settings, path.clone()).unwrap();
+        fs::remove_file(path).unwrap();
+        let m = settings.material_size;
+        let rubber = &settings.materials[m..2*m];
+        let default = &Settings::new().materials;
+        assert_eq!(settings.material_names[1], "Rubber");
+        assert_eq!(rubber[3], default[3]);
+        assert_eq!(rubber[6], 10.0);
+        assert_eq!(CohesionModel::from_material(rubber[9]), CohesionModel::JKR);
+        assert_eq!((rubber[4], rubber[5]), Settings::stiffness(10.0, default[7]));
+    }
+
+    #[test]
+    fn a_bad_entry_changes_nothing() {
+        let path = temp("bad");
+        fs::write(&path, "[[material]]\nname = \"Fine\"\n\n[[material]]\nname = \"Broken\"\ndensity = \"heavy\"\n").unwrap();
+        let mut settings = Settings::new();
+        let before = settings.materials.clone();
+        assert!(load(&mut settings, path.clone()).is_err());
+        fs::remove_file(path).unwrap();
+        assert_eq!(settings.materials, before);
+        assert_eq!(settings.material_count(), 1);
+    }
+}
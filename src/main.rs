@@ -6,9 +6,83 @@ pub mod wgpu_prog;
 pub mod settings;
 pub mod setup;
 pub mod state;
+pub mod cpu_prog;
+pub mod grid;
+pub mod barnes_hut;
+pub mod fluid;
+pub mod emitter;
+pub mod library;
 
 pub  fn main(){
     env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "--headless" {
+        headless(&args[2..]);
+        return;
+    }
     let mut client = async_std::task::block_on(client::Client::new());
     client.resize(client.canvas.size);
+}
+
+// No window and no GPU adapter, steps a saved state on the CPU and writes it back out.
+// Usage: --headless <input.bin> <generations> [output.bin] [bond_breaks.csv]
+fn headless(args: &[String]) {
+    if args.len() < 2 {
+        headless_error("missing arguments");
+    }
+    let input = std::path::PathBuf::from(&args[0]);
+    let generations: i32 = match args[1].parse() {
+        Ok(generations) => generations,
+        Err(_) => headless_error(&format!("generations should be a whole number, not {}", args[1])),
+    };
+    let output = match args.get(2) {
+        Some(path) => std::path::PathBuf::from(path),
+        None => input.clone(),
+    };
+
+    let mut settings = settings::Settings::new();
+    let mut state = state::State::from_settings(&mut settings);
+    if let Err(e) = state.load_from_file(input.clone()) {
+        headless_error(&format!("couldn't read {}: {}", input.display(), e));
+    }
+    if let Err(e) = state::root_as_state(&state.flatbuffer) {
+        headless_error(&format!("{} isn't a saved state: {}", input.display(), e.to_string().trim_end()));
+    }
+    state.load();
+
+    let mut prog = cpu_prog::CPUComputeProg::from_state(state, &mut settings);
+    if settings.timestep() > settings.critical_delta_time {
+        eprintln!("Warning: timestep {:e} s is above the critical timestep {:e} s", settings.timestep(), settings.critical_delta_time);
+    }
+    for _ in 0..generations {
+        prog.step(&settings);
+    }
+    println!("Stepped {} particles for {} generations", prog.state.p_count, prog.generation);
+    for w in 0..prog.state.walls.len()/settings.wall_size {
+        let wall = &prog.state.walls[w*settings.wall_size..(w + 1)*settings.wall_size];
+        println!("Wall {}: load {:e}, shear {:e}, speed {:e}", w + 1, wall[12], wall[13], wall[11]);
+    }
+    println!("Bond breakages: {}", prog.state.bond_breaks);
+    if prog.overflows != [0, 0] {
+        eprintln!("Warning: up to {} particles hit the contact cap ({}) and {} the bond cap ({}) in a step", prog.overflows[0], settings.max_contacts, prog.overflows[1], settings.max_bonds);
+    }
+    let energy = &prog.state.energy;
+    println!("Energy: kinetic {:e}, rotational {:e}, strain {:e}, gravity {:e}", energy[0], energy[1], energy[2], energy[3]);
+    println!("Dissipated: friction {:e}, damping {:e}, work done by forces {:e}", energy[4], energy[5], energy[6]);
+    if let Some(path) = args.get(3) {
+        if let Err(e) = prog.state.export_bond_events(std::path::PathBuf::from(path)) {
+            headless_error(&format!("couldn't write bond breakages to {}: {}", path, e));
+        }
+    }
+
+    prog.state.save();
+    if let Err(e) = prog.state.save_to_file(output.clone()) {
+        headless_error(&format!("couldn't write {}: {}", output.display(), e));
+    }
+}
+
+fn headless_error(message: &str) -> ! {
+    eprintln!("Error: {}", message);
+    eprintln!("Usage: --headless <input.bin> <generations> [output.bin] [bond_breaks.csv]");
+    std::process::exit(1);
 }
\ No newline at end of file
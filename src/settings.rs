@@ -5,6 +5,9 @@ use std::*;
 
 use native_dialog::{FileDialog, MessageDialog, MessageType};
 
+use crate::grid;
+use crate::library;
+use crate::state::{WALL_SIZE, EMITTER_SIZE, SINK_SIZE, PAIR_SIZE};
 use crate::wgpu_structs::Uniform;
 
 pub struct Menu {
@@ -35,6 +38,141 @@ pub struct Properties {
     pub y_fixity: bool,
     pub rot_fixity: bool,
     pub radius: f32,
+    pub set_temperature: bool,
+    pub temperature: f32,
+}
+
+// Base excitation, a sinusoid or an accelerogram, in g along angle (degrees from +x).
+// Gravity shakes the frame the particles sit in, Walls moves the box and segment walls through it instead.
+pub struct Excitation {
+    pub target: i32,
+    pub target_enum: ExcitationTarget,
+    pub source: i32,
+    pub source_enum: ExcitationSource,
+    pub amplitude: f32,
+    pub frequency: f32,
+    pub angle: f32,
+    // time, acceleration, then its integrals velocity and displacement, see Excitation::load_accelerogram
+    pub accelerogram: Vec<f32>,
+    pub accelerogram_file: std::path::PathBuf,
+    // how far the walls have been carried so far, and how far they go each step this frame
+    pub carried: [f32; 2],
+    pub wall_step: [f32; 2],
+}
+
+impl Excitation {
+    // Reads a two column text file of time (s) and acceleration (g), comma or whitespace separated.
+    // Lines that don't start with two numbers (headers, comments) are skipped.
+    pub fn load_accelerogram(&mut self, path: std::path::PathBuf) -> std::io::Result<()> {
+        let text = fs::read_to_string(&path)?;
+        let mut record: Vec<f32> = vec![];
+        for line in text.lines() {
+            let mut columns = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|c| !c.is_empty());
+            let time = columns.next().and_then(|c| c.parse::<f32>().ok());
+            let acc = columns.next().and_then(|c| c.parse::<f32>().ok());
+            if let (Some(time), Some(acc)) = (time, acc) {
+                let (mut vel, mut disp) = (0.0, 0.0);
+                let n = record.len();
+                if n > 0 {
+                    let dt = time - record[n - 4];
+                    vel = record[n - 2] + 0.5*(record[n - 3] + acc)*dt;
+                    disp = record[n - 1] + 0.5*(record[n - 2] + vel)*dt;
+                }
+                record.extend_from_slice(&[time, acc, vel, disp]);
+            }
+        }
+        if record.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "An accelerogram needs at least two time, acceleration rows"));
+        }
+        self.accelerogram = record;
+        self.accelerogram_file = path;
+        return Ok(());
+    }
+
+    // Acceleration, velocity and displacement of the base at time, along the excitation direction.
+    // The sinusoid is a cosine so the base starts at rest and stays within a bounded distance of where it started.
+    // At 0 Hz it's a constant tilt, the acceleration with the base held still, so only the Gravity target sees it.
+    // Before the accelerogram starts the base is still, after it ends it stays where the record left it.
+    pub fn motion(&self, time: f32) -> (f32, f32, f32) {
+        let g = 9.81; // MTIF
+        if self.source == 0 {
+            let omega = 2.0*std::f32::consts::PI*self.frequency;
+            if omega == 0.0 {
+                return (self.amplitude*g, 0.0, 0.0);
+            }
+            let amplitude = self.amplitude*g;
+            return (amplitude*(omega*time).cos(), amplitude/omega*(omega*time).sin(), amplitude/(omega*omega)*(1.0 - (omega*time).cos()));
+        }
+        let record = &self.accelerogram;
+        let rows = record.len()/4;
+        if rows < 2 || time < record[0] {
+            return (0.0, 0.0, 0.0);
+        }
+        let last = (rows - 1)*4;
+        if time >= record[last] {
+            return (0.0, 0.0, record[last + 3]*g);
+        }
+        let mut i = 0;
+        while record[(i + 1)*4] <= time {
+            i += 1;
+        }
+        let (a, b) = (&record[i*4..i*4 + 4], &record[(i + 1)*4..(i + 1)*4 + 4]);
+        let t = (time - a[0])/(b[0] - a[0]);
+        return ((a[1] + (b[1] - a[1])*t)*g, (a[2] + (b[2] - a[2])*t)*g, (a[3] + (b[3] - a[3])*t)*g);
+    }
+
+    // Base acceleration, velocity and displacement as x, y pairs for the Settings uniform.
+    // Only the ones the target uses are filled in, the rest stay zero.
+    pub fn base_motion(&self, time: f32) -> [f32; 6] {
+        if self.target == 0 {
+            return [0.0; 6];
+        }
+        let (acc, vel, disp) = self.motion(time);
+        let dir = (self.angle.to_radians().cos(), self.angle.to_radians().sin());
+        if self.target == 1 {
+            return [acc*dir.0, acc*dir.1, 0.0, 0.0, 0.0, 0.0];
+        }
+        return [0.0, 0.0, vel*dir.0, vel*dir.1, disp*dir.0, disp*dir.1];
+    }
+
+    // Spreads what's left between where the walls have been carried and the base's displacement at time over the steps.
+    // Moving them by the displacement rather than stepping the velocity keeps them with the box however long the record runs,
+    // and with the excitation off they go back to where they started, same as the box.
+    pub fn carry_walls(&mut self, time: f32, steps: usize) {
+        let motion = self.base_motion(time);
+        for k in 0..2 {
+            self.wall_step[k] = (motion[4 + k] - self.carried[k])/steps.max(1) as f32;
+            self.carried[k] = motion[4 + k];
+        }
+    }
+}
+
+// Fluid the particles sit in, a prescribed flow over a coarse grid with porosity dependent drag and buoyancy, see fluid.rs.
+// Uniform is a superficial velocity (fluidization), it speeds up through the pores. Shear is u_x = shear_rate*y.
+pub struct Fluid {
+    pub flow: FluidFlow,
+    pub drag: DragModel,
+    pub density: f32,
+    pub viscosity: f32,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    pub shear_rate: f32,
+    // cells across and up the domain
+    pub grid_w: i32,
+    pub grid_h: i32,
+    // draws the fluid velocity per cell over the particles
+    pub overlay: bool,
+}
+
+impl Fluid {
+    // The flow before the particles get in its way, at p.
+    pub fn velocity(&self, p: (f32, f32)) -> (f32, f32) {
+        return match self.flow {
+            FluidFlow::Uniform => (self.velocity_x, self.velocity_y),
+            FluidFlow::Shear => (self.shear_rate*p.1, 0.0),
+            _ => (0.0, 0.0),
+        };
+    }
 }
 
 pub struct Data {
@@ -44,10 +182,13 @@ pub struct Data {
     pub y_vel_data: Vec<[f64; 2]>,
     pub rot_data: Vec<[f64; 2]>,
     pub rot_vel_data: Vec<[f64; 2]>,
-    pub data1: Vec<[f64; 2]>,
-    pub data2: Vec<[f64; 2]>,
-    pub data3: Vec<[f64; 2]>,
-    pub data4: Vec<[f64; 2]>,
+    pub stress_xx_data: Vec<[f64; 2]>,
+    pub stress_yy_data: Vec<[f64; 2]>,
+    pub stress_xy_data: Vec<[f64; 2]>,
+    pub mean_stress_data: Vec<[f64; 2]>,
+    pub temperature_data: Vec<[f64; 2]>,
+    // time, then the State::energy terms, kept whether or not anything is selected
+    pub energy_data: Vec<[f64; 8]>,
 }
 
 impl Data {
@@ -59,24 +200,65 @@ impl Data {
             y_vel_data: Vec::new(),
             rot_data: Vec::new(),
             rot_vel_data: Vec::new(),
-            data1: Vec::new(),
-            data2: Vec::new(),
-            data3: Vec::new(),
-            data4: Vec::new(),
+            stress_xx_data: Vec::new(),
+            stress_yy_data: Vec::new(),
+            stress_xy_data: Vec::new(),
+            mean_stress_data: Vec::new(),
+            temperature_data: Vec::new(),
+            energy_data: Vec::new(),
         };
     }
 
-    pub fn push(&mut self, timestamp: f64, datum: [f64; 10]) {
+    // Drops the selection's history but not the energy budget, which is for the whole system
+    pub fn clear_selection(&mut self) {
+        let energy_data = std::mem::take(&mut self.energy_data);
+        *self = Data::new();
+        self.energy_data = energy_data;
+    }
+
+    pub fn push_energy(&mut self, timestamp: f64, energy: &[f32]) {
+        let mut datum = [timestamp, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for i in 0..energy.len().min(7) {
+            datum[i+1] = energy[i] as f64;
+        }
+        self.energy_data.push(datum);
+    }
+
+    // Stacked kinetic, rotational, strain, gravity, friction and damping, each line is the running total up to that term.
+    // The last line is the starting energy plus the work done by forces, the top of the stack should follow it.
+    pub fn energy_lines(&self) -> Vec<(&'static str, Vec<[f64; 2]>)> {
+        let names = ["Kinetic", "Rotational", "Strain", "Gravity", "Friction", "Damping"];
+        let mut lines: Vec<(&'static str, Vec<[f64; 2]>)> = names.iter().map(|name| (*name, Vec::new())).collect();
+        let mut input = Vec::new();
+        if self.energy_data.is_empty() {
+            return lines;
+        }
+        let first = self.energy_data[0];
+        let initial = first[1..7].iter().sum::<f64>() - first[7];
+        for datum in &self.energy_data {
+            let mut total = 0.0;
+            for i in 0..names.len() {
+                total += datum[i+1];
+                lines[i].1.push([datum[0], total]);
+            }
+            input.push([datum[0], initial + datum[7]]);
+        }
+        lines.push(("Initial + Work", input));
+        return lines;
+    }
+
+    pub fn push(&mut self, timestamp: f64, datum: [f64; 11]) {
         self.x_pos_data.push([timestamp, datum[0]]);
         self.y_pos_data.push([timestamp, datum[1]]);
         self.x_vel_data.push([timestamp, datum[2]]);
         self.y_vel_data.push([timestamp, datum[3]]);
         self.rot_data.push([timestamp, datum[4]]);
         self.rot_vel_data.push([timestamp, datum[5]]);
-        self.data1.push([timestamp, datum[6]]);
-        self.data2.push([timestamp, datum[7]]);
-        self.data3.push([timestamp, datum[8]]);
-        self.data4.push([timestamp, datum[9]]);
+        self.stress_xx_data.push([timestamp, datum[6]]);
+        self.stress_yy_data.push([timestamp, datum[7]]);
+        self.stress_xy_data.push([timestamp, datum[8]]);
+        self.mean_stress_data.push([timestamp, datum[9]]);
+        self.temperature_data.push([timestamp, datum[10]]);
     }
 }
 
@@ -96,6 +278,11 @@ pub struct Settings {
     pub structure: Structure,
     pub grid_width: f32,
     pub variable_rad: bool,
+    // every particle a random convex polygon inscribed in its radius, see setup::polygons
+    pub particle_shape: ParticleShape,
+    pub polygon_sides: usize,
+    // 0 is a regular polygon, 1 scatters the corners as far as they'll go
+    pub polygon_irregularity: f32,
     pub settings_menu: bool,
     pub holeyness: f32,
     pub maintain_ar: bool,
@@ -103,15 +290,35 @@ pub struct Settings {
     pub vert_bound: f32,
     pub gravity: bool,
     pub planet_mode: bool,
-    pub gravity_acceleration: f32,
+    // in g, (0, -1) is normal gravity
+    pub gravity_x: f32,
+    pub gravity_y: f32,
+    // mutual gravitation between particles instead of the gravity vector, see barnes_hut.rs
+    pub self_gravity: bool,
+    // conduction through contacts, bonds and thermal walls, see conduct() in 2D_Simulation.wgsl
+    pub heat_conduction: bool,
+    // what new particles start at
+    pub initial_temperature: f32,
+    pub gravitational_constant: f32,
+    pub barnes_hut_theta: f32,
+    pub gravity_softening: f32,
+    pub excitation: Excitation,
+    pub fluid: Fluid,
+    // seconds simulated, the client keeps it up to date so the excitation can follow it
+    pub sim_time: f32,
     pub bonds: i32,
     pub bondenum: BondType,
+    pub contact_model: i32,
+    pub contactenum: ContactModel,
     pub bond_tearing: bool,
     pub bond_force_limit: f32,
     pub stiffness: f32,
     pub collisions: bool,
     pub friction: bool,
     pub friction_coefficient: f32,
+    pub rolling_model: i32,
+    pub rollingenum: RollingResistance,
+    pub rolling_coefficient: f32,
     pub rotation: bool,
     pub linear_contact_bonds: bool,
     pub changed_collision_settings: bool,
@@ -119,25 +326,82 @@ pub struct Settings {
     pub circular_particles: bool,
     pub render_rot: bool,
     pub color_code_rot: bool,
+    // colors the particles from blue at temp_min to red at temp_max
+    pub color_code_temp: bool,
+    pub temp_min: f32,
+    pub temp_max: f32,
     pub colors: bool,
     pub random_colors: bool,
     pub render_bonds: bool,
     pub two_part: bool,
+    // [red, green, blue, density, normal stiffness, shear stiffness, youngs modulus, poisson ratio, friction coefficient,
+    //  cohesion model, surface energy, liquid volume, surface tension, contact angle (deg), conductivity, specific heat, restitution]
+    // see cohesion() and conduction() in 2D_Simulation.wgsl, and library.rs for how they're named in a library file
     pub materials: Vec<f32>,
     pub material_size: usize,
+    pub material_names: Vec<String>,
+    // material_count() squared pair records, row a column b is what a particle of a sees touching one of b, see derive_pair
+    pub material_pairs: Vec<f32>,
+    pub pair_size: usize,
+    // taken out in the Materials window, the particles made of them still need moving over (see State::remove_material)
+    pub removed_materials: Vec<usize>,
+    pub pair_property: PairProperty,
     pub materials_changed: bool,
     pub menu: Menu,
     pub current_file: std::path::PathBuf,
     pub load: bool,
     pub save: bool,
     pub regen_bonds: bool,
+    // discs of the clump template as x, y, radius in units of max_radius, see setup::clumps
+    pub clump_shape: ClumpShape,
+    pub clump_template: Vec<f32>,
+    pub clump_selection: bool,
+    pub release_clumps: bool,
     pub properties: Properties,
     pub set_properties: bool,
     pub data: Data,
     pub auto_size_plot: bool,
     pub plotted_prop: Property,
-    pub damping: f32,
-    pub bond_shear_limit: f32
+    pub force_scale: f32,
+    pub damping_model: i32,
+    pub damping_enum: DampingModel,
+    pub local_damping: f32,
+    pub contact_restitution: f32,
+    pub bond_shear_limit: f32,
+    pub bond_normal_stiffness: f32,
+    pub bond_shear_stiffness: f32,
+    pub bond_radius_multiplier: f32,
+    pub bond_tensile_strength: f32,
+    pub bond_cohesion: f32,
+    pub bond_friction_angle: f32,
+    pub coll_radius: f32,
+    pub coll_cell_size: f32,
+    pub coll_grid_w: usize,
+    pub coll_grid_h: usize,
+    pub delta_time: f32,
+    pub auto_delta_time: bool,
+    pub delta_time_safety: f32,
+    pub critical_delta_time: f32,
+    pub wall_elasticity: f32,
+    pub wall_rot_damping: f32,
+    pub boundary_x: i32,
+    pub boundary_x_enum: Boundary,
+    pub boundary_y: i32,
+    pub boundary_y_enum: Boundary,
+    pub shear_velocity: f32,
+    pub walls: Vec<f32>,
+    pub wall_size: usize,
+    pub walls_changed: bool,
+    // particle sources and drains, see emitter.rs
+    pub emitters: Vec<f32>,
+    pub emitter_size: usize,
+    pub sinks: Vec<f32>,
+    pub sink_size: usize,
+    pub bond_breaks: usize,
+    // the most particles that hit max_contacts and max_bonds in one step of the last frame, see State::update_state
+    pub overflows: [usize; 2],
+    pub export_bond_events: bool,
+    pub bond_event_file: std::path::PathBuf,
 }
 
 impl Settings {
@@ -164,15 +428,21 @@ impl Settings {
         let hor_bound = 1.333;
         let vert_bound = 1.0;
         let gravity = true;
-        let gravity_acceleration = 1.0;
+        let gravity_x = 0.0;
+        let gravity_y = -1.0;
         let bonds = 0;
         let bondenum = BondType::Unbonded;
+        let contact_model = 0;
+        let contactenum = ContactModel::Linear;
         let bond_tearing = false;
         let bond_force_limit = 0.5;
         let stiffness = 0.1;
         let collisions = true;
         let friction = true;
         let friction_coefficient = 0.5;
+        let rolling_model = 0;
+        let rollingenum = RollingResistance::None;
+        let rolling_coefficient = 0.1;
         let rotation = true;
         let linear_contact_bonds = true;
         let changed_collision_settings = false;
@@ -190,9 +460,20 @@ impl Settings {
             1.0,
             1.0,
             10.0,
-            0.25
+            0.25,
+            1000.0,
+            0.25,
+            0.5,
+            0.0,
+            0.1,
+            0.000001,
+            0.2,
+            20.0,
+            1.0,
+            1.0,
+            0.5
         ];
-        let material_size = 6;
+        let material_size = 17;
         let materials_changed = false; 
         let menu = Menu {
             render_settings: false,
@@ -207,7 +488,7 @@ impl Settings {
 
         let current_file = std::path::PathBuf::new();
 
-        Self {
+        let mut settings = Self {
             genPerFrame,
             particles,
             workgroups,
@@ -223,6 +504,9 @@ impl Settings {
             structure,
             grid_width,
             variable_rad,
+            particle_shape: ParticleShape::Disc,
+            polygon_sides: 6,
+            polygon_irregularity: 0.3,
             settings_menu,
             holeyness,
             maintain_ar,
@@ -230,15 +514,53 @@ impl Settings {
             vert_bound,
             gravity,
             planet_mode: false,
-            gravity_acceleration,
+            gravity_x,
+            gravity_y,
+            self_gravity: false,
+            heat_conduction: false,
+            initial_temperature: 20.0,
+            gravitational_constant: 1.0,
+            barnes_hut_theta: 0.5,
+            gravity_softening: 0.01,
+            excitation: Excitation {
+                target: 0,
+                target_enum: ExcitationTarget::None,
+                source: 0,
+                source_enum: ExcitationSource::Sinusoid,
+                amplitude: 0.1,
+                frequency: 2.0,
+                angle: 0.0,
+                accelerogram: vec![],
+                accelerogram_file: std::path::PathBuf::new(),
+                carried: [0.0; 2],
+                wall_step: [0.0; 2],
+            },
+            fluid: Fluid {
+                flow: FluidFlow::None,
+                drag: DragModel::Di_Felice,
+                density: 0.4,
+                viscosity: 0.001,
+                velocity_x: 0.0,
+                velocity_y: 0.5,
+                shear_rate: 1.0,
+                grid_w: 16,
+                grid_h: 12,
+                overlay: false,
+            },
+            sim_time: 0.0,
             bonds,
             bondenum,
+            contact_model,
+            contactenum,
             bond_tearing,
             bond_force_limit,
             stiffness,
             collisions,
             friction,
             friction_coefficient,
+            rolling_model,
+            rollingenum,
+            rolling_coefficient,
             rotation,
             linear_contact_bonds,
             changed_collision_settings,
@@ -246,18 +568,30 @@ impl Settings {
             circular_particles,
             render_rot,
             color_code_rot,
+            color_code_temp: false,
+            temp_min: 0.0,
+            temp_max: 100.0,
             colors,
             random_colors,
             render_bonds,
             two_part,
             materials,
             material_size,
+            material_names: vec!["Default".to_string()],
+            material_pairs: vec![],
+            pair_size: PAIR_SIZE,
+            removed_materials: vec![],
+            pair_property: PairProperty::Normal_Stiffness,
             materials_changed,
             menu,
             current_file,
             load: false,
             save: false,
             regen_bonds: false,
+            clump_shape: ClumpShape::Pair,
+            clump_template: ClumpShape::Pair.template(),
+            clump_selection: false,
+            release_clumps: false,
             properties: Properties {
                 set_x_force: false,
                 set_y_force: false,
@@ -275,14 +609,54 @@ impl Settings {
                 y_fixity: false,
                 rot_fixity: false,
                 radius: 0.0,
+                set_temperature: false,
+                temperature: 20.0,
             },
             set_properties: false,
             data: Data::new(),
             auto_size_plot: true,
             plotted_prop: Property::Y_Position,
-            damping: 0.2,
-            bond_shear_limit: 0.5
-        }
+            force_scale: 0.2,
+            damping_model: 0,
+            damping_enum: DampingModel::None,
+            local_damping: 0.7,
+            contact_restitution: 0.5,
+            bond_shear_limit: 0.5,
+            bond_normal_stiffness: 100.0,
+            bond_shear_stiffness: 40.0,
+            bond_radius_multiplier: 1.0,
+            bond_tensile_strength: 10.0,
+            bond_cohesion: 10.0,
+            bond_friction_angle: 30.0,
+            coll_radius: 0.0,
+            coll_cell_size: 1.0,
+            coll_grid_w: 1,
+            coll_grid_h: 1,
+            delta_time: 0.0000390625,
+            auto_delta_time: false,
+            delta_time_safety: 0.2,
+            critical_delta_time: f32::INFINITY,
+            wall_elasticity: 0.5,
+            wall_rot_damping: 0.9,
+            boundary_x: 0,
+            boundary_x_enum: Boundary::Walls,
+            boundary_y: 0,
+            boundary_y_enum: Boundary::Walls,
+            shear_velocity: 0.0,
+            walls: vec![],
+            wall_size: WALL_SIZE,
+            walls_changed: false,
+            emitters: vec![],
+            emitter_size: EMITTER_SIZE,
+            sinks: vec![],
+            sink_size: SINK_SIZE,
+            bond_breaks: 0,
+            overflows: [0, 0],
+            export_bond_events: false,
+            bond_event_file: std::path::PathBuf::new(),
+        };
+        settings.derive_pairs();
+        return settings;
     }
 
     pub fn set_particles(&mut self, particles: usize) {
@@ -290,6 +664,320 @@ impl Settings {
         self.workgroups = (self.particles as f32/self.workgroup_size as f32).ceil() as usize;
     }
 
+    // Sizes the broadphase grid to the walls, with cells at least as wide as the largest particle plus the reach of any cohesion
+    pub fn set_coll_grid(&mut self, largest_radius: f32) {
+        self.coll_radius = largest_radius;
+        let (cell_size, w, h) = grid::dimensions(self.hor_bound, self.vert_bound, 2.0*largest_radius + self.max_cohesion_range(largest_radius));
+        self.coll_cell_size = cell_size;
+        self.coll_grid_w = w;
+        self.coll_grid_h = h;
+    }
+
+    // How far apart two particles of these materials still pull on each other, from their pair's cohesion model.
+    // A liquid bridge ruptures at (1 + angle/2)*volume^(1/3) (Lian et al.), a JKR neck at the gap where the pull-off force is spent.
+    pub fn cohesion_range(&self, mat_a: usize, mat_b: usize, eff_radius: f32) -> f32 {
+        let a = &self.materials[mat_a*self.material_size..(mat_a + 1)*self.material_size];
+        let b = &self.materials[mat_b*self.material_size..(mat_b + 1)*self.material_size];
+        let pair = self.pair(mat_a, mat_b);
+        if pair[5] == 1.0 {
+            let adhesion = pair[6];
+            let eff_youngs = 1.0/((1.0 - a[7]*a[7])/a[6] + (1.0 - b[7]*b[7])/b[6]);
+            return 0.75*(std::f32::consts::PI*std::f32::consts::PI*adhesion*adhesion*eff_radius/(eff_youngs*eff_youngs)).cbrt();
+        } else if pair[5] == 3.0 {
+            let angle = pair[9].to_radians();
+            return (1.0 + 0.5*angle)*pair[7].cbrt();
+        }
+        return 0.0;
+    }
+
+    // The longest cohesion_range of any pair of materials, for two of the largest particles
+    pub fn max_cohesion_range(&self, largest_radius: f32) -> f32 {
+        let count = self.materials.len()/self.material_size;
+        let mut range = 0.0;
+        for a in 0..count {
+            for b in 0..count {
+                range = f32::max(range, self.cohesion_range(a, b, 0.5*largest_radius));
+            }
+        }
+        return range;
+    }
+
+    pub fn material_count(&self) -> usize {
+        return self.materials.len()/self.material_size;
+    }
+
+    // Material pair record: [normal stiffness, shear stiffness, friction coefficient, restitution, cohesion,
+    //  cohesion model, work of adhesion, liquid volume, surface tension, contact angle (deg)]
+    // A pair starts out from its two materials, the stiffnesses as two springs in series doubled (so a material against itself keeps its own),
+    // the lower friction coefficient and the lower restitution. Cohesion starts at 1, it scales whatever cohesion() works out.
+    // Editing a material's stiffness, friction or restitution re-derives those for its pairs, a cohesion set in the table stays.
+    // The cohesion model and its parameters come from derive_pair_cohesion.
+    pub fn derive_pair(&mut self, a: usize, b: usize) {
+        let m = self.material_size;
+        let series = |x: f32, y: f32| if x + y > 0.0 { 2.0*x*y/(x + y) } else { 0.0 };
+        let (mat_a, mat_b) = (&self.materials[a*m..(a + 1)*m], &self.materials[b*m..(b + 1)*m]);
+        let mut pair = self.pair(a, b).to_vec();
+        pair[0] = series(mat_a[4], mat_b[4]);
+        pair[1] = series(mat_a[5], mat_b[5]);
+        pair[2] = f32::min(mat_a[8], mat_b[8]);
+        pair[3] = f32::min(mat_a[16], mat_b[16]);
+        self.set_pair(a, b, &pair);
+    }
+
+    // A pair of materials with the same cohesion model keeps it, a mixed pair has none until it's given one in the table.
+    // The work of adhesion is 2*sqrt(surface_energy_a*surface_energy_b), the liquid properties are averaged.
+    // Editing a material's cohesion re-derives these for its pairs, anything else leaves them be.
+    pub fn derive_pair_cohesion(&mut self, a: usize, b: usize) {
+        let m = self.material_size;
+        let (mat_a, mat_b) = (&self.materials[a*m..(a + 1)*m], &self.materials[b*m..(b + 1)*m]);
+        let mut pair = self.pair(a, b).to_vec();
+        pair[5] = if mat_a[9] == mat_b[9] { mat_a[9] } else { 0.0 };
+        pair[6] = 2.0*(mat_a[10]*mat_b[10]).sqrt();
+        pair[7] = 0.5*(mat_a[11] + mat_b[11]);
+        pair[8] = 0.5*(mat_a[12] + mat_b[12]);
+        pair[9] = 0.5*(mat_a[13] + mat_b[13]);
+        self.set_pair(a, b, &pair);
+    }
+
+    // Contact stiffnesses from Young's modulus and the Poisson ratio, k_n = E/(1 - v^2) per unit thickness (plane strain)
+    // and k_s = k_n*2(1 - v)/(2 - v), the ratio Hertz-Mindlin gives a material against itself.
+    pub fn derive_stiffness(&mut self, i: usize) {
+        let m = self.material_size;
+        (self.materials[i*m + 4], self.materials[i*m + 5]) = Settings::stiffness(self.materials[i*m + 6], self.materials[i*m + 7]);
+        self.derive_material_pairs(i);
+    }
+
+    pub fn stiffness(youngs: f32, poisson: f32) -> (f32, f32) {
+        let normal_stiffness = youngs/(1.0 - poisson*poisson);
+        return (normal_stiffness, normal_stiffness*2.0*(1.0 - poisson)/(2.0 - poisson));
+    }
+
+    pub fn derive_material_pairs(&mut self, a: usize) {
+        for b in 0..self.material_count() {
+            self.derive_pair(a, b);
+        }
+        self.materials_changed = true;
+    }
+
+    pub fn derive_material_cohesion(&mut self, a: usize) {
+        for b in 0..self.material_count() {
+            self.derive_pair_cohesion(a, b);
+        }
+        self.materials_changed = true;
+    }
+
+    // Starts the table over from the materials, after something replaces them wholesale
+    pub fn derive_pairs(&mut self) {
+        self.material_pairs.clear();
+        self.resize_pairs(0, None);
+        for a in 0..self.material_count() {
+            self.derive_material_pairs(a);
+            self.derive_material_cohesion(a);
+        }
+    }
+
+    pub fn pair(&self, a: usize, b: usize) -> &[f32] {
+        let (n, p) = (self.material_count(), self.pair_size);
+        return &self.material_pairs[(a*n + b)*p..(a*n + b + 1)*p];
+    }
+
+    // Writes (a, b) and (b, a) both, the table stays symmetric
+    pub fn set_pair(&mut self, a: usize, b: usize, pair: &[f32]) {
+        let (n, p) = (self.material_count(), self.pair_size);
+        self.material_pairs[(a*n + b)*p..(a*n + b + 1)*p].copy_from_slice(pair);
+        self.material_pairs[(b*n + a)*p..(b*n + a + 1)*p].copy_from_slice(pair);
+    }
+
+    // Lays the table out for material_count() materials from one of old_count, dropping the row and column of removed.
+    // Pairs with a new material get a cohesion of 1, derive_pair and derive_pair_cohesion fill in the rest.
+    fn resize_pairs(&mut self, old_count: usize, removed: Option<usize>) {
+        let (n, p) = (self.material_count(), self.pair_size);
+        let old_index = |k: usize| match removed {
+            Some(r) if k >= r => k + 1,
+            _ => k,
+        };
+        let mut pairs = vec![0.0; n*n*p];
+        for a in 0..n {
+            for b in 0..n {
+                let (old_a, old_b) = (old_index(a), old_index(b));
+                let pair = &mut pairs[(a*n + b)*p..(a*n + b + 1)*p];
+                if old_a < old_count && old_b < old_count {
+                    pair.copy_from_slice(&self.material_pairs[(old_a*old_count + old_b)*p..(old_a*old_count + old_b + 1)*p]);
+                } else {
+                    pair[4] = 1.0;
+                }
+            }
+        }
+        self.material_pairs = pairs;
+    }
+
+    // A copy of the last material, its pairs come from the materials like a new table's would
+    pub fn add_material(&mut self) {
+        let (m, old_count) = (self.material_size, self.material_count());
+        let material = self.materials[(old_count - 1)*m..old_count*m].to_vec();
+        self.materials.extend_from_slice(&material);
+        self.material_names.push(format!("Material {}", old_count + 1));
+        self.resize_pairs(old_count, None);
+        self.derive_material_pairs(old_count);
+        self.derive_material_cohesion(old_count);
+    }
+
+    // Particles and emitters of material i fall back to the first one left (see State::remove_material), the last material can't go
+    pub fn remove_material(&mut self, i: usize) {
+        let (m, old_count) = (self.material_size, self.material_count());
+        if old_count < 2 {
+            return;
+        }
+        self.materials.drain(i*m..(i + 1)*m);
+        self.material_names.remove(i);
+        self.resize_pairs(old_count, Some(i));
+        let e = self.emitter_size;
+        for k in 0..self.emitter_count() {
+            let material = self.emitters[k*e + 9] as i32;
+            if material == i as i32 {
+                self.emitters[k*e + 9] = 0.0;
+            } else if material > i as i32 {
+                self.emitters[k*e + 9] -= 1.0;
+            }
+        }
+        self.properties.material = self.properties.material.min(old_count as i32 - 2);
+        self.removed_materials.push(i);
+        self.materials_changed = true;
+    }
+
+    // Takes on a loaded state's materials, files saved before they were embedded keep the current ones.
+    // Either way there are enough for every particle's material pointer.
+    pub fn set_materials(&mut self, materials: &[f32], pairs: &[f32], names: &[String], largest_pointer: i32) {
+        if !materials.is_empty() {
+            self.materials = materials.to_vec();
+            self.material_names = names.to_vec();
+            let n = self.material_count();
+            self.material_names.resize(n, String::new());
+            self.material_pairs = pairs.to_vec();
+            if self.material_pairs.len() == n*n*5 {
+                self.widen_pairs();
+            } else if self.material_pairs.len() != n*n*self.pair_size {
+                self.derive_pairs();
+            }
+        }
+        while (self.material_count() as i32) <= largest_pointer {
+            self.add_material();
+        }
+        self.properties.material = self.properties.material.min(self.material_count() as i32 - 1);
+        self.materials_changed = true;
+    }
+
+    // Files saved before the pairs had their own cohesion only have the first 5 fields, the rest come from the materials
+    fn widen_pairs(&mut self) {
+        let pairs = self.material_pairs.chunks(5).flat_map(|pair| {
+            let mut pair = pair.to_vec();
+            pair.resize(PAIR_SIZE, 0.0);
+            return pair;
+        }).collect();
+        self.material_pairs = pairs;
+        for a in 0..self.material_count() {
+            self.derive_material_cohesion(a);
+        }
+    }
+
+    pub fn coll_cells(&self) -> usize {
+        return self.coll_grid_w*self.coll_grid_h;
+    }
+
+    pub fn wall_count(&self) -> usize {
+        return self.walls.len()/self.wall_size;
+    }
+
+    // Storage buffers can't be empty, so with no walls the shaders get one zeroed record and wall_count = 0
+    pub fn wall_buffer(&self) -> Vec<f32> {
+        if self.walls.is_empty() {
+            return vec![0.0; self.wall_size];
+        }
+        return self.walls.clone();
+    }
+
+    // Wall record: [x1, y1, x2, y2, stiffness, friction, restitution, joined, control, setpoint, gain, speed, load, shear, thermal, heat]
+    // A joined wall starts where the previous one ends, a run of joined walls is a polyline.
+    // control 0 is fixed, 1 moves at a strain rate of setpoint, 2 is a servo holding a stress (load per unit length) of setpoint.
+    // control 3 is an outflow, it doesn't push back and whatever touches or crosses it is taken out (see emitter.rs).
+    // Controlled walls (platens) move along their left normal (-dy, dx), speed, load and shear are written back by the simulation.
+    // thermal 0 is adiabatic, 1 holds what it touches at a temperature of heat and 2 puts heat in per unit length of contact.
+    pub fn add_wall(&mut self, joined: bool) {
+        let w = self.wall_size;
+        let mut wall = vec![-0.5*self.hor_bound, 0.0, 0.5*self.hor_bound, 0.0, 10.0, 0.5, 0.5, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        if joined && self.wall_count() > 0 {
+            let last = self.walls.len() - w;
+            wall = self.walls[last..last + w].to_vec();
+            let delta = (wall[2] - wall[0], wall[3] - wall[1]);
+            wall[0] = wall[2];
+            wall[1] = wall[3];
+            wall[2] += delta.0;
+            wall[3] += delta.1;
+            wall[7] = 1.0;
+            wall[8..14].copy_from_slice(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        }
+        self.walls.extend_from_slice(&wall);
+    }
+
+    pub fn remove_wall(&mut self, i: usize) {
+        let w = self.wall_size;
+        self.walls.drain(i*w..(i + 1)*w);
+        if i == 0 && self.wall_count() > 0 {
+            self.walls[7] = 0.0;
+        }
+        self.join_walls();
+    }
+
+    // Snaps the start of every joined wall onto the end of the one before it.
+    // Platens move on their own, so they can't be part of a polyline.
+    pub fn join_walls(&mut self) {
+        let w = self.wall_size;
+        for i in 1..self.wall_count() {
+            if self.walls[i*w + 8] != 0.0 || self.walls[(i - 1)*w + 8] != 0.0 {
+                self.walls[i*w + 7] = 0.0;
+            }
+            if self.walls[i*w + 7] == 1.0 {
+                self.walls[i*w] = self.walls[(i - 1)*w + 2];
+                self.walls[i*w + 1] = self.walls[(i - 1)*w + 3];
+            }
+        }
+    }
+
+    pub fn emitter_count(&self) -> usize {
+        return self.emitters.len()/self.emitter_size;
+    }
+
+    pub fn sink_count(&self) -> usize {
+        return self.sinks.len()/self.sink_size;
+    }
+
+    // Whether anything can change the particle count while running, an outflow wall is one with control 3
+    pub fn has_emitters(&self) -> bool {
+        let w = self.wall_size;
+        return self.emitter_count() > 0 || self.sink_count() > 0 || (0..self.wall_count()).any(|i| self.walls[i*w + 8] == 3.0);
+    }
+
+    // Emitter record: [x1, y1, x2, y2, rate, min radius, max radius, velocity x, velocity y, material]
+    // Particles go in at rate per second, anywhere in the rectangle they don't overlap something, with a radius picked evenly between the two.
+    pub fn add_emitter(&mut self) {
+        let (h, v) = (self.hor_bound, self.vert_bound);
+        self.emitters.extend_from_slice(&[-0.2*h, 0.7*v, 0.2*h, 0.9*v, 10.0, self.min_radius, self.max_radius, 0.0, 0.0, 0.0]);
+    }
+
+    // Sink record: [x1, y1, x2, y2], anything whose center ends up in the rectangle is taken out
+    pub fn add_sink(&mut self) {
+        let (h, v) = (self.hor_bound, self.vert_bound);
+        self.sinks.extend_from_slice(&[-h, -v, h, -0.9*v]);
+    }
+
+    // The timestep the shaders actually use
+    pub fn timestep(&self) -> f32 {
+        if self.auto_delta_time && self.critical_delta_time.is_finite() {
+            return self.critical_delta_time*self.delta_time_safety;
+        }
+        return self.delta_time;
+    }
+
     pub fn ui(&mut self, ctx: &Context) -> bool {
         let mut reset = false;
         if !self.current_file.exists() && self.save {
@@ -307,6 +995,12 @@ impl Settings {
                     if ui.selectable_label(self.menu.walls_menu, "Walls").clicked() { self.menu.walls_menu = !self.menu.walls_menu; }
                     if ui.selectable_label(self.menu.data_menu, "Data").clicked() { self.menu.data_menu = !self.menu.data_menu; }
                     if ui.selectable_label(self.menu.save_load_menu, "Save/Load").clicked() { self.menu.save_load_menu = !self.menu.save_load_menu; }
+                    if self.overflows[0] > 0 {
+                        ui.colored_label(Color32::RED, format!("{} particles at the contact cap ({})", self.overflows[0], self.max_contacts));
+                    }
+                    if self.overflows[1] > 0 {
+                        ui.colored_label(Color32::RED, format!("{} particles over the bond cap ({})", self.overflows[1], self.max_bonds));
+                    }
                 });
             });
             if self.menu.render_settings {
@@ -317,6 +1011,11 @@ impl Settings {
                     ui.checkbox(&mut self.colors, "Colors");
                     ui.checkbox(&mut self.random_colors, "Random Colors");
                     ui.checkbox(&mut self.color_code_rot, "Color Code Rotation");
+                    ui.checkbox(&mut self.color_code_temp, "Color Code Temperature");
+                    if self.color_code_temp {
+                        ui.add(egui::Slider::new(&mut self.temp_min, -100.0..=1000.0).text("Coldest"));
+                        ui.add(egui::Slider::new(&mut self.temp_max, -100.0..=1000.0).text("Hottest"));
+                    }
                 });    
             }
             if self.menu.properties_menu {
@@ -383,13 +1082,25 @@ impl Settings {
                                     inner_ui4.add(egui::Slider::new(&mut self.properties.material, 0..=(self.materials.len()/self.material_size - 1) as i32));
                                 });
                             });
+                            inner_ui2.label("Temperature");
+                            inner_ui2.horizontal(|inner_ui3| {
+                                inner_ui3.checkbox(&mut self.properties.set_temperature, "");
+                                inner_ui3.add_enabled_ui(self.properties.set_temperature, |inner_ui4| {
+                                    inner_ui4.add(egui::DragValue::new(&mut self.properties.temperature).speed(0.1));
+                                });
+                            });
                             if inner_ui2.add_enabled(
-                                self.properties.set_material || self.properties.set_radius || self.properties.set_rot_fixity || self.properties.set_rot_force || self.properties.set_x_fixity || self.properties.set_x_force || self.properties.set_y_fixity || self.properties.set_y_force,
+                                self.properties.set_material || self.properties.set_radius || self.properties.set_rot_fixity || self.properties.set_rot_force || self.properties.set_x_fixity || self.properties.set_x_force || self.properties.set_y_fixity || self.properties.set_y_force || self.properties.set_temperature,
                                 egui::Button::new("Set Properties")).
                                 clicked() 
                             {
                                 self.set_properties = !self.set_properties;
                             }
+                            inner_ui2.label("Clumps");
+                            inner_ui2.horizontal(|inner_ui3| {
+                                if inner_ui3.button("Clump Selection").clicked() { self.clump_selection = true; }
+                                if inner_ui3.button("Release Clumps").clicked() { self.release_clumps = true; }
+                            });
                         });
                     });
                 });
@@ -415,6 +1126,7 @@ impl Settings {
                                 reset = reset || ui.selectable_value(&mut self.structure, Structure::Exp5, "Experiment 5").changed();
                                 reset = reset || ui.selectable_value(&mut self.structure, Structure::Exp6, "Experiment 6").changed();
                                 reset = reset || ui.selectable_value(&mut self.structure, Structure::Mats, "Mats").changed();
+                                reset = reset || ui.selectable_value(&mut self.structure, Structure::Clumps, "Clumps").changed();
                             });
                         if !self.two_part { if self.structure == Structure::Grid {
                             if ui.add(egui::Slider::new(&mut self.grid_width, 1.0..=self.particles as f32).
@@ -423,6 +1135,65 @@ impl Settings {
                                 reset = true;
                             };
                         }
+                        if self.structure == Structure::Clumps {
+                            let mut changed_shape = false;
+                            egui::ComboBox::from_label("Clump Shape")
+                                .selected_text(format!("{:?}", self.clump_shape))
+                                .show_ui(ui, |ui| {
+                                    changed_shape = ui.selectable_value(&mut self.clump_shape, ClumpShape::Pair, "Pair").changed() || changed_shape;
+                                    changed_shape = ui.selectable_value(&mut self.clump_shape, ClumpShape::Triangle, "Triangle").changed() || changed_shape;
+                                    changed_shape = ui.selectable_value(&mut self.clump_shape, ClumpShape::Rod, "Rod").changed() || changed_shape;
+                                    changed_shape = ui.selectable_value(&mut self.clump_shape, ClumpShape::L_Shape, "L-Shape").changed() || changed_shape;
+                                });
+                            if changed_shape {
+                                self.clump_template = self.clump_shape.template();
+                                reset = true;
+                            }
+                            // x, y and radius of each disc in units of the clump's radius
+                            let mut edited = false;
+                            egui::CollapsingHeader::new("Clump Template").show(ui, |ui| {
+                                let mut removed = None;
+                                for i in 0..self.clump_template.len()/3 {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("Disc {}", i+1));
+                                        edited = ui.add(egui::DragValue::new(&mut self.clump_template[i*3]).speed(0.01).prefix("x: ")).changed() || edited;
+                                        edited = ui.add(egui::DragValue::new(&mut self.clump_template[i*3 + 1]).speed(0.01).prefix("y: ")).changed() || edited;
+                                        edited = ui.add(egui::DragValue::new(&mut self.clump_template[i*3 + 2]).speed(0.01).clamp_range(0.05..=10.0).prefix("r: ")).changed() || edited;
+                                        if self.clump_template.len() > 3 && ui.button("Remove").clicked() {
+                                            removed = Some(i);
+                                        }
+                                    });
+                                }
+                                if ui.button("Add Disc").clicked() {
+                                    self.clump_template.extend_from_slice(&[0.0, 0.0, 1.0]);
+                                    edited = true;
+                                }
+                                if let Some(i) = removed {
+                                    self.clump_template.drain(i*3..i*3 + 3);
+                                    edited = true;
+                                }
+                            });
+                            if edited {
+                                self.clump_shape = ClumpShape::Custom;
+                                reset = true;
+                            }
+                        }
+                        egui::ComboBox::from_label("Particle Shape")
+                            .selected_text(format!("{:?}", self.particle_shape))
+                            .show_ui(ui, |ui| {
+                                reset = reset || ui.selectable_value(&mut self.particle_shape, ParticleShape::Disc, "Disc").changed();
+                                reset = reset || ui.selectable_value(&mut self.particle_shape, ParticleShape::Polygon, "Polygon").changed();
+                            });
+                        if self.particle_shape == ParticleShape::Polygon {
+                            if ui.add(egui::Slider::new(&mut self.polygon_sides, 3..=12).
+                            text("Sides")).changed() {
+                                reset = true;
+                            };
+                            if ui.add(egui::Slider::new(&mut self.polygon_irregularity, 0.0..=1.0).
+                            text("Irregularity")).changed() {
+                                reset = true;
+                            };
+                        }
                         if ui.checkbox(&mut self.variable_rad, "Random Radius").changed() {
                             reset = true;
                         }
@@ -477,6 +1248,15 @@ impl Settings {
                                 reset = true;
                             };
                         });}
+                        // these size the contact buffers and arrays in 2D_Simulation.wgsl, so changing them starts over
+                        if ui.add(egui::Slider::new(&mut self.max_contacts, 4..=32).
+                        text("Max Contacts")).changed() {
+                            reset = true;
+                        };
+                        if ui.add(egui::Slider::new(&mut self.max_bonds, 1..=16).
+                        text("Max Bonds")).changed() {
+                            reset = true;
+                        };
                         if ui.button("Regenerate Bonds").clicked() {
                             self.regen_bonds = true;                            
                         }
@@ -485,23 +1265,168 @@ impl Settings {
                 if self.menu.physics_menu {
                     egui::Window::new("Physics").collapsible(false).auto_sized().show(ctx, |ui| {
                         ui.add(egui::Slider::new(&mut self.genPerFrame, 1..=213).logarithmic(true).text("Gen/Frame"));
+                    if ui.checkbox(&mut self.auto_delta_time, "Auto Timestep").changed() {
+                        self.changed_collision_settings = true;
+                    }
+                    if self.auto_delta_time {
+                        if ui.add(egui::Slider::new(&mut self.delta_time_safety, 0.01..=1.0).
+                        text("Safety Factor")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                    } else {
+                        if ui.add(egui::Slider::new(&mut self.delta_time, 0.0000001..=0.001).logarithmic(true).
+                        text("Timestep")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                    }
+                    ui.label(format!("Timestep: {:.3e} s, Critical: {:.3e} s", self.timestep(), self.critical_delta_time));
+                    if self.timestep() > self.critical_delta_time {
+                        ui.colored_label(Color32::RED, "Timestep is above the critical timestep, expect the simulation to blow up");
+                    }
                     if ui.checkbox(&mut self.gravity, "Gravity").changed() {
                         self.changed_collision_settings = true;
                     }
                     if self.gravity {
                         if ui.checkbox(&mut self.planet_mode, "Planet Mode").changed() {
+                            self.self_gravity = false;
+                            self.changed_collision_settings = true;
+                        }
+                        if ui.checkbox(&mut self.self_gravity, "Self Gravity (N-Body)").changed() {
+                            self.planet_mode = false;
                             self.changed_collision_settings = true;
                         }
-                        if ui.add(egui::Slider::new(&mut self.gravity_acceleration, -100.0..=100.0).step_by(0.1).
-                        text("G Force")).changed() {
-                            // println!("{}", self.gravity_acceleration);
+                        if self.self_gravity {
+                            ui.add(egui::Slider::new(&mut self.gravitational_constant, 0.001..=1000.0).logarithmic(true).text("G"));
+                            ui.add(egui::Slider::new(&mut self.barnes_hut_theta, 0.0..=1.5).text("Opening Angle"));
+                            ui.add(egui::Slider::new(&mut self.gravity_softening, 0.0001..=0.1).logarithmic(true).text("Softening"));
+                            // see WGPUComputeProg::update_self_gravity, the positions come back and the tree is built on the CPU
+                            ui.label("On the GPU the field is held for as many steps as the strongest pull allows and rebuilt in between, a fast collapse rebuilds it every step. The read back and tree build slow large counts down.");
+                        } else {
+                            if ui.add(egui::Slider::new(&mut self.gravity_x, -100.0..=100.0).step_by(0.01).
+                            text("G Force X")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                            if ui.add(egui::Slider::new(&mut self.gravity_y, -100.0..=100.0).step_by(0.01).
+                            text("G Force Y")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                        }
+                    }
+                    let mut changed_excitation = false;
+                    egui::ComboBox::from_label("Excitation")
+                    .selected_text(format!("{:?}", self.excitation.target_enum))
+                    .show_ui(ui, |ui| {
+                        changed_excitation = changed_excitation || ui.selectable_value(&mut self.excitation.target_enum, ExcitationTarget::None, "None").changed();
+                        changed_excitation = changed_excitation || ui.selectable_value(&mut self.excitation.target_enum, ExcitationTarget::Gravity, "Gravity").changed();
+                        changed_excitation = changed_excitation || ui.selectable_value(&mut self.excitation.target_enum, ExcitationTarget::Walls, "Walls").changed();
+                    });
+                    if changed_excitation {
+                        self.changed_collision_settings = true;
+                        self.excitation.target = match self.excitation.target_enum {
+                            ExcitationTarget::None => { 0 },
+                            ExcitationTarget::Gravity => { 1 },
+                            ExcitationTarget::Walls => { 2 },
+                        }
+                    }
+                    if self.excitation.target != 0 {
+                        let mut changed_source = false;
+                        egui::ComboBox::from_label("Time History")
+                        .selected_text(format!("{:?}", self.excitation.source_enum))
+                        .show_ui(ui, |ui| {
+                            changed_source = changed_source || ui.selectable_value(&mut self.excitation.source_enum, ExcitationSource::Sinusoid, "Sinusoid").changed();
+                            changed_source = changed_source || ui.selectable_value(&mut self.excitation.source_enum, ExcitationSource::Accelerogram, "Accelerogram").changed();
+                        });
+                        if changed_source {
+                            self.excitation.source = match self.excitation.source_enum {
+                                ExcitationSource::Sinusoid => { 0 },
+                                ExcitationSource::Accelerogram => { 1 },
+                            }
+                        }
+                        if self.excitation.source == 0 {
+                            ui.add(egui::Slider::new(&mut self.excitation.amplitude, 0.0..=2.0).text("Amplitude (g)"));
+                            ui.add(egui::Slider::new(&mut self.excitation.frequency, 0.0..=50.0).text("Frequency (Hz)"));
+                            if self.excitation.frequency == 0.0 && self.excitation.target == 2 {
+                                ui.label("0 Hz is a constant tilt, it only does anything to gravity");
+                            }
+                        } else {
+                            if ui.button("Load Accelerogram").clicked() {
+                                self.load_accelerogram();
+                            }
+                            match self.excitation.accelerogram_file.file_name() {
+                                Some(name) => { ui.label(format!("{} ({} rows)", name.to_string_lossy(), self.excitation.accelerogram.len()/4)); },
+                                None => { ui.label("No accelerogram loaded"); },
+                            }
+                        }
+                        ui.add(egui::Slider::new(&mut self.excitation.angle, -180.0..=180.0).text("Direction (deg)"));
+                    }
+                    let mut changed_fluid = false;
+                    egui::ComboBox::from_label("Fluid")
+                    .selected_text(format!("{:?}", self.fluid.flow))
+                    .show_ui(ui, |ui| {
+                        changed_fluid = changed_fluid || ui.selectable_value(&mut self.fluid.flow, FluidFlow::None, "None").changed();
+                        changed_fluid = changed_fluid || ui.selectable_value(&mut self.fluid.flow, FluidFlow::Still, "Still").changed();
+                        changed_fluid = changed_fluid || ui.selectable_value(&mut self.fluid.flow, FluidFlow::Uniform, "Uniform").changed();
+                        changed_fluid = changed_fluid || ui.selectable_value(&mut self.fluid.flow, FluidFlow::Shear, "Shear").changed();
+                    });
+                    if self.fluid.flow != FluidFlow::None {
+                        egui::ComboBox::from_label("Drag")
+                        .selected_text(format!("{:?}", self.fluid.drag))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.fluid.drag, DragModel::Di_Felice, "Di Felice");
+                            ui.selectable_value(&mut self.fluid.drag, DragModel::Ergun, "Ergun/Wen-Yu");
+                        });
+                        // buoyancy is done on the GPU, so the density goes in the collision settings
+                        changed_fluid = changed_fluid || ui.add(egui::Slider::new(&mut self.fluid.density, 0.0..=10.0).text("Fluid Density")).changed();
+                        ui.add(egui::Slider::new(&mut self.fluid.viscosity, 0.00001..=10.0).logarithmic(true).text("Viscosity"));
+                        if self.fluid.flow == FluidFlow::Uniform {
+                            ui.add(egui::Slider::new(&mut self.fluid.velocity_x, -10.0..=10.0).text("Superficial Velocity X"));
+                            ui.add(egui::Slider::new(&mut self.fluid.velocity_y, -10.0..=10.0).text("Superficial Velocity Y"));
+                        }
+                        if self.fluid.flow == FluidFlow::Shear {
+                            ui.add(egui::Slider::new(&mut self.fluid.shear_rate, -100.0..=100.0).text("Shear Rate"));
+                        }
+                        ui.add(egui::Slider::new(&mut self.fluid.grid_w, 1..=128).text("Fluid Cells X"));
+                        ui.add(egui::Slider::new(&mut self.fluid.grid_h, 1..=128).text("Fluid Cells Y"));
+                        ui.checkbox(&mut self.fluid.overlay, "Show Fluid Velocity");
+                    }
+                    if changed_fluid {
+                        self.changed_collision_settings = true;
+                    }
+                    if ui.add(egui::Slider::new(&mut self.force_scale, 0.01..=10.0).logarithmic(true).
+                    text("Force Scale")).changed() {
+                        self.changed_collision_settings = true;
+                    };
+                    let mut changed_damping = false;
+                    egui::ComboBox::from_label("Damping")
+                    .selected_text(format!("{:?}", self.damping_enum))
+                    .show_ui(ui, |ui| {
+                        changed_damping = changed_damping || ui.selectable_value(&mut self.damping_enum, DampingModel::None, "None").changed();
+                        changed_damping = changed_damping || ui.selectable_value(&mut self.damping_enum, DampingModel::Local, "Local").changed();
+                        changed_damping = changed_damping || ui.selectable_value(&mut self.damping_enum, DampingModel::Viscous, "Viscous").changed();
+                        changed_damping = changed_damping || ui.selectable_value(&mut self.damping_enum, DampingModel::Local_Viscous, "Local + Viscous").changed();
+                    });
+                    if changed_damping {
+                        self.changed_collision_settings = true;
+                        self.damping_model = match self.damping_enum {
+                            DampingModel::None => { 0 },
+                            DampingModel::Local => { 1 },
+                            DampingModel::Viscous => { 2 },
+                            DampingModel::Local_Viscous => { 3 },
+                        }
+                    }
+                    if self.damping_model & 1 != 0 {
+                        if ui.add(egui::Slider::new(&mut self.local_damping, 0.0..=1.0).
+                        text("Local Damping")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                    }
+                    if self.damping_model & 2 != 0 {
+                        if ui.add(egui::Slider::new(&mut self.contact_restitution, 0.01..=1.0).
+                        text("Contact Restitution")).changed() {
+                            self.set_restitution();
                             self.changed_collision_settings = true;
                         };
                     }
-                    // if ui.add(egui::Slider::new(&mut self.damping, 0.0..=10.0).
-                    // text("Damping")).changed() {
-                    //     self.changed_collision_settings = true;
-                    // };
                     let mut changed_bonds = false;
                     egui::ComboBox::from_label("Bonds")
                     .selected_text(format!("{:?}", self.bondenum))
@@ -520,22 +1445,68 @@ impl Settings {
                             BondType::Parallel_Linear_Contact_Bond => { 3 },
                         }
                     }
+                    let mut changed_contact_model = false;
+                    egui::ComboBox::from_label("Contact Model")
+                    .selected_text(format!("{:?}", self.contactenum))
+                    .show_ui(ui, |ui| {
+                        changed_contact_model = changed_contact_model || ui.selectable_value(&mut self.contactenum, ContactModel::Linear, "Linear").changed();
+                        changed_contact_model = changed_contact_model || ui.selectable_value(&mut self.contactenum, ContactModel::Hertz_Mindlin, "Hertz-Mindlin").changed();
+                    });
+                    if changed_contact_model {
+                        self.changed_collision_settings = true;
+                        self.contact_model = match self.contactenum {
+                            ContactModel::Linear => { 0 },
+                            ContactModel::Hertz_Mindlin => { 1 },
+                        }
+                    }
                     // if ui.checkbox(&mut self.bonds, "Bonds").changed() {
                         //     self.changed_collision_settings = true;
                         // }
-                    if self.bonds != 0 {
+                    if self.bonds == 1 || self.bonds == 2 {
                         if ui.add(egui::Slider::new(&mut self.stiffness, 0.01..=100.0).step_by(0.01).
                         text("Stiffness")).changed() {
                             self.changed_collision_settings = true;
                         };
-                        // if ui.add(egui::Slider::new(&mut self.bond_shear_limit, 0.0..=10.0).
-                        // text("Bond Shear Limit")).changed() {
-                        //     self.changed_collision_settings = true;
-                        // };
+                    }
+                    if self.bonds == 2 {
+                        if ui.add(egui::Slider::new(&mut self.bond_shear_limit, 0.0..=10.0).
+                        text("Bond Shear Limit")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                    }
+                    if self.bonds == 3 {
+                        // stiffnesses are per unit area and the strengths are stresses, see parallel_bond in 2D_Simulation.wgsl
+                        if ui.add(egui::Slider::new(&mut self.bond_normal_stiffness, 0.0..=100000.0).logarithmic(true).
+                        text("Bond Normal Stiffness")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                        if ui.add(egui::Slider::new(&mut self.bond_shear_stiffness, 0.0..=100000.0).logarithmic(true).
+                        text("Bond Shear Stiffness")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                        if ui.add(egui::Slider::new(&mut self.bond_radius_multiplier, 0.01..=1.0).
+                        text("Bond Radius Multiplier")).changed() {
+                            self.changed_collision_settings = true;
+                        };
+                    }
+                    if self.bonds != 0 {
                         if ui.checkbox(&mut self.bond_tearing, "Bond Tearing").changed() {
                             self.changed_collision_settings = true;
                         }
-                        if self.bond_tearing {
+                        if self.bond_tearing && self.bonds == 3 {
+                            if ui.add(egui::Slider::new(&mut self.bond_tensile_strength, 0.0..=10000.0).logarithmic(true).
+                            text("Tensile Strength")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                            if ui.add(egui::Slider::new(&mut self.bond_cohesion, 0.0..=10000.0).logarithmic(true).
+                            text("Cohesion")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                            if ui.add(egui::Slider::new(&mut self.bond_friction_angle, 0.0..=89.0).
+                            text("Friction Angle (deg)")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                        } else if self.bond_tearing {
                             if ui.add(egui::Slider::new(&mut self.bond_force_limit, 0.0..=5.0).step_by(0.0001).
                             text("Tear Limit")).changed() {
                                 self.changed_collision_settings = true;
@@ -546,10 +1517,45 @@ impl Settings {
                         self.changed_collision_settings = true;
                     }
                     if self.collisions {
-                        if ui.add(egui::Slider::new(&mut self.friction_coefficient, 0.0..=1.0).
-                        text("Friction Coef.")).changed() {
+                        // the friction coefficient itself is per material pair, see the Materials window, this one is for particles without a material
+                        if ui.checkbox(&mut self.friction, "Friction").changed() {
                             self.changed_collision_settings = true;
-                        };
+                        }
+                        if self.friction {
+                            if ui.add(egui::Slider::new(&mut self.friction_coefficient, 0.0..=1.0).
+                            text("Friction Coef. (No Material)")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                        }
+                        let mut changed_rolling = false;
+                        egui::ComboBox::from_label("Rolling Resistance")
+                        .selected_text(format!("{:?}", self.rollingenum))
+                        .show_ui(ui, |ui| {
+                            changed_rolling = changed_rolling || ui.selectable_value(&mut self.rollingenum, RollingResistance::None, "None").changed();
+                            changed_rolling = changed_rolling || ui.selectable_value(&mut self.rollingenum, RollingResistance::Constant_Torque, "Constant Torque (Type A)").changed();
+                            changed_rolling = changed_rolling || ui.selectable_value(&mut self.rollingenum, RollingResistance::Elastic_Plastic, "Elastic-Plastic Spring (Type C)").changed();
+                        });
+                        if changed_rolling {
+                            self.changed_collision_settings = true;
+                            self.rolling_model = match self.rollingenum {
+                                RollingResistance::None => { 0 },
+                                RollingResistance::Constant_Torque => { 1 },
+                                RollingResistance::Elastic_Plastic => { 2 },
+                            }
+                        }
+                        if self.rolling_model != 0 {
+                            if ui.add(egui::Slider::new(&mut self.rolling_coefficient, 0.0..=1.0).
+                            text("Rolling Resistance Coef.")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                        }
+                    }
+                    // conductivity and specific heat are per material, hot and cold walls are set up in the Walls window
+                    if ui.checkbox(&mut self.heat_conduction, "Heat Conduction").changed() {
+                        self.changed_collision_settings = true;
+                    }
+                    if self.heat_conduction {
+                        ui.add(egui::Slider::new(&mut self.initial_temperature, -100.0..=1000.0).text("Initial Temperature (on reset)"));
                     }
                 });
             }          
@@ -571,26 +1577,354 @@ impl Settings {
                                     self.hor_bound = self.vert_bound*ar;
                                 }
                             };
+                        if ui.add(egui::Slider::new(&mut self.wall_elasticity, 0.0..=1.0).
+                            text("Box Elasticity")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                        if ui.add(egui::Slider::new(&mut self.wall_rot_damping, 0.0..=1.0).
+                            text("Box Rotational Damping")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                    let mut changed_boundary = false;
+                    egui::ComboBox::from_label("X Boundary")
+                    .selected_text(format!("{:?}", self.boundary_x_enum))
+                    .show_ui(ui, |ui| {
+                        changed_boundary = ui.selectable_value(&mut self.boundary_x_enum, Boundary::Walls, "Walls").changed() || changed_boundary;
+                        changed_boundary = ui.selectable_value(&mut self.boundary_x_enum, Boundary::Periodic, "Periodic").changed() || changed_boundary;
+                    });
+                    egui::ComboBox::from_label("Y Boundary")
+                    .selected_text(format!("{:?}", self.boundary_y_enum))
+                    .show_ui(ui, |ui| {
+                        changed_boundary = ui.selectable_value(&mut self.boundary_y_enum, Boundary::Walls, "Walls").changed() || changed_boundary;
+                        changed_boundary = ui.selectable_value(&mut self.boundary_y_enum, Boundary::Periodic, "Periodic").changed() || changed_boundary;
+                        changed_boundary = ui.selectable_value(&mut self.boundary_y_enum, Boundary::Lees_Edwards, "Lees-Edwards").changed() || changed_boundary;
+                    });
+                    if changed_boundary {
+                        self.changed_collision_settings = true;
+                        if self.boundary_y_enum == Boundary::Lees_Edwards {
+                            self.boundary_x_enum = Boundary::Periodic;
+                        }
+                        self.boundary_x = match self.boundary_x_enum {
+                            Boundary::Walls => { 0 },
+                            _ => { 1 },
+                        };
+                        self.boundary_y = match self.boundary_y_enum {
+                            Boundary::Walls => { 0 },
+                            Boundary::Periodic => { 1 },
+                            Boundary::Lees_Edwards => { 2 },
+                        };
+                    }
+                    if self.boundary_y == 2 {
+                        if ui.add(egui::Slider::new(&mut self.shear_velocity, -10.0..=10.0).
+                            text("Shear Velocity")).changed() {
+                                self.changed_collision_settings = true;
+                            };
+                    }
+                    ui.separator();
+                    let w = self.wall_size;
+                    let mut removed = None;
+                    let mut changed = false;
+                    for i in 0..self.wall_count() {
+                        let wall_num = i+1;
+                        let joined = self.walls[i*w + 7] == 1.0;
+                        let title = if joined { format!("Wall {wall_num} (joined)") } else { format!("Wall {wall_num}") };
+                        egui::CollapsingHeader::new(title).id_source(format!("wall {i}")).show(ui, |ui| {
+                            ui.add_enabled_ui(!joined, |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Start");
+                                    changed = ui.add(egui::DragValue::new(&mut self.walls[i*w + 0]).speed(0.01)).changed() || changed;
+                                    changed = ui.add(egui::DragValue::new(&mut self.walls[i*w + 1]).speed(0.01)).changed() || changed;
+                                });
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("End");
+                                changed = ui.add(egui::DragValue::new(&mut self.walls[i*w + 2]).speed(0.01)).changed() || changed;
+                                changed = ui.add(egui::DragValue::new(&mut self.walls[i*w + 3]).speed(0.01)).changed() || changed;
+                            });
+                            changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 4], 0.1..=100000.0).logarithmic(true).text("Stiffness")).changed() || changed;
+                            changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 5], 0.0..=1.0).text("Friction Coef.")).changed() || changed;
+                            changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 6], 0.0..=1.0).text("Restitution")).changed() || changed;
+                            if !joined {
+                                let controls = ["Fixed", "Strain Rate", "Servo Stress", "Outflow"];
+                                let mut control = self.walls[i*w + 8] as usize;
+                                egui::ComboBox::from_id_source(format!("wall control {i}"))
+                                .selected_text(controls[control])
+                                .show_ui(ui, |ui| {
+                                    for c in 0..controls.len() {
+                                        changed = ui.selectable_value(&mut control, c, controls[c]).changed() || changed;
+                                    }
+                                });
+                                self.walls[i*w + 8] = control as f32;
+                                if control == 1 {
+                                    changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 9], -10.0..=10.0).text("Strain Rate (1/s)")).changed() || changed;
+                                } else if control == 2 {
+                                    changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 9], 0.0..=1000.0).logarithmic(true).text("Target Stress")).changed() || changed;
+                                    changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 10], 0.0..=100.0).logarithmic(true).text("Servo Gain")).changed() || changed;
+                                }
+                            }
+                            let thermals = ["Adiabatic", "Fixed Temperature", "Heat Flux"];
+                            let mut thermal = self.walls[i*w + 14] as usize;
+                            egui::ComboBox::from_id_source(format!("wall thermal {i}"))
+                            .selected_text(thermals[thermal])
+                            .show_ui(ui, |ui| {
+                                for t in 0..thermals.len() {
+                                    changed = ui.selectable_value(&mut thermal, t, thermals[t]).changed() || changed;
+                                }
+                            });
+                            self.walls[i*w + 14] = thermal as f32;
+                            if thermal == 1 {
+                                changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 15], -100.0..=1000.0).text("Temperature")).changed() || changed;
+                            } else if thermal == 2 {
+                                changed = ui.add(egui::Slider::new(&mut self.walls[i*w + 15], -1000.0..=1000.0).text("Heat Flux")).changed() || changed;
+                            }
+                            ui.label(format!("Reaction: load {:.4e}, shear {:.4e}, speed {:.4e}", self.walls[i*w + 12], self.walls[i*w + 13], self.walls[i*w + 11]));
+                            if i > 0 {
+                                let mut join = joined;
+                                if ui.checkbox(&mut join, "Join to Previous").changed() {
+                                    self.walls[i*w + 7] = join as i32 as f32;
+                                    changed = true;
+                                }
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Add Wall").clicked() {
+                            self.add_wall(false);
+                            changed = true;
+                        }
+                        if self.wall_count() > 0 && ui.button("Extend Polyline").clicked() {
+                            self.add_wall(true);
+                            changed = true;
+                        }
+                    });
+                    if let Some(i) = removed {
+                        self.remove_wall(i);
+                        changed = true;
+                    }
+                    if changed {
+                        self.join_walls();
+                        self.walls_changed = true;
+                        self.changed_collision_settings = true;
+                    }
+                    ui.separator();
+                    // emitters and sinks are read on the CPU each frame, nothing needs uploading when they change
+                    let e = self.emitter_size;
+                    let materials = (self.materials.len()/self.material_size) as i32;
+                    let mut removed = None;
+                    for i in 0..self.emitter_count() {
+                        egui::CollapsingHeader::new(format!("Emitter {}", i + 1)).id_source(format!("emitter {i}")).show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Corner");
+                                ui.add(egui::DragValue::new(&mut self.emitters[i*e + 0]).speed(0.01));
+                                ui.add(egui::DragValue::new(&mut self.emitters[i*e + 1]).speed(0.01));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Corner");
+                                ui.add(egui::DragValue::new(&mut self.emitters[i*e + 2]).speed(0.01));
+                                ui.add(egui::DragValue::new(&mut self.emitters[i*e + 3]).speed(0.01));
+                            });
+                            ui.add(egui::Slider::new(&mut self.emitters[i*e + 4], 0.0..=10000.0).logarithmic(true).text("Rate (1/s)"));
+                            ui.add(egui::Slider::new(&mut self.emitters[i*e + 5], 0.0001..=0.5).logarithmic(true).text("Min Radius"));
+                            ui.add(egui::Slider::new(&mut self.emitters[i*e + 6], 0.0001..=0.5).logarithmic(true).text("Max Radius"));
+                            self.emitters[i*e + 6] = self.emitters[i*e + 6].max(self.emitters[i*e + 5]);
+                            ui.horizontal(|ui| {
+                                ui.label("Velocity");
+                                ui.add(egui::DragValue::new(&mut self.emitters[i*e + 7]).speed(0.01));
+                                ui.add(egui::DragValue::new(&mut self.emitters[i*e + 8]).speed(0.01));
+                            });
+                            let mut material = self.emitters[i*e + 9] as i32;
+                            if ui.add(egui::Slider::new(&mut material, 0..=materials - 1).text("Material")).changed() {
+                                self.emitters[i*e + 9] = material as f32;
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = removed {
+                        self.emitters.drain(i*e..(i + 1)*e);
+                    }
+                    let k = self.sink_size;
+                    let mut removed = None;
+                    for i in 0..self.sink_count() {
+                        egui::CollapsingHeader::new(format!("Sink {}", i + 1)).id_source(format!("sink {i}")).show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Corner");
+                                ui.add(egui::DragValue::new(&mut self.sinks[i*k + 0]).speed(0.01));
+                                ui.add(egui::DragValue::new(&mut self.sinks[i*k + 1]).speed(0.01));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Corner");
+                                ui.add(egui::DragValue::new(&mut self.sinks[i*k + 2]).speed(0.01));
+                                ui.add(egui::DragValue::new(&mut self.sinks[i*k + 3]).speed(0.01));
+                            });
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = removed {
+                        self.sinks.drain(i*k..(i + 1)*k);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Add Emitter").clicked() {
+                            self.add_emitter();
+                        }
+                        if ui.button("Add Sink").clicked() {
+                            self.add_sink();
+                        }
+                    });
                 });
 
             }
             if self.menu.materials_menu { egui::Window::new("Materials").collapsible(false).auto_sized().show(ctx, |ui| {
                 let materials_count = self.materials.len()/self.material_size;
+                let mut removed = None;
+                ui.horizontal(|ui| {
+                    if ui.button("Load Library").clicked() { self.load_material_library(); }
+                    if ui.button("Save Library").clicked() { self.save_material_library(); }
+                });
                 for i in 0..materials_count {
                     let mat_num = i+1;
-                    egui::CollapsingHeader::new(format!("Material {mat_num}")).show(ui, |ui| {
+                    egui::CollapsingHeader::new(format!("{mat_num}: {}", self.material_names[i])).id_source(format!("material {mat_num}")).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Name");
+                            ui.text_edit_singleline(&mut self.material_names[i]);
+                        });
                         if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 0], 0.0..=1.0).text("Red")).changed() { self.materials_changed = true; };
                         if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 1], 0.0..=1.0).text("Green")).changed() { self.materials_changed = true; };
                         if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 2], 0.0..=1.0).text("Blue")).changed() { self.materials_changed = true; };
                         if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 3], 0.01..=100.0).text("Density")).changed() { self.materials_changed = true; };
-                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 4], 0.01..=100.0).text("Normal Stiffness")).changed() { self.materials_changed = true; };
-                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 5], 0.01..=100.0).text("Shear Stiffness")).changed() { self.materials_changed = true; };
+                        // the stiffnesses follow Young's modulus and the Poisson ratio, but can be set on their own afterwards
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 6], 1.0..=1000000.0).logarithmic(true).text("Young's Modulus")).changed() { self.derive_stiffness(i); };
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 7], 0.0..=0.49).text("Poisson Ratio")).changed() { self.derive_stiffness(i); };
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 4], 0.01..=1000000.0).logarithmic(true).text("Normal Stiffness")).changed() { self.derive_material_pairs(i); };
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 5], 0.01..=1000000.0).logarithmic(true).text("Shear Stiffness")).changed() { self.derive_material_pairs(i); };
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 8], 0.0..=1.0).text("Friction Coef.")).changed() { self.derive_material_pairs(i); };
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 16], 0.01..=1.0).text("Restitution")).changed() { self.derive_material_pairs(i); };
+                        // seeds the cohesion of this material's pairs, a mixed pair gets none unless it's given a model in Material Pairs
+                        let mut cohesion = CohesionModel::from_material(self.materials[i*self.material_size + 9]);
+                        let mut changed_cohesion = false;
+                        egui::ComboBox::from_id_source(format!("cohesion {mat_num}"))
+                        .selected_text(format!("Cohesion: {:?}", cohesion))
+                        .show_ui(ui, |ui| {
+                            changed_cohesion = changed_cohesion || ui.selectable_value(&mut cohesion, CohesionModel::None, "None").changed();
+                            changed_cohesion = changed_cohesion || ui.selectable_value(&mut cohesion, CohesionModel::JKR, "JKR Adhesion").changed();
+                            changed_cohesion = changed_cohesion || ui.selectable_value(&mut cohesion, CohesionModel::DMT, "DMT Adhesion").changed();
+                            changed_cohesion = changed_cohesion || ui.selectable_value(&mut cohesion, CohesionModel::Capillary, "Capillary Bridge").changed();
+                        });
+                        if changed_cohesion {
+                            self.materials[i*self.material_size + 9] = cohesion as i32 as f32;
+                            self.derive_material_cohesion(i);
+                        }
+                        if cohesion == CohesionModel::JKR || cohesion == CohesionModel::DMT {
+                            if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 10], 0.0..=100.0).logarithmic(true).text("Surface Energy")).changed() { self.derive_material_cohesion(i); };
+                        }
+                        if cohesion == CohesionModel::Capillary {
+                            if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 11], 0.0..=0.001).logarithmic(true).text("Liquid Volume")).changed() { self.derive_material_cohesion(i); };
+                            if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 12], 0.0..=100.0).logarithmic(true).text("Surface Tension")).changed() { self.derive_material_cohesion(i); };
+                            if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 13], 0.0..=89.0).text("Contact Angle (deg)")).changed() { self.derive_material_cohesion(i); };
+                        }
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 14], 0.0..=1000.0).logarithmic(true).text("Conductivity")).changed() { self.materials_changed = true; };
+                        if ui.add(egui::Slider::new(&mut self.materials[i*self.material_size + 15], 0.01..=10000.0).logarithmic(true).text("Specific Heat")).changed() { self.materials_changed = true; };
+                        if ui.add_enabled(materials_count > 1, egui::Button::new("Remove Material")).clicked() {
+                            removed = Some(i);
+                        }
                     });
                 }
+                if let Some(i) = removed {
+                    self.remove_material(i);
+                }
+                if ui.button("Add Material").clicked() {
+                    self.add_material();
+                }
+                // one field of every pair at a time, the table is symmetric so only the upper half is editable
+                egui::CollapsingHeader::new("Material Pairs").show(ui, |ui| {
+                    egui::ComboBox::from_label("Pair Property")
+                    .selected_text(format!("{:?}", self.pair_property))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Normal_Stiffness, "Normal Stiffness");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Shear_Stiffness, "Shear Stiffness");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Friction, "Friction Coef.");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Restitution, "Restitution");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Cohesion, "Cohesion");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Cohesion_Model, "Cohesion Model");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Adhesion, "Work of Adhesion");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Liquid_Volume, "Liquid Volume");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Surface_Tension, "Surface Tension");
+                        ui.selectable_value(&mut self.pair_property, PairProperty::Contact_Angle, "Contact Angle (deg)");
+                    });
+                    let field = self.pair_property as usize;
+                    let range = match self.pair_property {
+                        PairProperty::Normal_Stiffness | PairProperty::Shear_Stiffness => 0.01..=1000000.0,
+                        PairProperty::Friction => 0.0..=1.0,
+                        PairProperty::Restitution => 0.01..=1.0,
+                        PairProperty::Cohesion => 0.0..=10.0,
+                        PairProperty::Cohesion_Model => 0.0..=3.0,
+                        PairProperty::Adhesion | PairProperty::Surface_Tension => 0.0..=200.0,
+                        PairProperty::Liquid_Volume => 0.0..=0.001,
+                        PairProperty::Contact_Angle => 0.0..=89.0,
+                    };
+                    // liquid volumes are tiny next to everything else
+                    let (speed, digits) = match self.pair_property {
+                        PairProperty::Liquid_Volume => (0.000001, 6),
+                        _ => (0.01, 3),
+                    };
+                    if self.pair_property == PairProperty::Cohesion {
+                        ui.label("Scales whatever the pair's cohesion model works out");
+                    }
+                    let count = self.material_count();
+                    egui::Grid::new("material pairs").show(ui, |ui| {
+                        ui.label("");
+                        for b in 0..count {
+                            ui.label(format!("{}", b + 1));
+                        }
+                        ui.end_row();
+                        for a in 0..count {
+                            ui.label(format!("{}", a + 1));
+                            for b in 0..count {
+                                let mut pair = self.pair(a, b).to_vec();
+                                if self.pair_property == PairProperty::Cohesion_Model {
+                                    let mut model = CohesionModel::from_material(pair[field]);
+                                    if b < a {
+                                        ui.label(format!("{:?}", model));
+                                        continue;
+                                    }
+                                    let mut changed = false;
+                                    egui::ComboBox::from_id_source(format!("pair cohesion {a} {b}"))
+                                    .selected_text(format!("{:?}", model))
+                                    .show_ui(ui, |ui| {
+                                        changed = changed || ui.selectable_value(&mut model, CohesionModel::None, "None").changed();
+                                        changed = changed || ui.selectable_value(&mut model, CohesionModel::JKR, "JKR Adhesion").changed();
+                                        changed = changed || ui.selectable_value(&mut model, CohesionModel::DMT, "DMT Adhesion").changed();
+                                        changed = changed || ui.selectable_value(&mut model, CohesionModel::Capillary, "Capillary Bridge").changed();
+                                    });
+                                    if changed {
+                                        pair[field] = model as i32 as f32;
+                                        self.set_pair(a, b, &pair);
+                                        self.materials_changed = true;
+                                    }
+                                } else if b < a {
+                                    ui.label(format!("{:.*}", digits, pair[field]));
+                                } else if ui.add(egui::DragValue::new(&mut pair[field]).speed(speed).max_decimals(digits).clamp_range(range.clone())).changed() {
+                                    self.set_pair(a, b, &pair);
+                                    self.materials_changed = true;
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
             });}
             if self.menu.data_menu {
                 egui::Window::new("Data").collapsible(false).resizable(true).show(ctx, |ui| {
                     let mut plot = egui::plot::Plot::new("physics plot").auto_bounds_x().auto_bounds_y().clamp_grid(true);
+                    if self.plotted_prop == Property::Energy {
+                        plot = plot.legend(egui::plot::Legend::default());
+                    }
                     let button = egui::Button::new("Reset View");
                     egui::ComboBox::from_label("Property")
                             .selected_text(format!("{:?}", self.plotted_prop))
@@ -601,12 +1935,18 @@ impl Settings {
                                 ui.selectable_value(&mut self.plotted_prop, Property::Y_Velocity, "Y Velocity");
                                 ui.selectable_value(&mut self.plotted_prop, Property::Rotation, "Rotation");
                                 ui.selectable_value(&mut self.plotted_prop, Property::Rotational_Velocity, "Rotational Velocity");
-                                ui.selectable_value(&mut self.plotted_prop, Property::Data_1, "Data 1");
-                                ui.selectable_value(&mut self.plotted_prop, Property::Data_2, "Data 2");
-                                ui.selectable_value(&mut self.plotted_prop, Property::Data_3, "Data 3");
-                                ui.selectable_value(&mut self.plotted_prop, Property::Data_4, "Data 4");
+                                ui.selectable_value(&mut self.plotted_prop, Property::Stress_XX, "Stress XX");
+                                ui.selectable_value(&mut self.plotted_prop, Property::Stress_YY, "Stress YY");
+                                ui.selectable_value(&mut self.plotted_prop, Property::Stress_XY, "Stress XY");
+                                ui.selectable_value(&mut self.plotted_prop, Property::Mean_Stress, "Mean Stress");
+                                ui.selectable_value(&mut self.plotted_prop, Property::Temperature, "Temperature");
+                                ui.selectable_value(&mut self.plotted_prop, Property::Energy, "Energy");
                             });
                     if ui.add(button).clicked() { plot = plot.reset() }
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Bond Breakages: {}", self.bond_breaks));
+                        if ui.button("Export Bond Breaks").clicked() { self.export_bond_events(); }
+                    });
                     plot.show(ui, |plot_ui| {
                         match self.plotted_prop {
                             Property::X_Position => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.x_pos_data.to_owned())));},
@@ -615,10 +1955,22 @@ impl Settings {
                             Property::Y_Velocity => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.y_vel_data.to_owned())));},
                             Property::Rotation => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.rot_data.to_owned())));},
                             Property::Rotational_Velocity => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.rot_vel_data.to_owned())));},
-                            Property::Data_1 => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.data1.to_owned())));},
-                            Property::Data_2 => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.data2.to_owned())));},
-                            Property::Data_3 => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.data3.to_owned())));},
-                            Property::Data_4 => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.data4.to_owned())));},
+                            Property::Stress_XX => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.stress_xx_data.to_owned())));},
+                            Property::Stress_YY => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.stress_yy_data.to_owned())));},
+                            Property::Stress_XY => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.stress_xy_data.to_owned())));},
+                            Property::Mean_Stress => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.mean_stress_data.to_owned())));},
+                            Property::Temperature => {plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(self.data.temperature_data.to_owned())));},
+                            Property::Energy => {
+                                let lines = self.data.energy_lines();
+                                let stacked = lines.len().min(6);
+                                // draw the top of the stack first so the lower bands fill over it
+                                for (name, points) in lines[0..stacked].iter().rev() {
+                                    plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(points.to_owned())).fill(0.0).name(*name));
+                                }
+                                for (name, points) in &lines[stacked..] {
+                                    plot_ui.line(egui::plot::Line::new(egui::plot::PlotPoints::from(points.to_owned())).name(*name));
+                                }
+                            },
                         }
                     });
                 });
@@ -673,9 +2025,101 @@ impl Settings {
         // }
     }
 
+    pub fn load_accelerogram(&mut self) {
+        let path = FileDialog::new()
+            .set_location("~/OneDrive/Code/WASM/Engine Programs/Particle-Physics-Sim/saved_states")
+            .add_filter("Text File", &["txt", "csv", "dat"])
+            .show_open_single_file()
+            .unwrap();
+
+        match path {
+            Some(path) => {
+                match self.excitation.load_accelerogram(path) {
+                    Ok(()) => {},
+                    Err(error) => {
+                        MessageDialog::new()
+                            .set_type(MessageType::Error)
+                            .set_title("Couldn't load accelerogram")
+                            .set_text(&error.to_string())
+                            .show_alert()
+                            .unwrap();
+                    },
+                }
+            },
+            None => {},
+        };
+    }
+
+    // Materials with the same name as one in the library are replaced, the rest of the library is added on, see library::load
+    pub fn load_material_library(&mut self) {
+        let path = FileDialog::new()
+            .set_location("~/OneDrive/Code/WASM/Engine Programs/Particle-Physics-Sim/saved_states")
+            .add_filter("TOML File", &["toml"])
+            .show_open_single_file()
+            .unwrap();
+
+        match path {
+            Some(path) => {
+                match library::load(self, path) {
+                    Ok(_) => {},
+                    Err(error) => {
+                        MessageDialog::new()
+                            .set_type(MessageType::Error)
+                            .set_title("Couldn't load material library")
+                            .set_text(&error.to_string())
+                            .show_alert()
+                            .unwrap();
+                    },
+                }
+            },
+            None => {},
+        };
+    }
+
+    pub fn save_material_library(&mut self) {
+        let path = FileDialog::new()
+            .set_location("~/OneDrive/Code/WASM/Engine Programs/Particle-Physics-Sim/saved_states")
+            .add_filter("TOML File", &["toml"])
+            .show_save_single_file()
+            .unwrap();
+
+        match path {
+            Some(path) => {
+                match library::save(self, path) {
+                    Ok(()) => {},
+                    Err(error) => {
+                        MessageDialog::new()
+                            .set_type(MessageType::Error)
+                            .set_title("Couldn't save material library")
+                            .set_text(&error.to_string())
+                            .show_alert()
+                            .unwrap();
+                    },
+                }
+            },
+            None => {},
+        };
+    }
+
+    pub fn export_bond_events(&mut self) {
+        let path = FileDialog::new()
+            .set_location("~/OneDrive/Code/WASM/Engine Programs/Particle-Physics-Sim/saved_states")
+            .add_filter("CSV File", &["csv"])
+            .show_save_single_file()
+            .unwrap();
+
+        match path {
+            Some(path) => {
+                self.bond_event_file = path.clone();
+                self.export_bond_events = true;
+            },
+            None => {},
+        };
+    }
+
     pub fn collison_settings(&mut self) -> Vec<f32> {
         self.changed_collision_settings = false;
-        return vec![
+        let mut settings = vec![
             self.hor_bound,
             self.vert_bound,
             bytemuck::cast(self.gravity as i32),
@@ -686,13 +2130,99 @@ impl Settings {
             self.friction_coefficient,
             bytemuck::cast(self.rotation as i32),
             bytemuck::cast(self.linear_contact_bonds as i32),
-            self.gravity_acceleration,
+            self.gravity_x,
+            self.gravity_y,
             self.stiffness,
             bytemuck::cast(self.bond_tearing as i32),
             self.bond_force_limit,
-            self.damping,
+            self.force_scale,
             self.bond_shear_limit,
+            self.coll_cell_size,
+            bytemuck::cast(self.coll_grid_w as i32),
+            bytemuck::cast(self.coll_grid_h as i32),
+            self.timestep(),
+            bytemuck::cast(self.contact_model),
+            bytemuck::cast(self.rolling_model),
+            self.rolling_coefficient,
+            bytemuck::cast(self.wall_count() as i32),
+            self.wall_elasticity,
+            self.wall_rot_damping,
+            self.bond_normal_stiffness,
+            self.bond_shear_stiffness,
+            self.bond_radius_multiplier,
+            self.bond_tensile_strength,
+            self.bond_cohesion,
+            self.bond_friction_angle.to_radians(),
+            bytemuck::cast(self.boundary_x),
+            bytemuck::cast(self.boundary_y),
+            self.shear_velocity,
+            bytemuck::cast(self.damping_model),
+            self.local_damping,
+            self.contact_restitution,
         ];
+        settings.extend_from_slice(&self.excitation.base_motion(self.sim_time));
+        settings.extend_from_slice(&self.excitation.wall_step);
+        settings.push(bytemuck::cast(self.self_gravity as i32));
+        settings.push(bytemuck::cast(self.heat_conduction as i32));
+        settings.push(bytemuck::cast((self.fluid.flow != FluidFlow::None) as i32));
+        settings.push(self.fluid.density);
+        settings.push(bytemuck::cast(self.particles as i32));
+        return settings;
+    }
+
+    // Sets the damping model from a saved scenario, keeping the combo box in step.
+    pub fn set_damping(&mut self, model: i32, local_damping: f32, contact_restitution: f32) {
+        self.damping_model = model;
+        self.damping_enum = match model {
+            1 => DampingModel::Local,
+            2 => DampingModel::Viscous,
+            3 => DampingModel::Local_Viscous,
+            _ => DampingModel::None,
+        };
+        self.local_damping = local_damping;
+        self.contact_restitution = contact_restitution;
+        self.set_restitution();
+    }
+
+    // Damping ratio of the viscous contact dashpots, picked so a head-on linear collision bounces back at restitution.
+    // It comes out a little livelier than that since the dashpot isn't allowed to pull. Zero when viscous damping is off.
+    pub fn damping_ratio(&self, restitution: f32) -> f32 {
+        if self.damping_model & 2 == 0 || restitution >= 1.0 {
+            return 0.0;
+        }
+        if restitution <= 0.0 {
+            return 1.0;
+        }
+        let log_e = restitution.ln();
+        return -log_e/(std::f32::consts::PI*std::f32::consts::PI + log_e*log_e).sqrt();
+    }
+
+    // The most damped of contact_restitution (particles without a material) and every material pair
+    pub fn contact_damping_ratio(&self) -> f32 {
+        let mut ratio = self.damping_ratio(self.contact_restitution);
+        for k in 0..self.material_pairs.len()/self.pair_size {
+            ratio = f32::max(ratio, self.damping_ratio(self.material_pairs[k*self.pair_size + 3]));
+        }
+        return ratio;
+    }
+
+    // contact_restitution goes for every material and every pair, each can be tuned in the Materials window afterwards
+    pub fn set_restitution(&mut self) {
+        for i in 0..self.material_count() {
+            self.materials[i*self.material_size + 16] = self.contact_restitution;
+        }
+        for k in 0..self.material_pairs.len()/self.pair_size {
+            self.material_pairs[k*self.pair_size + 3] = self.contact_restitution;
+        }
+        self.materials_changed = true;
+    }
+
+    // Instances per particle in the main render pass, periodic boundaries add ghost copies across the seams (see 2D_Render.wgsl)
+    pub fn ghost_images(&self) -> u32 {
+        if self.boundary_x != 0 || self.boundary_y != 0 {
+            return 4;
+        }
+        return 1;
     }
 
     pub fn render_settings(&mut self) -> Vec<i32> {
@@ -706,6 +2236,12 @@ impl Settings {
             self.vert_bound.to_bits() as i32,
             self.stiffness.to_bits() as i32,
             self.random_colors as i32,
+            self.boundary_x,
+            self.boundary_y,
+            self.color_code_temp as i32,
+            self.temp_min.to_bits() as i32,
+            self.temp_max.to_bits() as i32,
+            self.particles as i32,
         ];
     }
 
@@ -727,6 +2263,8 @@ impl Settings {
             bytemuck::cast(self.properties.y_fixity as i32),
             bytemuck::cast(self.properties.rot_fixity as i32),
             self.properties.radius,
+            bytemuck::cast(self.properties.set_temperature as i32),
+            self.properties.temperature,
         ];
     }
 
@@ -738,7 +2276,8 @@ impl Settings {
         self.variable_rad = true;
         self.holeyness = 1.7;
         self.min_radius = self.max_radius/self.holeyness;
-        self.max_bonds = 4;
+        self.max_bonds = 6;
+        self.max_contacts = 8;
         self.max_h_velocity = 0.0;
         self.min_h_velocity = 0.0;
         self.max_v_velocity = 0.0;
@@ -770,7 +2309,44 @@ pub enum Structure {
     Exp4,
     Exp5,
     Exp6,
-    Mats
+    Mats,
+    Clumps,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParticleShape {
+    Disc,
+    Polygon,
+}
+
+// Presets for the clump template, Custom is whatever was edited by hand
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ClumpShape {
+    Pair,
+    Triangle,
+    Rod,
+    L_Shape,
+    Custom,
+}
+
+impl ClumpShape {
+    // x, y, radius per disc, in units of max_radius
+    pub fn template(&self) -> Vec<f32> {
+        match self {
+            ClumpShape::Pair => { return vec![-0.6, 0.0, 1.0, 0.6, 0.0, 1.0]; },
+            ClumpShape::Triangle => {
+                let mut template = vec![];
+                for i in 0..3 {
+                    let angle = std::f32::consts::PI*(0.5 + 2.0*i as f32/3.0);
+                    template.extend_from_slice(&[0.7*angle.cos(), 0.7*angle.sin(), 1.0]);
+                }
+                return template;
+            },
+            ClumpShape::Rod => { return vec![-1.8, 0.0, 1.0, -0.6, 0.0, 1.0, 0.6, 0.0, 1.0, 1.8, 0.0, 1.0]; },
+            ClumpShape::L_Shape => { return vec![-1.0, 1.2, 0.8, -1.0, 0.0, 0.8, -1.0, -1.2, 0.8, 0.2, -1.2, 0.8, 1.4, -1.2, 0.8]; },
+            ClumpShape::Custom => { return vec![0.0, 0.0, 1.0]; },
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -781,6 +2357,99 @@ pub enum BondType {
     Parallel_Linear_Contact_Bond,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum ContactModel {
+    Linear,
+    Hertz_Mindlin,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CohesionModel {
+    None,
+    JKR,
+    DMT,
+    Capillary,
+}
+
+// Which field of the material pair records the Materials window's table shows, in record order
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PairProperty {
+    Normal_Stiffness,
+    Shear_Stiffness,
+    Friction,
+    Restitution,
+    Cohesion,
+    Cohesion_Model,
+    Adhesion,
+    Liquid_Volume,
+    Surface_Tension,
+    Contact_Angle,
+}
+
+impl CohesionModel {
+    // materials keeps the model as a float, see Settings::materials
+    pub fn from_material(model: f32) -> Self {
+        return match model as i32 {
+            1 => CohesionModel::JKR,
+            2 => CohesionModel::DMT,
+            3 => CohesionModel::Capillary,
+            _ => CohesionModel::None,
+        };
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RollingResistance {
+    None,
+    Constant_Torque,
+    Elastic_Plastic,
+}
+
+// Walls is the box, Periodic wraps around to the other side.
+// Lees-Edwards is periodic in y with the image above sliding by at shear_velocity, so it only makes sense with x periodic too.
+#[derive(Debug, PartialEq)]
+pub enum Boundary {
+    Walls,
+    Periodic,
+    Lees_Edwards,
+}
+
+// Local is Cundall's non-viscous damping on each particle's resultant force and moment, Viscous puts dashpots on the contacts.
+#[derive(Debug, PartialEq)]
+pub enum DampingModel {
+    None,
+    Local,
+    Viscous,
+    Local_Viscous,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExcitationTarget {
+    None,
+    Gravity,
+    Walls,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExcitationSource {
+    Sinusoid,
+    Accelerogram,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FluidFlow {
+    None,
+    Still,
+    Uniform,
+    Shear,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DragModel {
+    Di_Felice,
+    Ergun,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Property {
     X_Position,
@@ -789,8 +2458,203 @@ pub enum Property {
     Y_Velocity,
     Rotation,
     Rotational_Velocity,
-    Data_1,
-    Data_2,
-    Data_3,
-    Data_4,
-}
\ No newline at end of file
+    Stress_XX,
+    Stress_YY,
+    Stress_XY,
+    Mean_Stress,
+    Temperature,
+    Energy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accelerogram(name: &str, text: &str) -> Excitation {
+        let path = std::env::temp_dir().join(format!("accelerogram_{}_{}.txt", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let mut excitation = Settings::new().excitation;
+        excitation.source = 1;
+        excitation.load_accelerogram(path.clone()).unwrap();
+        fs::remove_file(path).unwrap();
+        return excitation;
+    }
+
+    #[test]
+    fn accelerogram_skips_headers_and_integrates() {
+        // a 1 g pulse for a second
+        let excitation = accelerogram("headers", "time, acc\n# comment\n0 1\n1, 1\n2 0\n");
+        assert_eq!(excitation.accelerogram.len(), 12);
+        let record = &excitation.accelerogram;
+        assert_eq!(&record[4..8], &[1.0, 1.0, 1.0, 0.5]);
+        assert_eq!(&record[8..12], &[2.0, 0.0, 1.5, 1.75]);
+    }
+
+    #[test]
+    fn accelerogram_needs_two_rows() {
+        let path = std::env::temp_dir().join(format!("accelerogram_{}_short.txt", std::process::id()));
+        fs::write(&path, "0 1\n").unwrap();
+        let mut excitation = Settings::new().excitation;
+        assert!(excitation.load_accelerogram(path.clone()).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn accelerogram_holds_still_after_it_ends() {
+        let excitation = accelerogram("ends", "0 1\n1 1\n2 0\n");
+        let g = 9.81;
+        assert_eq!(excitation.motion(-1.0), (0.0, 0.0, 0.0));
+        let (acc, vel, disp) = excitation.motion(0.5);
+        assert!((acc - g).abs() < 1e-4 && (vel - 0.5*g).abs() < 1e-4 && disp > 0.0);
+        let end = excitation.motion(2.0);
+        assert_eq!(end, (0.0, 0.0, 1.75*g));
+        assert_eq!(excitation.motion(100.0), end);
+    }
+
+    #[test]
+    fn carried_walls_keep_up_with_the_box() {
+        let mut excitation = accelerogram("carried", "0 1\n1 -1\n2 0.5\n3 0\n");
+        excitation.target = 2;
+        excitation.angle = 30.0;
+        let mut walls = [0.0; 2];
+        for frame in 0..400 {
+            let time = frame as f32*0.01;
+            excitation.carry_walls(time, 7);
+            for _ in 0..7 {
+                walls[0] += excitation.wall_step[0];
+                walls[1] += excitation.wall_step[1];
+            }
+            let box_offset = excitation.base_motion(time);
+            assert!((walls[0] - box_offset[4]).abs() < 1e-4 && (walls[1] - box_offset[5]).abs() < 1e-4);
+        }
+        // switched off, they come back with the box
+        excitation.target = 0;
+        excitation.carry_walls(4.0, 7);
+        for _ in 0..7 {
+            walls[0] += excitation.wall_step[0];
+            walls[1] += excitation.wall_step[1];
+        }
+        assert!(walls[0].abs() < 1e-4 && walls[1].abs() < 1e-4);
+    }
+
+    #[test]
+    fn sinusoid_starts_at_rest_and_stays_bounded() {
+        let mut excitation = Settings::new().excitation;
+        excitation.source = 0;
+        excitation.amplitude = 0.5;
+        excitation.frequency = 2.0;
+        let (_, vel, disp) = excitation.motion(0.0);
+        assert_eq!((vel, disp), (0.0, 0.0));
+        let omega = 4.0*std::f32::consts::PI;
+        let most = 2.0*0.5*9.81/(omega*omega);
+        for step in 0..1000 {
+            let (_, _, disp) = excitation.motion(step as f32*0.01);
+            assert!(disp >= -1e-6 && disp <= most + 1e-6);
+        }
+    }
+
+    #[test]
+    fn zero_hertz_tilts_gravity_and_leaves_the_walls() {
+        let mut excitation = Settings::new().excitation;
+        excitation.source = 0;
+        excitation.amplitude = 0.5;
+        excitation.frequency = 0.0;
+        excitation.angle = 90.0;
+        excitation.target = 1;
+        for time in [0.0, 1.0, 100.0] {
+            let base = excitation.base_motion(time);
+            assert!((base[1] - 0.5*9.81).abs() < 1e-5);
+            assert_eq!(&base[2..], &[0.0; 4]);
+        }
+        excitation.target = 2;
+        for frame in 0..100 {
+            excitation.carry_walls(frame as f32, 7);
+            assert_eq!(excitation.base_motion(frame as f32), [0.0; 6]);
+            assert_eq!(excitation.wall_step, [0.0; 2]);
+        }
+    }
+
+    fn symmetric(settings: &Settings) -> bool {
+        let n = settings.material_count();
+        assert_eq!(settings.material_pairs.len(), n*n*settings.pair_size);
+        return (0..n).all(|a| (0..n).all(|b| settings.pair(a, b) == settings.pair(b, a)));
+    }
+
+    #[test]
+    fn pair_table_stays_symmetric_as_materials_come_and_go() {
+        let mut settings = Settings::new();
+        while settings.material_count() < 3 {
+            settings.add_material();
+        }
+        let m = settings.material_size;
+        settings.materials[m + 8] = 0.1;
+        settings.derive_material_pairs(1);
+        assert!(symmetric(&settings));
+        assert_eq!(settings.pair(1, 2)[2], 0.1);
+        // cohesion set in the table, (a, b) and (b, a) both
+        for (a, b) in [(0, 1), (0, 2), (1, 2)] {
+            let mut pair = settings.pair(a, b).to_vec();
+            pair[4] = (a*10 + b) as f32;
+            settings.set_pair(a, b, &pair);
+        }
+        assert!(symmetric(&settings));
+        settings.add_material();
+        assert!(symmetric(&settings));
+        assert_eq!(settings.pair(1, 2)[4], 12.0);
+        assert_eq!(settings.pair(3, 1)[4], 1.0);
+        // taking out 1 closes up the table, 2 becomes 1
+        settings.remove_material(1);
+        assert!(symmetric(&settings));
+        assert_eq!(settings.material_count(), 3);
+        assert_eq!(settings.pair(0, 1)[4], 2.0);
+        assert_eq!(settings.pair(1, 0)[4], 2.0);
+        assert!((0..3).all(|k| settings.pair(k, k)[4] == 1.0));
+    }
+
+    #[test]
+    fn mixed_pairs_take_their_cohesion_from_the_table() {
+        let mut settings = Settings::new();
+        settings.add_material();
+        let m = settings.material_size;
+        // 0 is capillary, 1 has none
+        settings.materials[9] = 3.0;
+        settings.derive_material_cohesion(0);
+        assert_eq!(settings.pair(0, 0)[5], 3.0);
+        assert_eq!(settings.pair(0, 1)[5], 0.0);
+        assert_eq!(settings.cohesion_range(0, 1, 0.01), 0.0);
+        // given one in the table, the mixed pair reaches as far as the bridge it has
+        let mut pair = settings.pair(0, 1).to_vec();
+        pair[5] = 3.0;
+        settings.set_pair(0, 1, &pair);
+        assert!(settings.cohesion_range(1, 0, 0.01) > 0.0);
+        assert_eq!(settings.cohesion_range(1, 0, 0.01), settings.cohesion_range(0, 0, 0.01));
+        // a stiffness or friction edit keeps it, a change to either material's cohesion seeds it again
+        settings.materials[m + 8] = 0.1;
+        settings.derive_material_pairs(1);
+        assert_eq!(settings.pair(0, 1)[5], 3.0);
+        settings.materials[m + 11] *= 2.0;
+        settings.derive_material_cohesion(1);
+        assert_eq!(settings.pair(0, 1)[5], 0.0);
+        assert_eq!(settings.pair(1, 1)[7], settings.materials[m + 11]);
+        assert!(symmetric(&settings));
+    }
+
+    #[test]
+    fn old_pair_tables_are_widened_from_the_materials() {
+        let mut settings = Settings::new();
+        settings.add_material();
+        let m = settings.material_size;
+        settings.materials[9] = 2.0;
+        settings.materials[m + 9] = 2.0;
+        let old: Vec<f32> = (0..4).flat_map(|k| [1.0, 2.0, 0.3, 0.5, (k/2 + k%2) as f32]).collect();
+        let (materials, names) = (settings.materials.clone(), settings.material_names.clone());
+        settings.set_materials(&materials, &old, &names, 1);
+        assert_eq!(settings.material_pairs.len(), 4*settings.pair_size);
+        for k in 0..4 {
+            let pair = settings.pair(k/2, k%2);
+            assert_eq!(&pair[..5], &old[k*5..(k + 1)*5]);
+            assert_eq!(pair[5], 2.0);
+            assert_eq!(pair[6], 2.0*settings.materials[10]);
+        }
+    }
+}
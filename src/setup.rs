@@ -2,7 +2,9 @@ use std::f32::consts::PI;
 
 use rand::Rng;
 
+use crate::grid::Grid;
 use crate::settings::{*, self};
+use crate::state::BOND_SIZE;
 
 pub fn p_count(settings: &mut Settings) -> usize {
     match settings.structure {
@@ -13,6 +15,11 @@ pub fn p_count(settings: &mut Settings) -> usize {
             return 4
         },
         settings::Structure::Random => {return settings.particles},
+        settings::Structure::Clumps => {
+            // whole clumps only
+            let discs = (settings.clump_template.len()/3).max(1);
+            return (settings.particles/discs).max(1)*discs;
+        },
         _ => {
             return 2;
         }
@@ -23,8 +30,6 @@ pub fn p_count(settings: &mut Settings) -> usize {
 pub fn grid(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot: &mut Vec<f32>, rot_vel: &mut Vec<f32>, radii: &mut Vec<f32>, fixity: &mut Vec<i32>, forces: &mut Vec<f32>, material_pointers: &mut Vec<i32>) -> (Vec<i32>, Vec<i32>){
     settings.two_part = false;
 
-    settings.materials.resize(settings.material_size*2, 0.0);
-
     // settings.materials[0] = 1.0; // Red
     // settings.materials[1] = 0.0; // Green
     // settings.materials[2] = 0.0; // Blue
@@ -79,32 +84,33 @@ pub fn grid(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
         }
     }
     // Initialize Collision Sections
-    let vert_bound = 2.0;
-    let hor_bound = vert_bound*16.0/11.0;
-    let coll_grid_w = 30;
-    let coll_grid_h = 30;
-    let coll_section_size = 100;
-    // let mut col_sec = vec![-1 as i32; coll_grid_w*coll_grid_h*coll_section_size];
+    let largest_radius = radii.iter().fold(0.0, |a: f32, &b| a.max(b));
+    let coll_grid = Grid::around(pos, 2.0*largest_radius);
     // Initialize Bonds
     let MAX_BONDS = settings.max_bonds;
-    let mut bonds = vec![-1; p_count*MAX_BONDS*3];
+    let mut bonds = vec![-1; p_count*MAX_BONDS*BOND_SIZE];
     let mut bond_info = vec![-1; p_count*2];
     let mut found_bonds = false;
     for i in 0..p_count {
         let mut col_num = 0;
-        for j in 0..p_count {
+        let mut candidates = coll_grid.neighbors(pos[i*2], pos[i*2+1]);
+        candidates.sort();
+        for j in candidates {
             if j != i {
                 if ((pos[j*2] - pos[i*2]).powf(2.0) + (pos[j*2+1] - pos[i*2+1]).powf(2.0)).powf(0.5) < radii[i] + radii[j] {
-                    if col_num < MAX_BONDS && bonds[(i*MAX_BONDS+col_num)*3] == -1 {
-                        bonds[(i*MAX_BONDS+col_num)*3] = j as i32;
+                    if col_num < MAX_BONDS && bonds[(i*MAX_BONDS+col_num)*BOND_SIZE] == -1 {
+                        bonds[(i*MAX_BONDS+col_num)*BOND_SIZE] = j as i32;
                         let delta = (pos[j*2] - pos[i*2], pos[j*2+1] - pos[i*2+1]);
                         let magnitude = (delta.0*delta.0 + delta.1*delta.1).powf(0.5);
                         let normalized_delta = (delta.0/magnitude, delta.1/magnitude);
                         let angle = normalized_delta.0.atan2(normalized_delta.1);
                         // println!("({}, {}) vs ({}, {})", normalized_delta.0, normalized_delta.1, angle.sin(), angle.cos());
-                        bonds[(i*MAX_BONDS+col_num)*3+1] = (angle).to_bits() as i32;
-                        bonds[(i*MAX_BONDS+col_num)*3+2] = (magnitude).to_bits() as i32;
-                        // println!("{}, {}, {}", bonds[(i*MAX_BONDS+col_num)*3], angle, magnitude);
+                        bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+1] = (angle).to_bits() as i32;
+                        bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+2] = (magnitude).to_bits() as i32;
+                        bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+3] = 0;
+                        bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+4] = 0;
+                        bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+5] = 0;
+                        // println!("{}, {}, {}", bonds[(i*MAX_BONDS+col_num)*BOND_SIZE], angle, magnitude);
                         col_num += 1;
                         found_bonds = true;
                     } else if col_num == MAX_BONDS{
@@ -113,34 +119,13 @@ pub fn grid(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
                 }
             }
         }
-        // let sec_id = ((pos[i*2+1] + vert_bound)/(2.0*vert_bound)*coll_grid_h as f32) as usize * coll_grid_w as usize + (((pos[i*2] + hor_bound)/(2.0*hor_bound)) * coll_grid_w as f32) as usize;
-        // for k in coll_section_size*sec_id..coll_section_size*sec_id+coll_section_size {
-        //     if(col_sec[k] == -1) {
-        //         col_sec[k] = i as i32;
-        //         break;
-        //     }
-        // }
     }
-    // let mut point_count = 0;
-    // for i in 0..coll_grid_h*coll_grid_w {
-    //     let x = i%coll_grid_w;
-    //     let y = i/coll_grid_h;
-    //     print!("Section {}({}, {}): ", i, x, y);
-    //     for j in 0..coll_section_size {
-    //         if col_sec[i*coll_section_size+j] != -1 {
-    //             print!("{},", col_sec[i*coll_section_size+j]);
-    //             point_count += 1;
-    //         }
-    //     }
-    //     print!("\n");
-    // }
-    // println!("Total Points: {}", point_count);
     let mut index = 0;
     for i in 0..p_count {
         let start = index;
         let mut length = 0;
         for j in 0..MAX_BONDS {
-            if bonds[(i*MAX_BONDS+j)*3] != -1 {
+            if bonds[(i*MAX_BONDS+j)*BOND_SIZE] != -1 {
                 length += 1;
                 index += 1;
             }
@@ -189,8 +174,8 @@ pub fn exp1(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     let magnitude = (delta.0*delta.0 + delta.1*delta.1).powf(0.5);
     let normalized_delta = (delta.0/magnitude, delta.1/magnitude);
     let angle = normalized_delta.0.atan2(normalized_delta.1);
-    return (vec![1, (angle).to_bits() as i32, (magnitude).to_bits() as i32,
-                 0, (angle+PI).to_bits() as i32, (magnitude).to_bits() as i32], // bonds
+    return (vec![1, (angle).to_bits() as i32, (magnitude).to_bits() as i32, 0, 0, 0,
+                 0, (angle+PI).to_bits() as i32, (magnitude).to_bits() as i32, 0, 0, 0], // bonds
             vec![0, 1, 
                  1, 1]); // bond_info
 }
@@ -230,7 +215,7 @@ pub fn exp2(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     forces[4] =  0.0; forces[10] =  0.0; // Y-Force Vel
     forces[5] =  0.0; forces[11] =  0.0; // Moment Vel
 
-    return (vec![-1; 2*2], vec![-1; 2*settings.max_bonds]);
+    return (vec![-1; 2*BOND_SIZE], vec![-1; 2*settings.max_bonds]);
 
 }
 
@@ -267,7 +252,7 @@ pub fn exp3(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     forces[4] =  0.0; forces[10] =  0.0; // Y-Force Vel
     forces[5] =  0.0; forces[11] =  0.0; // Moment Vel
 
-    return (vec![-1; 2*2], vec![-1; 2*settings.max_bonds]);
+    return (vec![-1; 2*BOND_SIZE], vec![-1; 2*settings.max_bonds]);
 
 }
 
@@ -304,7 +289,7 @@ pub fn exp4(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     forces[3] =  500.0; forces[9]  =  0.0; // X-Force Vel
     forces[4] =  0.0; forces[10] =  0.0; // Y-Force Vel
     forces[5] =  0.0; forces[11] =  0.0; // Moment Vel
-    return (vec![-1; 2*2], vec![-1; 2*settings.max_bonds]);
+    return (vec![-1; 2*BOND_SIZE], vec![-1; 2*settings.max_bonds]);
 
 }
 
@@ -341,7 +326,7 @@ pub fn exp5(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     forces[4] =  0.0; forces[10] =  0.0; // Y-Force Vel
     forces[5] =  0.0; forces[11] =  0.0; // Moment Vel
 
-    return (vec![-1; 2*2], vec![-1; 2*settings.max_bonds]);
+    return (vec![-1; 2*BOND_SIZE], vec![-1; 2*settings.max_bonds]);
 
 }
 
@@ -384,7 +369,7 @@ pub fn exp6(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     let magnitude = (delta.0*delta.0 + delta.1*delta.1).powf(0.5);
     let normalized_delta = (delta.0/magnitude, delta.1/magnitude);
     let angle = normalized_delta.0.atan2(normalized_delta.1);
-    return (vec![-1; 2*2], vec![-1; 2*settings.max_bonds]);
+    return (vec![-1; 2*BOND_SIZE], vec![-1; 2*settings.max_bonds]);
 }
 
 pub fn mats(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot: &mut Vec<f32>, rot_vel: &mut Vec<f32>, radii: &mut Vec<f32>, fixity: &mut Vec<i32>, forces: &mut Vec<f32>, material_pointers: &mut Vec<i32>) -> (Vec<i32>, Vec<i32>){
@@ -418,19 +403,30 @@ pub fn mats(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
 
     settings.materials.resize(settings.material_size*2, 0.0);
 
+    let m = settings.material_size;
     settings.materials[0] = 1.0; // Red
     settings.materials[1] = 0.0; // Green
     settings.materials[2] = 0.0; // Blue
     settings.materials[3] = 1.0; // Density
     settings.materials[4] = 10.0; // Normal Stiffness
     settings.materials[5] = 0.25; // Shear Stiffness
-
-    settings.materials[6] = 0.0; // Red
-    settings.materials[7] = 0.0; // Green
-    settings.materials[8] = 1.0; // Blue
-    settings.materials[9] = 1.0; // Density
-    settings.materials[10] = 10.0; // Normal Stiffness
-    settings.materials[11] = 0.25; // Shear Stiffness
+    settings.materials[6] = 1000.0; // Young's Modulus
+    settings.materials[7] = 0.25; // Poisson Ratio
+    settings.materials[8] = 0.5; // Friction Coefficient
+    settings.materials[16] = settings.contact_restitution; // Restitution
+
+    settings.materials[m+0] = 0.0; // Red
+    settings.materials[m+1] = 0.0; // Green
+    settings.materials[m+2] = 1.0; // Blue
+    settings.materials[m+3] = 1.0; // Density
+    settings.materials[m+4] = 10.0; // Normal Stiffness
+    settings.materials[m+5] = 0.25; // Shear Stiffness
+    settings.materials[m+6] = 1000.0; // Young's Modulus
+    settings.materials[m+7] = 0.25; // Poisson Ratio
+    settings.materials[m+8] = 0.5; // Friction Coefficient
+    settings.materials[m+16] = settings.contact_restitution; // Restitution
+    settings.material_names = vec!["Red".to_string(), "Blue".to_string()];
+    settings.derive_pairs();
 
     material_pointers[0] = 0;
     material_pointers[1] = 1;
@@ -441,8 +437,92 @@ pub fn mats(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot
     let magnitude = (delta.0*delta.0 + delta.1*delta.1).powf(0.5);
     let normalized_delta = (delta.0/magnitude, delta.1/magnitude);
     let angle = normalized_delta.0.atan2(normalized_delta.1);
-    return (vec![1, (angle).to_bits() as i32, (magnitude).to_bits() as i32,
-                 0, (angle+PI).to_bits() as i32, (magnitude).to_bits() as i32], // bonds
+    return (vec![1, (angle).to_bits() as i32, (magnitude).to_bits() as i32, 0, 0, 0,
+                 0, (angle+PI).to_bits() as i32, (magnitude).to_bits() as i32, 0, 0, 0], // bonds
             vec![0, 1, 
                  1, 1]); // bond_info
 }
+
+// Rigid clumps of the template's discs, laid out on a grid in the middle of the box at random angles.
+// Every clump gets one random velocity, and with Random Radius a random size between min and max radius.
+pub fn clumps(settings: &mut Settings, pos: &mut Vec<f32>, vel: &mut Vec<f32>, rot: &mut Vec<f32>, rot_vel: &mut Vec<f32>, radii: &mut Vec<f32>, fixity: &mut Vec<i32>, forces: &mut Vec<f32>, material_pointers: &mut Vec<i32>, clump_pointers: &mut Vec<i32>) -> (Vec<i32>, Vec<i32>){
+    settings.two_part = false;
+    let mut rng = rand::thread_rng();
+    let template = settings.clump_template.clone();
+    let discs = template.len()/3;
+    let count = pos.len()/2/discs;
+    // the clump's reach from its center, in units of its radius
+    let mut reach: f32 = 0.0;
+    for d in 0..discs {
+        reach = reach.max((template[d*3]*template[d*3] + template[d*3+1]*template[d*3+1]).sqrt() + template[d*3+2]);
+    }
+    let spacing = 2.0*reach*settings.max_radius*1.05;
+    let columns = ((count as f32*settings.hor_bound/settings.vert_bound).sqrt().ceil() as usize).max(1);
+    let rows = (count + columns - 1)/columns;
+    for c in 0..count {
+        let center = (
+            ((c%columns) as f32 - 0.5*(columns - 1) as f32)*spacing,
+            ((c/columns) as f32 - 0.5*(rows - 1) as f32)*spacing,
+        );
+        let mut scale = settings.max_radius;
+        if settings.variable_rad && settings.min_radius < settings.max_radius {
+            scale = rng.gen_range(settings.min_radius..settings.max_radius);
+        }
+        let angle: f32 = rng.gen_range(0.0..2.0*PI);
+        let (sin, cos) = angle.sin_cos();
+        let mut velocity = (settings.min_h_velocity, settings.min_v_velocity);
+        if settings.min_h_velocity < settings.max_h_velocity { velocity.0 = rng.gen_range(settings.min_h_velocity..settings.max_h_velocity); }
+        if settings.min_v_velocity < settings.max_v_velocity { velocity.1 = rng.gen_range(settings.min_v_velocity..settings.max_v_velocity); }
+        for d in 0..discs {
+            let i = c*discs + d;
+            let (x, y) = (template[d*3]*scale, template[d*3+1]*scale);
+            pos[i*2] = center.0 + x*cos - y*sin;
+            pos[i*2+1] = center.1 + x*sin + y*cos;
+            vel[i*2] = velocity.0;
+            vel[i*2+1] = velocity.1;
+            radii[i] = template[d*3+2]*scale;
+            rot[i] = angle;
+            clump_pointers[i] = c as i32;
+        }
+    }
+    return (vec![-1; BOND_SIZE], vec![-1; pos.len()]);
+}
+
+// Turns every particle into a random convex polygon: corners on its circle (so it's convex) at jittered angles,
+// then moved so the centroid sits on the particle's position and scaled back inside its radius, so nothing set up apart ends up overlapping.
+// Returns polygon_info (start, count per particle) and the vertices (x, y in the particle's frame), see State.
+pub fn polygons(settings: &Settings, radii: &Vec<f32>) -> (Vec<i32>, Vec<f32>) {
+    let mut rng = rand::thread_rng();
+    let sides = settings.polygon_sides.max(3);
+    let mut polygon_info = vec![];
+    let mut vertices = vec![];
+    for i in 0..radii.len() {
+        let step = 2.0*PI/sides as f32;
+        let start = rng.gen_range(0.0..step);
+        let mut corners = vec![];
+        for k in 0..sides {
+            let jitter = 0.45*settings.polygon_irregularity*rng.gen_range(-step..step);
+            let angle = start + k as f32*step + jitter;
+            corners.push((angle.cos(), angle.sin()));
+        }
+        let (cx, cy) = polygon_centroid(&corners);
+        let reach = corners.iter().fold(0.0, |a: f32, c| a.max(((c.0 - cx).powi(2) + (c.1 - cy).powi(2)).sqrt()));
+        polygon_info.extend_from_slice(&[(vertices.len()/2) as i32, sides as i32]);
+        for c in corners {
+            vertices.extend_from_slice(&[(c.0 - cx)*radii[i]/reach, (c.1 - cy)*radii[i]/reach]);
+        }
+    }
+    return (polygon_info, vertices);
+}
+
+fn polygon_centroid(corners: &Vec<(f32, f32)>) -> (f32, f32) {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    for k in 0..corners.len() {
+        let (a, b) = (corners[k], corners[(k + 1)%corners.len()]);
+        let cross = a.0*b.1 - b.0*a.1;
+        area += 0.5*cross;
+        cx += (a.0 + b.0)*cross/6.0;
+        cy += (a.1 + b.1)*cross/6.0;
+    }
+    return (cx/area, cy/area);
+}
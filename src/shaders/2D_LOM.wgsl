@@ -13,6 +13,67 @@ struct Forces {
     delRot: f32,
 }
 
+struct Bond_Log {
+    count: u32,
+    step: u32,
+}
+
+struct Settings {
+    hor_bound: f32,
+    vert_bound: f32,
+    gravity: i32,
+    planet_mode: i32,
+    bonds: i32,
+    collisions: i32,
+    friction: i32,
+    friction_coefficient: f32,
+    rotation: i32,
+    linear_contact_bonds: i32,
+    gravity_x: f32,
+    gravity_y: f32,
+    stiffness: f32,
+    bonds_tear: i32,
+    bond_force_limit: f32,
+    force_scale: f32,
+    bond_shear_lim: f32,
+    coll_cell_size: f32,
+    coll_grid_w: i32,
+    coll_grid_h: i32,
+    delta_time: f32,
+    contact_model: i32,
+    rolling_model: i32,
+    rolling_coefficient: f32,
+    wall_count: i32,
+    wall_elasticity: f32,
+    wall_rot_damping: f32,
+    bond_normal_stiffness: f32,
+    bond_shear_stiffness: f32,
+    bond_radius_multiplier: f32,
+    bond_tensile_strength: f32,
+    bond_cohesion: f32,
+    bond_friction_angle: f32,
+    boundary_x: i32,
+    boundary_y: i32,
+    shear_velocity: f32,
+    damping_model: i32,
+    local_damping: f32,
+    contact_restitution: f32,
+    base_acc_x: f32,
+    base_acc_y: f32,
+    base_vel_x: f32,
+    base_vel_y: f32,
+    base_offset_x: f32,
+    base_offset_y: f32,
+    base_step_x: f32,
+    base_step_y: f32,
+    self_gravity: i32,
+    heat_conduction: i32,
+    fluid: i32,
+    fluid_density: f32,
+    // active particles, the buffers past them are spare capacity, see WGPUComputeProg::upload_particles
+    particles: i32
+}
+
 @group(0) @binding(0) var<storage, read_write> positions: array<vec2<f32>>;
 @group(1) @binding(0) var<storage, read_write> velocities: array<vec2<f32>>;
 @group(1) @binding(1) var<storage, read_write> velocities_buf: array<vec2<f32>>;
@@ -22,13 +83,25 @@ struct Forces {
 @group(1) @binding(5) var<storage, read_write> acc: array<vec3<f32>>;
 @group(1) @binding(6) var<storage, read_write> fixity: array<Particle_Settings>;
 @group(1) @binding(7) var<storage, read_write> forces: array<Forces>;
+@group(1) @binding(8) var<storage, read_write> shear_offset: f32;
+@group(1) @binding(10) var<storage, read_write> temperature: array<f32>;
+@group(1) @binding(11) var<storage, read_write> temperature_buf: array<f32>;
+@group(3) @binding(8) var<storage, read_write> bond_log: Bond_Log;
+@group(4) @binding(0) var<uniform> settings: Settings;
 
-const deltaTime: f32 = 0.0000390625;
 const PI = 3.141592653589793238;
 
 @compute @workgroup_size(256)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let id: u32 = global_id.x;
+    let deltaTime = settings.delta_time;
+    // generation counter for the bond breakage log
+    if id == 0u {
+        bond_log.step += 1u;
+    }
+    if id >= u32(settings.particles) {
+        return;
+    }
 
     if fixity[id].x_vel == 0 {
         acc[id] = vec3(vec2((velocities_buf[id] - velocities[id]).x, acc[id].y), acc[id].z);
@@ -47,6 +120,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     velocities[id] += vec2(forces[id].x, forces[id].y)*deltaTime;
 
     positions[id] = positions[id] + velocities[id] * deltaTime;
+    wrap(id);
 
     if fixity[id].rot_vel == 0 {
         acc[id] = vec3(acc[id].xy, rot_vel_buf[id] - rot_vel[id]/deltaTime);
@@ -57,7 +131,29 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     rot_vel_buf[id] = rot_vel[id];
     rot[id] = (rot[id] + rot_vel[id] * deltaTime)%(2.0*PI);
 
+    temperature[id] = temperature_buf[id];
+
     forces[id].x += forces[id].delX*deltaTime;
     forces[id].y += forces[id].delY*deltaTime;
     forces[id].rot += forces[id].delRot*deltaTime;
+}
+
+// Periodic boundaries, whatever goes out one side comes back in the other.
+// Going through a Lees-Edwards seam also lands it on the slid image, and it picks up that image's velocity.
+fn wrap(id: u32) {
+    if settings.boundary_y != 0 {
+        let height = 2.0*settings.vert_bound;
+        let shift = floor((positions[id].y + settings.vert_bound)/height);
+        if shift != 0.0 {
+            positions[id].y -= shift*height;
+            if settings.boundary_y == 2 {
+                positions[id].x -= shift*shear_offset;
+                velocities[id].x -= shift*settings.shear_velocity;
+            }
+        }
+    }
+    if settings.boundary_x != 0 {
+        let width = 2.0*settings.hor_bound;
+        positions[id].x -= floor((positions[id].x + settings.hor_bound)/width)*width;
+    }
 }
\ No newline at end of file
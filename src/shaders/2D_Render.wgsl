@@ -25,6 +25,17 @@ struct Material {
     density: f32,
     normal_stiffness: f32,
     shear_stiffness: f32,
+    youngs_modulus: f32,
+    poisson_ratio: f32,
+    friction_coefficient: f32,
+    cohesion_model: f32,
+    surface_energy: f32,
+    liquid_volume: f32,
+    surface_tension: f32,
+    contact_angle: f32,
+    conductivity: f32,
+    specific_heat: f32,
+    restitution: f32,
 }
 
 struct VertexOutput {
@@ -48,13 +59,22 @@ struct Settings {
     w: f32,
     h: f32,
     stiffness: f32,
-    random_colors: i32
+    random_colors: i32,
+    boundary_x: i32,
+    boundary_y: i32,
+    color_code_temp: i32,
+    temp_min: f32,
+    temp_max: f32,
+    particles: i32
 }
 
 struct Bond {
     index: i32,
     angle: f32,
-    length: f32
+    length: f32,
+    normal_force: f32,
+    shear_force: f32,
+    moment: f32
 };
 
 @group(0) @binding(0) var<uniform> dim: Dimensions;
@@ -62,9 +82,13 @@ struct Bond {
 @group(2) @binding(0) var<storage, read_write> radii_buf: array<f32>;
 @group(3) @binding(2) var<storage, read_write> rot_buf: array<f32>;
 @group(3) @binding(3) var<storage, read_write> rot_vel: array<f32>;
+@group(3) @binding(8) var<storage, read_write> shear_offset: f32;
+@group(3) @binding(10) var<storage, read_write> temperature: array<f32>;
 @group(4) @binding(0) var<storage, read_write> bonds: array<Bond>;
 @group(4) @binding(1) var<storage, read_write> bond_info: array<vec2<i32>>;
 @group(4) @binding(4) var<storage, read_write> material_pointers: array<i32>;
+@group(4) @binding(12) var<storage, read_write> polygon_info: array<vec2<i32>>;
+@group(4) @binding(13) var<storage, read_write> polygon_vertices: array<vec2<f32>>;
 @group(5) @binding(0) var<uniform> settings: Settings;
 @group(6) @binding(0) var<storage, read_write> materials: array<Material>;
 @group(7) @binding(0) var<storage, read_write> selections: array<i32>;
@@ -76,18 +100,26 @@ fn vs_main(
     @builtin(instance_index) instance: u32,
 ) -> VertexOutput {
     var out: VertexOutput;
+    // with periodic boundaries each particle gets drawn 4 times, see ghost_position
+    let count = u32(settings.particles);
+    let id = instance % count;
+    let image = ghost_position(id, instance / count);
     let aspect = dim.width/dim.height;
     let scale= dim.scale;
     let xy = 2.0*scale*vec2(in.position.x / aspect, in.position.y);
-    let center = scale*vec2(pos_buf[instance].x / aspect, pos_buf[instance].y);
+    let center = scale*vec2(image.x / aspect, image.y);
     let off = vec2(dim.xOff / aspect, -dim.yOff)/1000.0;
-    out.clip_position = vec4(xy*radii_buf[instance] + center + off, 0.0, 1.0);
+    out.clip_position = vec4(xy*radii_buf[id] + center + off, 0.0, 1.0);
+    if image.z == 0.0 {
+        // every vertex on the same spot off screen, so nothing gets drawn
+        out.clip_position = vec4(-2.0, -2.0, 0.0, 1.0);
+    }
     out.position = in.position;
     // out.color = color_buf[instance % u32(settings.colors)];
-    if material_pointers[instance] != -1 { out.color = vec3(materials[(material_pointers[instance])].red, materials[(material_pointers[instance])].green, materials[(material_pointers[instance])].blue); }
+    if material_pointers[id] != -1 { out.color = vec3(materials[(material_pointers[id])].red, materials[(material_pointers[id])].green, materials[(material_pointers[id])].blue); }
     else { out.color = vec3(1.0, 1.0, 1.0); }
     if settings.random_colors == 1 {
-        let seed1 = u32(rand(instance, 4294967296.0));
+        let seed1 = u32(rand(id, 4294967296.0));
         let seed2 = u32(rand(seed1, 4294967296.0));
         let seed3 = u32(rand(seed2, 4294967296.0));
         out.color = vec3(
@@ -96,16 +128,68 @@ fn vs_main(
             rand(seed3, 1.0),
         );
     }
+    if settings.color_code_temp == 1 {
+        out.color = temperature_color(temperature[id]);
+    }
     // let rect_off = vec2(-dim.xOff, dim.yOff)/1000.0/scale;
-    out.rot = rot_buf[instance];
-    out.rot_vel = rot_vel[instance];
-    out.id = instance;
-    out.selected = selections[instance];
+    out.rot = rot_buf[id];
+    out.rot_vel = rot_vel[id];
+    out.id = id;
+    out.selected = selections[id];
     out.w_h = vec2(i32(dim.width), i32(dim.height));
     out.pixel = out.clip_position.xy;
     return out;
 }
 
+// Jet colormap, blue at temp_min through green to red at temp_max.
+fn temperature_color(t: f32) -> vec3<f32> {
+    var x = 0.5;
+    if settings.temp_max > settings.temp_min {
+        x = clamp((t - settings.temp_min)/(settings.temp_max - settings.temp_min), 0.0, 1.0);
+    }
+    return clamp(vec3(1.5 - abs(4.0*x - 3.0), 1.5 - abs(4.0*x - 2.0), 1.5 - abs(4.0*x - 1.0)), vec3(0.0), vec3(1.0));
+}
+
+// Ghost copies across the periodic seams, 0 is the particle itself, bit 1 the copy across the x seam and bit 2 across the y seam.
+// A copy only shows (z = 1) when the particle hangs over that seam. Across a Lees-Edwards seam it's also slid along by shear_offset.
+fn ghost_position(id: u32, image: u32) -> vec3<f32> {
+    var pos = pos_buf[id];
+    let rad = radii_buf[id];
+    var visible = 1.0;
+    if (image & 2u) != 0u {
+        if settings.boundary_y == 0 || abs(pos.y) + rad < settings.h {
+            visible = 0.0;
+        }
+        let side = sign(pos.y);
+        pos.y -= side*2.0*settings.h;
+        if settings.boundary_y == 2 {
+            pos.x -= side*shear_offset;
+            pos.x -= round(pos.x/(2.0*settings.w))*2.0*settings.w;
+        }
+    }
+    if (image & 1u) != 0u {
+        if settings.boundary_x == 0 || abs(pos.x) + rad < settings.w {
+            visible = 0.0;
+        }
+        pos.x -= sign(pos.x)*2.0*settings.w;
+    }
+    return vec3(pos, visible);
+}
+
+// How far inside its polygon a point of the quad is, in quad units (the quad is 2 radii across), negative outside
+fn polygon_depth(id: u32, position: vec2<f32>, rot: f32) -> f32 {
+    let p = 2.0*radii_buf[id]*vec2(position.x*cos(rot) + position.y*sin(rot), -position.x*sin(rot) + position.y*cos(rot));
+    let start = polygon_info[id].x;
+    let count = polygon_info[id].y;
+    var depth = 1e30;
+    for(var k = 0; k < count; k++){
+        let v = polygon_vertices[start + k];
+        let edge = polygon_vertices[start + (k + 1)%count] - v;
+        depth = min(depth, dot(v - p, normalize(vec2(edge.y, -edge.x))));
+    }
+    return depth/(2.0*radii_buf[id]);
+}
+
 fn rand(seed: u32, max: f32) -> f32{
     //PCG Hash
     var res = seed;
@@ -121,9 +205,16 @@ const PI = 3.141592653589793238;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // discard corners to make circle
+    // discard corners to make circle, or cut the polygon out
     let len = length(in.position);
-    if settings.circular_particles == 1 {
+    let polygon = polygon_info[in.id].y > 2;
+    var depth = 0.0;
+    if polygon {
+        depth = polygon_depth(in.id, in.position, in.rot);
+        if depth < 0.0 {
+            discard;
+        }
+    } else if settings.circular_particles == 1 {
         if len > 0.5 {
             discard;
         }
@@ -144,9 +235,9 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     }
 
     // add border/outline
-    if settings.circular_particles == 1 {
-        let border_width = 0.08;
-        if len > 0.5-border_width && len < 0.5 {
+    let border_width = 0.08;
+    if settings.circular_particles == 1 || polygon {
+        if (!polygon && len > 0.5-border_width && len < 0.5) || (polygon && depth < border_width) {
             if in.selected == 1 {
                 color = vec4(1.0, 0.8, 0.0, 1.0);
             } else if in.selected == 2 {
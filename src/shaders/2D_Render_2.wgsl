@@ -25,6 +25,17 @@ struct Material {
     density: f32,
     normal_stiffness: f32,
     shear_stiffness: f32,
+    youngs_modulus: f32,
+    poisson_ratio: f32,
+    friction_coefficient: f32,
+    cohesion_model: f32,
+    surface_energy: f32,
+    liquid_volume: f32,
+    surface_tension: f32,
+    contact_angle: f32,
+    conductivity: f32,
+    specific_heat: f32,
+    restitution: f32,
 }
 
 struct VertexOutput {
@@ -53,7 +64,10 @@ struct Settings {
 struct Bond {
     index: i32,
     angle: f32,
-    length: f32
+    length: f32,
+    normal_force: f32,
+    shear_force: f32,
+    moment: f32
 };
 
 @group(0) @binding(0) var<uniform> dim: Dimensions;
@@ -22,6 +22,17 @@ struct Material {
     density: f32,
     normal_stiffness: f32,
     shear_stiffness: f32,
+    youngs_modulus: f32,
+    poisson_ratio: f32,
+    friction_coefficient: f32,
+    cohesion_model: f32,
+    surface_energy: f32,
+    liquid_volume: f32,
+    surface_tension: f32,
+    contact_angle: f32,
+    conductivity: f32,
+    specific_heat: f32,
+    restitution: f32,
 }
 
 struct VertexOutput {
@@ -48,7 +59,10 @@ struct Settings {
 struct Bond {
     index: i32,
     angle: f32,
-    length: f32
+    length: f32,
+    normal_force: f32,
+    shear_force: f32,
+    moment: f32
 };
 
 @group(0) @binding(0) var<uniform> dim: Dimensions;
@@ -60,6 +74,8 @@ struct Bond {
 @group(4) @binding(0) var<storage, read_write> bonds: array<Bond>;
 @group(4) @binding(1) var<storage, read_write> bond_info: array<vec2<i32>>;
 @group(4) @binding(4) var<storage, read_write> material_pointers: array<i32>;
+@group(4) @binding(12) var<storage, read_write> polygon_info: array<vec2<i32>>;
+@group(4) @binding(13) var<storage, read_write> polygon_vertices: array<vec2<f32>>;
 @group(5) @binding(0) var<uniform> settings: Settings;
 @group(6) @binding(0) var<storage, read_write> materials: array<Material>;
 @group(7) @binding(0) var<storage, read_write> selections: array<i32>;
@@ -90,9 +106,13 @@ const PI = 3.141592653589793238;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // discard corners to make circle
+    // discard corners to make circle, or cut the polygon out
     let len = length(in.position);
-    if settings.circular_particles == 1 {
+    if polygon_info[in.id - 1u].y > 2 {
+        if polygon_depth(in.id - 1u, in.position, in.rot) < 0.0 {
+            discard;
+        }
+    } else if settings.circular_particles == 1 {
         if len > 0.5 {
             discard;
         }
@@ -110,6 +130,20 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     );
 }
 
+// How far inside its polygon a point of the quad is, in quad units (the quad is 2 radii across), negative outside
+fn polygon_depth(id: u32, position: vec2<f32>, rot: f32) -> f32 {
+    let p = 2.0*radii_buf[id]*vec2(position.x*cos(rot) + position.y*sin(rot), -position.x*sin(rot) + position.y*cos(rot));
+    let start = polygon_info[id].x;
+    let count = polygon_info[id].y;
+    var depth = 1e30;
+    for(var k = 0; k < count; k++){
+        let v = polygon_vertices[start + k];
+        let edge = polygon_vertices[start + (k + 1)%count] - v;
+        depth = min(depth, dot(v - p, normalize(vec2(edge.y, -edge.x))));
+    }
+    return depth/(2.0*radii_buf[id]);
+}
+
 fn linear_to_srgb(value: f32) -> f32 {
     if (value <= 0.0031308) {
         return 12.92 * value;
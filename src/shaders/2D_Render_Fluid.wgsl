@@ -0,0 +1,61 @@
+struct VertexIn {
+    @location(0) position: vec2<f32>,
+};
+
+struct Dimensions {
+    width: f32, time: f32,
+    height: f32, temp: f32,
+    xOff: f32, yOff: f32,
+    scale: f32, dark: f32,
+    x: i32, y: i32,
+    rW: i32, rH: i32,
+    pressed: i32
+}
+
+// See FluidGrid::overlay
+struct Arrow {
+    start: vec2<f32>,
+    end: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) position: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> dim: Dimensions;
+@group(1) @binding(0) var<storage, read_write> arrows: array<Arrow>;
+
+// One instance per fluid cell, the quad is pinched to a point at the end so it reads as a wedge pointing downstream.
+const HALF_WIDTH = 3.0; // pixels
+
+@vertex
+fn vs_main(
+    in: VertexIn,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let aspect = dim.width/dim.height;
+    let scale = dim.scale;
+    let off = vec2(dim.xOff / aspect, -dim.yOff)/1000.0;
+    let start = scale*vec2(arrows[instance].start.x / aspect, arrows[instance].start.y) + off;
+    let end = scale*vec2(arrows[instance].end.x / aspect, arrows[instance].end.y) + off;
+
+    let pixels = vec2(dim.width, dim.height)/2.0;
+    var dir = vec2(1.0, 0.0);
+    if length((end - start)*pixels) > 0.0 {
+        dir = normalize((end - start)*pixels);
+    }
+    let normal = vec2(-dir.y, dir.x);
+    let t = (in.position.x + 1.0)/2.0;
+    let along = mix(start, end, t);
+    let width = normal*in.position.y*(1.0 - t)*HALF_WIDTH/pixels;
+    out.clip_position = vec4(along + width, 0.0, 1.0);
+    out.position = in.position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(0.3, 0.7, 1.0, 1.0);
+}
@@ -0,0 +1,71 @@
+struct VertexIn {
+    @location(0) position: vec2<f32>,
+};
+
+struct Dimensions {
+    width: f32, time: f32,
+    height: f32, temp: f32,
+    xOff: f32, yOff: f32,
+    scale: f32, dark: f32,
+    x: i32, y: i32,
+    rW: i32, rH: i32,
+    pressed: i32
+}
+
+struct Wall {
+    start: vec2<f32>,
+    end: vec2<f32>,
+    stiffness: f32,
+    friction: f32,
+    restitution: f32,
+    joined: f32,
+    control: f32,
+    setpoint: f32,
+    gain: f32,
+    speed: f32,
+    load: f32,
+    shear: f32,
+    thermal: f32,
+    heat: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) position: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> dim: Dimensions;
+@group(1) @binding(0) var<storage, read_write> walls: array<Wall>;
+
+// One instance per wall, the quad gets stretched along the segment and kept a few pixels wide at any zoom.
+const HALF_WIDTH = 1.5; // pixels
+
+@vertex
+fn vs_main(
+    in: VertexIn,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let aspect = dim.width/dim.height;
+    let scale = dim.scale;
+    let off = vec2(dim.xOff / aspect, -dim.yOff)/1000.0;
+    let start = scale*vec2(walls[instance].start.x / aspect, walls[instance].start.y) + off;
+    let end = scale*vec2(walls[instance].end.x / aspect, walls[instance].end.y) + off;
+
+    let pixels = vec2(dim.width, dim.height)/2.0;
+    var dir = vec2(1.0, 0.0);
+    if length((end - start)*pixels) > 0.0 {
+        dir = normalize((end - start)*pixels);
+    }
+    let normal = vec2(-dir.y, dir.x);
+    let along = mix(start, end, (in.position.x + 1.0)/2.0);
+    let width = (dir*in.position.x + normal*in.position.y)*HALF_WIDTH/pixels;
+    out.clip_position = vec4(along + width, 0.0, 1.0);
+    out.position = in.position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(0.6, 0.6, 0.6, 1.0);
+}
@@ -17,15 +17,88 @@ struct Contact {
     a: i32,
     b: i32,
     tangent_force: f32,
-    bonded: i32
+    bonded: i32,
+    rolling_moment: f32,
+    // 1 once the pair has touched, JKR necks and liquid bridges only form then (see cohesion)
+    touched: i32
 };
 
+// See State::regen_bonds, the last three are only used by the parallel bond
 struct Bond {
     index: i32,
     angle: f32,
-    length: f32
+    length: f32,
+    normal_force: f32,
+    shear_force: f32,
+    moment: f32
 };
 
+// Torn bonds get appended to the log and drained by State::update_state, see BondEvent
+struct Bond_Event {
+    step: u32,
+    a: i32,
+    b: i32,
+    x: f32,
+    y: f32,
+    mode: i32,
+}
+
+struct Bond_Log {
+    count: atomic<u32>,
+    step: u32, // counted up in 2D_LOM.wgsl
+    events: array<Bond_Event>,
+}
+
+// Particles that had more touching than MAX_CONTACTS or more bonds than MAX_BONDS this step,
+// energy_budget keeps the most in any one step until State::update_state reads them and clears it
+struct Overflow {
+    contacts: atomic<u32>,
+    bonds: atomic<u32>,
+    most_contacts: u32,
+    most_bonds: u32,
+}
+
+// Per particle energies, the last three are what got dissipated or put in during this step.
+// energy_budget sums them into energy_total, keeping a running total of the last three, see State::energy.
+struct Energy {
+    kinetic: f32,
+    rotational: f32,
+    strain: f32,
+    gravity: f32,
+    friction: f32,
+    damping: f32,
+    work: f32,
+}
+
+// Filled in on the CPU from a Barnes-Hut tree once a frame, see barnes_hut.rs
+struct Self_Gravity {
+    x: f32,
+    y: f32,
+    potential: f32,
+}
+
+// fluid velocity and drag per unit mass, see fluid::drag
+struct Fluid_Drag {
+    u_x: f32,
+    u_y: f32,
+    rate: f32,
+}
+
+// Where a disc sits in its clump, x and y from the clump's center of mass and its rotation less the clump's
+struct Clump_Member {
+    id: i32,
+    x: f32,
+    y: f32,
+    rot: f32,
+}
+
+// How two shapes touch, normal points from b to a and point is the middle of the overlap, relative to a's position
+struct Shape_Contact {
+    normal: vec2<f32>,
+    point: vec2<f32>,
+    overlap: f32,
+}
+
 struct Settings {
     hor_bound: f32,
     vert_bound: f32,
@@ -37,12 +110,49 @@ struct Settings {
     friction_coefficient: f32,
     rotation: i32,
     linear_contact_bonds: i32,
-    gravity_acc: f32, // make this  a vector with an x and y acceleration 
+    gravity_x: f32,
+    gravity_y: f32,
     stiffness: f32,
     bonds_tear: i32,
     bond_force_limit: f32,
-    damping: f32,
-    bond_shear_lim: f32
+    force_scale: f32,
+    bond_shear_lim: f32,
+    coll_cell_size: f32,
+    coll_grid_w: i32,
+    coll_grid_h: i32,
+    delta_time: f32,
+    contact_model: i32,
+    rolling_model: i32,
+    rolling_coefficient: f32,
+    wall_count: i32,
+    wall_elasticity: f32,
+    wall_rot_damping: f32,
+    bond_normal_stiffness: f32,
+    bond_shear_stiffness: f32,
+    bond_radius_multiplier: f32,
+    bond_tensile_strength: f32,
+    bond_cohesion: f32,
+    bond_friction_angle: f32,
+    boundary_x: i32,
+    boundary_y: i32,
+    shear_velocity: f32,
+    damping_model: i32,
+    local_damping: f32,
+    contact_restitution: f32,
+    base_acc_x: f32,
+    base_acc_y: f32,
+    base_vel_x: f32,
+    base_vel_y: f32,
+    base_offset_x: f32,
+    base_offset_y: f32,
+    base_step_x: f32,
+    base_step_y: f32,
+    self_gravity: i32,
+    heat_conduction: i32,
+    fluid: i32,
+    fluid_density: f32,
+    // active particles, the buffers past them are spare capacity, see WGPUComputeProg::upload_particles
+    particles: i32
 }
 
 struct Material {
@@ -52,6 +162,50 @@ struct Material {
     density: f32,
     normal_stiffness: f32,
     shear_stiffness: f32,
+    youngs_modulus: f32,
+    poisson_ratio: f32,
+    friction_coefficient: f32,
+    cohesion_model: f32,
+    surface_energy: f32,
+    liquid_volume: f32,
+    surface_tension: f32,
+    contact_angle: f32,
+    conductivity: f32,
+    specific_heat: f32,
+    restitution: f32,
+}
+
+// See Settings::derive_pair, one per ordered pair of materials
+struct Material_Pair {
+    normal_stiffness: f32,
+    shear_stiffness: f32,
+    friction_coefficient: f32,
+    restitution: f32,
+    cohesion: f32,
+    // see cohesion(), set from the two materials or in the table
+    cohesion_model: f32,
+    adhesion: f32,
+    liquid_volume: f32,
+    surface_tension: f32,
+    contact_angle: f32,
+}
+
+// See Settings::add_wall
+struct Wall {
+    start: vec2<f32>,
+    end: vec2<f32>,
+    stiffness: f32,
+    friction: f32,
+    restitution: f32,
+    joined: f32,
+    control: f32,
+    setpoint: f32,
+    gain: f32,
+    speed: f32,
+    load: f32,
+    shear: f32,
+    thermal: f32,
+    heat: f32,
 }
 
 @group(0) @binding(0) var<storage, read_write> positions: array<vec2<f32>>;
@@ -63,34 +217,52 @@ struct Material {
 @group(1) @binding(5) var<storage, read_write> acc: array<vec3<f32>>;
 @group(1) @binding(6) var<storage, read_write> fixity: array<Particle_Settings>;
 @group(1) @binding(7) var<storage, read_write> forces: array<Forces>;
+@group(1) @binding(8) var<storage, read_write> shear_offset: f32;
+@group(1) @binding(9) var<storage, read_write> self_gravity: array<Self_Gravity>;
+@group(1) @binding(10) var<storage, read_write> temperature: array<f32>;
+@group(1) @binding(11) var<storage, read_write> temperature_buf: array<f32>;
+@group(1) @binding(12) var<storage, read_write> fluid_drag: array<Fluid_Drag>;
 @group(2) @binding(0) var<storage, read_write> radii: array<f32>;
 @group(3) @binding(0) var<storage, read_write> bonds: array<Bond>;
 @group(3) @binding(1) var<storage, read_write> bond_info: array<vec2<i32>>;
 @group(3) @binding(2) var<storage, read_write> contacts: array<Contact>;
 @group(3) @binding(3) var<storage, read_write> contact_pointers: array<i32>;
 @group(3) @binding(4) var<storage, read_write> material_pointers: array<i32>;
+@group(3) @binding(6) var<storage, read_write> cell_starts: array<u32>;
+@group(3) @binding(7) var<storage, read_write> sorted_particles: array<u32>;
+@group(3) @binding(8) var<storage, read_write> bond_log: Bond_Log;
+@group(3) @binding(9) var<storage, read_write> clump_pointers: array<i32>;
+@group(3) @binding(10) var<storage, read_write> clumps: array<vec2<i32>>;
+@group(3) @binding(11) var<storage, read_write> clump_members: array<Clump_Member>;
+@group(3) @binding(12) var<storage, read_write> polygon_info: array<vec2<i32>>;
+@group(3) @binding(13) var<storage, read_write> polygon_vertices: array<vec2<f32>>;
 @group(4) @binding(0) var<uniform> settings: Settings;
 @group(5) @binding(0) var<storage, read_write> materials: array<Material>; 
+@group(5) @binding(1) var<storage, read_write> material_pairs: array<Material_Pair>;
 @group(6) @binding(0) var<storage, read_write> data: array<f32>; 
+@group(6) @binding(1) var<storage, read_write> energy: array<Energy>;
+@group(6) @binding(2) var<storage, read_write> energy_total: Energy;
+@group(6) @binding(3) var<storage, read_write> overflow: Overflow;
+@group(7) @binding(0) var<storage, read_write> walls: array<Wall>;
 
 
-const deltaTime: f32 = 0.0000390625;
-// deltaTime should be a function of the particle mass and stiffness dt = sqrt(min_particle_mass/max_parallel_stiffness)
-// It's more complicated than that, but that is a good starting point. At the very least we should check to see what that calc would give us relative to what we're using.
+// deltaTime comes from settings, auto mode sets it to a fraction of dt = sqrt(min_particle_mass/max_parallel_stiffness) (see State::critical_delta_time)
 const PI = 3.141592653589793238;
+const TENSION = 1;
+const SHEAR = 2;
+// Swapped for Settings::max_contacts and Settings::max_bonds when the pipeline gets built, see WGPUComputeProg::simulation_shader
+const MAX_CONTACTS: u32 = 8u;
+const MAX_BONDS: u32 = 6u;
 
 @compute @workgroup_size(256)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    // data[id*4u] = 1.0;
-    // data[id*4u+1u] = 2.0;
-    // data[id*4u+2u] = 3.0;
-    // data[id*4u+3u] = 4.0;
-
     let id: u32 = global_id.x;
+    if id >= u32(settings.particles) {
+        return;
+    }
     let mat_id = material_pointers[id];
-    // let damping: f32 = 0.2; // Damping factor, can be adjusted
+    let deltaTime = settings.delta_time;
 
-    let damping: f32 = 0.2; // Damping factor, can be adjusted
     // MTIF ("move to input file", see explanation below)
     // This is my first time making what I expect will be a recurring comment, so I'll explain it here.
     // I think we should keep all variables in a separate file of input parameters. 
@@ -100,40 +272,48 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     var net_force = vec2(0.0, 0.0);
     var net_moment = 0.0;
-    // var stress_tensor = vec3(0.0, 0.0, 0.0);
+    stress = vec3(0.0, 0.0, 0.0);
+    particle_energy = Energy();
+    heat = 0.0;
 
-    // Bonds
-    // let bond_shear_lim = 0.5;
     //Bonds
-    let bond_shear_lim = 0.5; // MTIF
-    var bonded_particles = array<i32, 6u>(-1,-1,-1,-1,-1,-1);
+    var bonded_particles: array<i32, MAX_BONDS>;
+    for(var j = 0u; j<MAX_BONDS; j++){
+        bonded_particles[j] = -1;
+    }
     if settings.bonds != 0 {
         let start = bond_info[id].x;
         let length = bond_info[id].y;
         if(start != -1){
+            // bonds made with a bigger max_bonds than this pipeline's, the ones past the cap don't stop contacts
+            if u32(length) > MAX_BONDS {
+                atomicAdd(&overflow.bonds, 1u);
+            }
             for(var i = u32(start); i<u32(start+length); i++){
                 let bond_id: i32 = bonds[i].index;
-                if(bond_id < 0){
+                if(bond_id < 0 || same_clump(id, u32(bond_id))){
                     continue;
                 }
-                if settings.bonds == 2 || settings.bonds == 3 {
+                if (settings.bonds == 2 || settings.bonds == 3) && i-u32(start) < MAX_BONDS {
                     bonded_particles[i-u32(start)] = bond_id;
                 }
-                if settings.bonds == 1 || settings.bonds == 2 || settings.bonds == 3 {
+                // an intact bond conducts through its cement
+                conduct(id, u32(bond_id), settings.bond_radius_multiplier*min(radii[id], radii[bond_id]));
+                if settings.bonds == 1 || settings.bonds == 2 {
                     let displacement: f32 = -distance(i32(id), bond_id);
-                    let spring_force: vec2<f32> = settings.stiffness * displacement * normalize(positions[bond_id] - positions[id]);
-                    var force = (spring_force) * settings.damping;
+                    let spring_force: vec2<f32> = settings.stiffness * displacement * normalize(separation(id, u32(bond_id)));
+                    var force = (spring_force) * settings.force_scale;
                     net_force -= force;
+                    add_stress(radii[id]*normalize(separation(id, u32(bond_id))), -force);
+                    particle_energy.strain += 0.25*settings.force_scale*settings.stiffness*displacement*displacement;
                     if settings.bonds_tear == 1 && displacement < -settings.bond_force_limit {
-                        bonds[i].index = -bonds[i].index;
-                    }
-                    if settings.bonds == 3 {
-                        let ideal_rot = rot[bond_id];
-                        let rot_disp = rot[id] - rot[bond_id];
-                        net_moment -= (radii[id])*rot_disp/10000.0;
-                        net_moment -= (radii[id])*rot_disp/10000.0; // MTIF
-
+                        break_bond(i, id, u32(bond_id), TENSION);
                     }
+                } else if settings.bonds == 3 {
+                    let bond_forces = parallel_bond(i, id, u32(bond_id));
+                    net_force += bond_forces.xy;
+                    add_stress(radii[id]*normalize(separation(id, u32(bond_id))), bond_forces.xy);
+                    net_moment += bond_forces.z;
                 } else {
                     // Linear Bonds, w/ shear resistance 
 
@@ -156,21 +336,44 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         }
     }
 
-    // OG O(n^2) Collisions
+    // Grid Collisions (Grid.wgsl sorts the particles into cells before this pass)
     if settings.collisions == 1 {
-        let max_contacts = 8u;
-        var collisions = array<i32, 8u>();
+        let max_contacts = MAX_CONTACTS;
+        var collisions: array<i32, MAX_CONTACTS>;
         var count = 0u;
-        // make a list of particles that we're colliding with
-        for(var i = 0u; i<arrayLength(&radii); i++){
-            if i != id {
-                if length(positions[i] - positions[id]) < (radii[i] + radii[id]){
-                    collisions[count] = i32(i);
-                    count += 1u;
-                    if count == max_contacts {
-                        break;
+        var overflowed = false;
+        // make a list of particles that we're colliding with, only the 3x3 block of cells around this one can hold any
+        // near a periodic seam the blocks around its images on the other side get searched too (see image_point)
+        for(var image = 0u; image < 4u && !overflowed; image++){
+            let point = image_point(positions[id], image);
+            if point.z == 0.0 {
+                continue;
+            }
+            let cell = cell_coords(point.xy);
+            for(var y = max(cell.y - 1, 0); y <= min(cell.y + 1, settings.coll_grid_h - 1) && !overflowed; y++){
+                for(var x = max(cell.x - 1, 0); x <= min(cell.x + 1, settings.coll_grid_w - 1) && !overflowed; x++){
+                    let c = u32(y*settings.coll_grid_w + x);
+                    for(var k = cell_starts[c]; k<cell_starts[c+1u]; k++){
+                        let i = sorted_particles[k];
+                        if i != id && !same_clump(id, i) && touching(id, i, cohesion_range(id, i)){
+                            // small boxes can turn up the same particle around two images
+                            var found = false;
+                            for(var j = 0u; j<count; j++){
+                                found = found || collisions[j] == i32(i);
+                            }
+                            if found {
+                                continue;
+                            }
+                            // one more than there's room for
+                            if count == max_contacts {
+                                overflowed = true;
+                                break;
+                            }
+                            collisions[count] = i32(i);
+                            count += 1u;
+                        }
                     }
-                } 
+                }
             }
         }
         // delete contacts that don't exist
@@ -214,11 +417,13 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
             }
             
             if existing_index == -1 && empty_index == -1 {
+                // every slot is held, bonded contacts outlast the touch that made them
+                overflowed = true;
                 continue;
             } else if existing_index == -1 { // initialize completely new contact
                 let b = collisions[i];
-                for(var j = 0u; j<6u; j++){
-                    if bonded_particles[j] == contacts[i].b {
+                for(var j = 0u; j<MAX_BONDS; j++){
+                    if bonded_particles[j] == b {
                         contacts[empty_index].bonded = 1;
                         break;
                     }
@@ -226,16 +431,21 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
                 contacts[empty_index].a = i32(id);
                 contacts[empty_index].b = b;
                 contacts[empty_index].tangent_force = 0.0;
+                contacts[empty_index].rolling_moment = 0.0;
+                contacts[empty_index].touched = -1;
             }
 
         }
+        if overflowed {
+            atomicAdd(&overflow.contacts, 1u);
+        }
 
         for(var i = id*max_contacts; i<(id+1u)*max_contacts; i++){
             if contacts[i].b == -1{
                 continue;
             }
             var bonded = false;
-            for(var j = 0u; j<6u; j++){
+            for(var j = 0u; j<MAX_BONDS; j++){
                 if bonded_particles[j] == contacts[i].b {
                     bonded = true;
                     break;
@@ -246,63 +456,343 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
             }
             let a = contacts[i].a;
             let b = contacts[i].b;
-            let overlap = max(-distance(a, b), 0.0);
+            // contacts within the cohesion range can have a gap
+            var gap = distance(a, b);
+            var overlap = max(-gap, 0.0);
+            var normal = -normalize(separation(u32(a), u32(b)));
+            // where the contact is from each center, and how fast b's side of it moves past a's
+            var arm_a = -normal*radii[a];
+            var rel_vel = image_velocity(u32(a), u32(b)) - velocities[a];
+            // polygons touch wherever their corners say, discs along the line between their centers
+            let polygons = is_polygon(u32(a)) || is_polygon(u32(b));
+            if polygons {
+                let shape = shape_contact(u32(a), u32(b));
+                gap = -shape.overlap;
+                overlap = max(shape.overlap, 0.0);
+                normal = shape.normal;
+                arm_a = shape.point;
+                let arm_b = shape.point - separation(u32(a), u32(b));
+                rel_vel += rot_vel[b]*vec2(-arm_b.y, arm_b.x) - rot_vel[a]*vec2(-arm_a.y, arm_a.x);
+            }
             
             var normal_stiffness = 10.0; // MTIF
             var shear_stiffness = 0.25; // MTIF
-            if mat_id != -1 {
-                normal_stiffness = (materials[(material_pointers[b])].normal_stiffness);
-                shear_stiffness = (materials[(material_pointers[b])].shear_stiffness);
+            var friction_coefficient = settings.friction_coefficient; // MTIF
+            var restitution = settings.contact_restitution;
+            var cohesion_scale = 1.0;
+            // the pair table and Hertz-Mindlin need both materials, a particle without one keeps the defaults above
+            let paired = mat_id != -1 && material_pointers[b] != -1;
+            if paired {
+                let pair = material_pair(u32(a), u32(b));
+                normal_stiffness = pair.normal_stiffness;
+                shear_stiffness = pair.shear_stiffness;
+                friction_coefficient = pair.friction_coefficient;
+                restitution = pair.restitution;
+                cohesion_scale = pair.cohesion;
             }
             var normal_force = overlap*normal_stiffness;
-            let normal = normalize(positions[a] - positions[b]); 
+            // Hertz-Mindlin is for spheres, polygons stay linear
+            if settings.contact_model == 1 && paired && !polygons {
+                let hertz = hertz_mindlin(a, b, overlap);
+                normal_force = hertz.x;
+                shear_stiffness = hertz.y;
+                if overlap > 0.0 {
+                    normal_stiffness = 1.5*normal_force/overlap; // dF/d(overlap), only the rolling spring needs it
+                }
+            }
             let tangent = vec2(-normal.y, normal.x);
 
             let del_pos_a = velocities[a]*deltaTime;
-            let del_pos_b = velocities[b]*deltaTime;
+            let del_pos_b = image_velocity(u32(a), u32(b))*deltaTime;
             let del_rot_a = rot_vel[a]*deltaTime*(radii[a]);//-overlap/2.0);
             let del_rot_b = rot_vel[b]*deltaTime*(radii[b]);//-overlap/2.0);
             
             let rel_trans = del_pos_b - del_pos_a;
             let rel_rot = del_rot_b + del_rot_a;
             
-            let rel_tangent = dot(rel_trans, tangent) + rel_rot;
+            var rel_tangent = dot(rel_trans, tangent) + rel_rot;
+            if polygons {
+                rel_tangent = dot(rel_vel, tangent)*deltaTime;
+            }
             
-            var friction_limit = abs(normal_force)*settings.friction_coefficient;
+            var friction_limit = abs(normal_force)*friction_coefficient;
+            if settings.friction == 0 {
+                friction_limit = 0.0;
+            }
             var moment = true;
-            if bonded && settings.bonds == 2 || settings.bonds == 3 {
+            if bonded && settings.bonds == 2 {
                 normal_force = 0.0;
                 friction_limit = settings.bond_shear_lim;
                 moment = false;
             }
-            contacts[i].tangent_force = contacts[i].tangent_force + rel_tangent*shear_stiffness;//clamp(contacts[i].tangent_force + rel_tangent*shear_stiffness, -friction_limit, friction_limit);
-            net_force += settings.damping * (normal*normal_force + tangent*contacts[i].tangent_force);
-            // if moment {
-            net_moment -= (radii[a])*contacts[i].tangent_force;// - overlap/2.0
-            // }
-            // stress_tensor += stress_tensor(id, net_force, normal*radii[a]);
+            // Coulomb sliding, the tangential spring can't carry more than the friction limit
+            let trial_force = contacts[i].tangent_force + rel_tangent*shear_stiffness;
+            contacts[i].tangent_force = clamp(trial_force, -friction_limit, friction_limit);
+            // both particles see the contact, so each books half of its energy
+            if abs(trial_force) > friction_limit && shear_stiffness > 0.0 {
+                particle_energy.friction += 0.5*settings.force_scale*friction_limit*(abs(trial_force) - friction_limit)/shear_stiffness;
+            }
+            var normal_energy = 0.5*normal_force*overlap;
+            if settings.contact_model == 1 && mat_id != -1 && !polygons {
+                normal_energy = 0.4*normal_force*overlap;
+            }
+            particle_energy.strain += 0.5*settings.force_scale*normal_energy;
+            if shear_stiffness > 0.0 {
+                particle_energy.strain += 0.25*settings.force_scale*contacts[i].tangent_force*contacts[i].tangent_force/shear_stiffness;
+            }
+            // viscous dashpot along the normal, tuned to the pair's restitution
+            var dashpot_force = 0.0;
+            if (settings.damping_model & 2) != 0 && normal_force > 0.0 {
+                let closing = dot(rel_vel, normal);
+                let mass_a = particle_mass(u32(a));
+                let mass_b = particle_mass(u32(b));
+                let dashpot = 2.0*contact_damping_ratio(restitution)*sqrt(settings.force_scale*normal_stiffness*mass_a*mass_b/(mass_a + mass_b));
+                // it can cancel the spring but never pull the particles together
+                dashpot_force = max(dashpot*closing, -settings.force_scale*normal_force);
+                particle_energy.damping += 0.5*dashpot_force*closing*deltaTime;
+            }
+            // cohesion pulls the pair together, friction above only sees the elastic part of the normal force
+            let touching_now = gap <= 0.0 && contacts[i].touched != 1;
+            if gap <= 0.0 {
+                contacts[i].touched = 1;
+            }
+            let cohesive = cohesion_scale*cohesion(u32(a), u32(b), gap, contacts[i].touched == 1);
+            // the neck or bridge snaps on with the energy it already has, that's booked as lost so the budget still adds up
+            if touching_now {
+                particle_energy.damping -= 0.5*settings.force_scale*cohesive.y;
+            }
+            // heat goes through the contact patch, bonded pairs already conduct through the bond
+            if !bonded && overlap > 0.0 {
+                conduct(u32(a), u32(b), sqrt(radii[a]*radii[b]/(radii[a] + radii[b])*overlap));
+            }
+            particle_energy.strain += 0.5*settings.force_scale*cohesive.y;
+            let contact_force = settings.force_scale * (normal*(normal_force - cohesive.x) + tangent*contacts[i].tangent_force) + normal*dashpot_force;
+            net_force += contact_force;
+            add_stress(arm_a, contact_force);
+            if polygons {
+                // off center the normal force turns it too, the full moment of the force keeps the strain energy honest
+                net_moment += cross_2d(arm_a, contact_force);
+            } else {
+                net_moment -= (radii[a])*contacts[i].tangent_force;// - overlap/2.0
+            }
+            // a polygon's corners already resist rolling
+            if moment && settings.rolling_model != 0 && !polygons {
+                net_moment += rolling_resistance(i, a, b, normal_force, normal_stiffness);
+            }
         }
-        // data[id*4] = stress_tensor.x;
-        // data[id*4+1] = stress_tensor.y;
-        // data[id*4+2] = stress_tensor.z;
     }
     
+    let wall_forces = segment_walls(id);
+    net_force += wall_forces.xy;
+    net_moment += wall_forces.z;
+
     store_forces(id, mat_id, net_force, net_moment);
+    store_stress(id);
+    store_temperature(id);
     
-    walls(id);
+    box_walls(id);
+    store_energy(id);
+}
+
+// Love-Weber stress, sigma_ij = 1/A * sum over contacts of branch_i*force_j, A being the particle's area.
+// branch runs from the particle's center to the contact point and force is what the contact puts on this particle, so tension is positive.
+// Only the symmetric part is kept, data is [xx, yy, xy, mean] per particle.
+var<private> stress: vec3<f32>;
+var<private> particle_energy: Energy;
+// heat flowing into this particle this step, and how deep the last wall_contact pressed in
+var<private> heat: f32;
+var<private> wall_overlap: f32;
+
+fn add_stress(branch: vec2<f32>, force: vec2<f32>) {
+    stress += vec3(branch.x*force.x, branch.y*force.y, 0.5*(branch.x*force.y + branch.y*force.x));
+}
+
+fn store_stress(id: u32) {
+    var area = PI*radii[id]*radii[id];
+    if is_polygon(id) {
+        area = polygon_area(id);
+    }
+    let tensor = stress/area;
+    data[id*4u] = tensor.x;
+    data[id*4u+1u] = tensor.y;
+    data[id*4u+2u] = tensor.z;
+    data[id*4u+3u] = 0.5*(tensor.x + tensor.y);
+}
+
+fn cell_coords(pos: vec2<f32>) -> vec2<i32> {
+    let cell = vec2<i32>(floor((pos + vec2(settings.hor_bound, settings.vert_bound))/settings.coll_cell_size));
+    return clamp(cell, vec2(0, 0), vec2(settings.coll_grid_w - 1, settings.coll_grid_h - 1));
+}
+
+// Where to search for contacts across the periodic seams, 0 is the particle itself, bit 1 its image across the x seam and bit 2 across the y seam.
+// An image only counts (z = 1) when the particle is within a cell of that seam. Across a Lees-Edwards seam the image is also slid along by shear_offset.
+// 2D_Render.wgsl draws its ghosts the same way.
+fn image_point(position: vec2<f32>, image: u32) -> vec3<f32> {
+    var pos = position;
+    var valid = 1.0;
+    if (image & 2u) != 0u {
+        if settings.boundary_y == 0 || abs(pos.y) + settings.coll_cell_size < settings.vert_bound {
+            valid = 0.0;
+        }
+        let side = sign(pos.y);
+        pos.y -= side*2.0*settings.vert_bound;
+        if settings.boundary_y == 2 {
+            pos.x -= side*shear_offset;
+            pos.x -= round(pos.x/(2.0*settings.hor_bound))*2.0*settings.hor_bound;
+        }
+    }
+    if (image & 1u) != 0u {
+        if settings.boundary_x == 0 || abs(pos.x) + settings.coll_cell_size < settings.hor_bound {
+            valid = 0.0;
+        }
+        pos.x -= sign(pos.x)*2.0*settings.hor_bound;
+    }
+    return vec3(pos, valid);
+}
+
+// Shortest vector from a to b, with periodic boundaries that's to whichever image of b is closest.
+// The images above and below a Lees-Edwards seam are slid along by shear_offset.
+fn separation(a: u32, b: u32) -> vec2<f32> {
+    var delta = positions[b] - positions[a];
+    let size = 2.0*vec2(settings.hor_bound, settings.vert_bound);
+    if settings.boundary_y != 0 {
+        let shift = round(delta.y/size.y);
+        delta.y -= shift*size.y;
+        if settings.boundary_y == 2 {
+            delta.x -= shift*shear_offset;
+        }
+    }
+    if settings.boundary_x != 0 {
+        delta.x -= round(delta.x/size.x)*size.x;
+    }
+    return delta;
+}
+
+// Velocity of the image of b that separation picked, the images above a Lees-Edwards seam move at shear_velocity
+fn image_velocity(a: u32, b: u32) -> vec2<f32> {
+    if settings.boundary_y == 2 {
+        let shift = round((positions[b].y - positions[a].y)/(2.0*settings.vert_bound));
+        return velocities[b] - vec2(shift*settings.shear_velocity, 0.0);
+    }
+    return velocities[b];
+}
+
+// Discs in one clump never touch or bond, the clump pass holds them where they are
+fn same_clump(a: u32, b: u32) -> bool {
+    return clump_pointers[a] != -1 && clump_pointers[a] == clump_pointers[b];
 }
 
 fn distance(a: i32, b: i32) -> f32 {
-    return  length(positions[a] - positions[b]) - (radii[a] + radii[b]);
+    return  length(separation(u32(a), u32(b))) - (radii[a] + radii[b]);
 }
 
-fn store_forces(id: u32, mat_id: i32, net_force: vec2<f32>, net_moment: f32) {
-    // Apply sum of forces and gravity to velocities
-    var density = 1.0;
+// Parallel bond (Potyondy & Cundall 2004), returns the force and moment on a.
+// A beam of radius multiplier*min(Ra, Rb) glued across the contact, in parallel with the linear contact spring.
+// The forces are incremental, so they're kept in the bond and carried over between generations.
+// Each side of the bond keeps its own copy, they see the same relative motion so they stay equal and opposite.
+fn parallel_bond(i: u32, a: u32, b: u32) -> vec3<f32> {
+    let deltaTime = settings.delta_time;
+    let bond_radius = settings.bond_radius_multiplier*min(radii[a], radii[b]);
+    let area = 2.0*bond_radius; // unit thickness
+    let inertia = 2.0*bond_radius*bond_radius*bond_radius/3.0;
+
+    let normal = normalize(separation(a, b));
+    let tangent = vec2(-normal.y, normal.x);
+    let rel_vel = image_velocity(a, b) - velocities[a] - (rot_vel[a]*radii[a] + rot_vel[b]*radii[b])*tangent;
+
+    // tension is positive
+    bonds[i].normal_force += settings.force_scale*settings.bond_normal_stiffness*area*dot(rel_vel, normal)*deltaTime;
+    bonds[i].shear_force += settings.force_scale*settings.bond_shear_stiffness*area*dot(rel_vel, tangent)*deltaTime;
+    bonds[i].moment += settings.force_scale*settings.bond_normal_stiffness*inertia*(rot_vel[b] - rot_vel[a])*deltaTime;
+
+    let normal_force = bonds[i].normal_force;
+    let shear_force = bonds[i].shear_force;
+    let moment = bonds[i].moment;
+
+    if settings.bonds_tear == 1 {
+        let tensile_stress = normal_force/area + abs(moment)*bond_radius/inertia;
+        let shear_stress = abs(shear_force)/area;
+        let shear_strength = settings.bond_cohesion - normal_force/area*tan(settings.bond_friction_angle);
+        if tensile_stress >= settings.bond_tensile_strength {
+            break_bond(i, a, b, TENSION);
+            return vec3(0.0, 0.0, 0.0);
+        }
+        if shear_stress >= shear_strength {
+            break_bond(i, a, b, SHEAR);
+            return vec3(0.0, 0.0, 0.0);
         }
     }
-    
-    var density = 1.0; // MTIF
+
+    // the stored forces already include damping, and each side books half
+    let normal_spring = settings.force_scale*settings.bond_normal_stiffness;
+    let shear_spring = settings.force_scale*settings.bond_shear_stiffness;
+    if normal_spring > 0.0 {
+        particle_energy.strain += 0.25*(normal_force*normal_force/(normal_spring*area) + moment*moment/(normal_spring*inertia));
+    }
+    if shear_spring > 0.0 {
+        particle_energy.strain += 0.25*shear_force*shear_force/(shear_spring*area);
+    }
+
+    return vec3(normal*normal_force + tangent*shear_force, radii[a]*shear_force + moment);
+}
+
+// Both copies of a bond see the same forces and tear in the same generation, so only the lower particle logs it.
+// The event is at the middle of the gap between the two surfaces.
+fn break_bond(i: u32, a: u32, b: u32, mode: i32) {
+    bonds[i].index = -bonds[i].index;
+    if a > b {
+        return;
+    }
+    let slot = atomicAdd(&bond_log.count, 1u);
+    if slot < arrayLength(&bond_log.events) {
+        let normal = normalize(separation(a, b));
+        let gap = length(separation(a, b)) - (radii[a] + radii[b]);
+        let location = positions[a] + normal*(radii[a] + 0.5*gap);
+        bond_log.events[slot] = Bond_Event(bond_log.step, i32(a), i32(b), location.x, location.y, mode);
+    }
+}
+
+// Rolling resistance, returns the moment on a.
+// Type A is a constant torque against the relative rotation, type C is an elastic-plastic spring (Ai et al. 2011) capped at the same torque.
+fn rolling_resistance(i: u32, a: i32, b: i32, normal_force: f32, normal_stiffness: f32) -> f32 {
+    let eff_radius = radii[a]*radii[b]/(radii[a] + radii[b]);
+    let limit = settings.rolling_coefficient*eff_radius*abs(normal_force);
+    let rel_rot_vel = rot_vel[a] - rot_vel[b];
+    if settings.rolling_model == 1 {
+        particle_energy.friction += 0.5*limit*abs(rel_rot_vel)*settings.delta_time;
+        return -limit*sign(rel_rot_vel);
+    }
+    let rolling_stiffness = 2.25*normal_stiffness*settings.rolling_coefficient*settings.rolling_coefficient*eff_radius*eff_radius;
+    let trial_moment = contacts[i].rolling_moment - rolling_stiffness*rel_rot_vel*settings.delta_time;
+    contacts[i].rolling_moment = clamp(trial_moment, -limit, limit);
+    if rolling_stiffness > 0.0 {
+        particle_energy.friction += 0.5*limit*max(abs(trial_moment) - limit, 0.0)/rolling_stiffness;
+        particle_energy.strain += 0.25*contacts[i].rolling_moment*contacts[i].rolling_moment/rolling_stiffness;
+    }
+    return contacts[i].rolling_moment;
+}
+
+// What a touching b sees, the table is symmetric so it's the same the other way around
+fn material_pair(a: u32, b: u32) -> Material_Pair {
+    return material_pairs[u32(material_pointers[a])*arrayLength(&materials) + u32(material_pointers[b])];
+}
+
+// Hertz-Mindlin (no-slip), returns the normal force and the tangential stiffness at the current overlap
+fn hertz_mindlin(a: i32, b: i32, overlap: f32) -> vec2<f32> {
+    let mat_a = materials[material_pointers[a]];
+    let mat_b = materials[material_pointers[b]];
+    let eff_youngs = 1.0/((1.0 - mat_a.poisson_ratio*mat_a.poisson_ratio)/mat_a.youngs_modulus + (1.0 - mat_b.poisson_ratio*mat_b.poisson_ratio)/mat_b.youngs_modulus);
+    let shear_a = mat_a.youngs_modulus/(2.0*(1.0 + mat_a.poisson_ratio));
+    let shear_b = mat_b.youngs_modulus/(2.0*(1.0 + mat_b.poisson_ratio));
+    let eff_shear = 1.0/((2.0 - mat_a.poisson_ratio)/shear_a + (2.0 - mat_b.poisson_ratio)/shear_b);
+    let eff_radius = radii[a]*radii[b]/(radii[a] + radii[b]);
+    let contact_radius = sqrt(eff_radius*overlap);
+    return vec2(4.0/3.0*eff_youngs*contact_radius*overlap, 8.0*eff_shear*contact_radius);
+}
+
+fn store_forces(id: u32, mat_id: i32, net_force: vec2<f32>, net_moment: f32) {
+    // Apply sum of forces and gravity to velocities
+    let deltaTime = settings.delta_time;
     // Move laws of motion to the beginning of the calculation cycle
         // Let's rethink this and break it down into its components and put them in the right order.
         // Translational motion, then rotational motion
@@ -311,56 +801,795 @@ fn store_forces(id: u32, mat_id: i32, net_force: vec2<f32>, net_moment: f32) {
     
     // START{Laws of Motion}
         // Translational Motion
-    if mat_id != -1 {
-        density = materials[mat_id].density;
+    let mass1 = particle_mass(id);
+    var force = net_force;
+    var moment = net_moment;
+    // Cundall's local damping, each component of the resultant loses local_damping of its size against the velocity
+    if (settings.damping_model & 1) != 0 {
+        let resultant = net_force + mass1*gravity_acceleration(id);
+        let damping_force = -settings.local_damping*abs(resultant)*sign(velocities[id]);
+        let damping_moment = -settings.local_damping*abs(net_moment)*sign(rot_vel[id]);
+        force += damping_force;
+        moment += damping_moment;
+        particle_energy.damping -= (dot(damping_force, velocities[id]) + damping_moment*rot_vel[id])*deltaTime;
+    }
+    if settings.fluid == 1 {
+        let fluid = fluid_force(id, mass1);
+        force += fluid;
+        particle_energy.work += dot(fluid, velocities[id])*deltaTime;
     }
-    let mass1 = density * PI * radii[id] * radii[id]; // make it a function mass(density,radius)
-    let half_step_velocity = velocities[id] + 0.5 * (resultant_force/mass1 - grav) * deltaTime
+    velocities_buf[id] = velocities[id] + force/mass1 * deltaTime;
         // Rotational Motion
-    let rot_inertia = 0.5 * mass1 * radii[id] * radii[id]; // make it a function rot_inertia(density,radius)
-    let half_step_ang_vel = rot_vel[id] + 0.5 * net_moment/rot_inertia) * deltaTime;
-    
-    rot_vel_buf[id] = 
-    if settings.gravity == 1 && settings.planet_mode == 1  {
+    rot_vel_buf[id] = rot_vel[id] + moment/rot_inertia(id) * deltaTime;
+    // END{Laws of Motion}
+
+    velocities_buf[id] += gravity_acceleration(id) * deltaTime;
+}
+
+// Gravity, plus the pseudo force of a shaking base when the excitation drives gravity (see Excitation::base_motion)
+// Planet mode pulls toward the center with the size of the gravity vector.
+// Drag toward the fluid velocity and buoyancy against gravity.
+// The drag rate is held over the frame, capped at 1/dt it can bring a particle up to the fluid's speed but never past it.
+fn fluid_force(id: u32, mass: f32) -> vec2<f32> {
+    let rate = min(fluid_drag[id].rate, 1.0/settings.delta_time);
+    let drag = rate*(vec2(fluid_drag[id].u_x, fluid_drag[id].u_y) - velocities[id]);
+    let buoyancy = settings.fluid_density/particle_density(id)*gravity_acceleration(id);
+    return mass*(drag - buoyancy);
+}
+
+fn gravity_acceleration(id: u32) -> vec2<f32> {
+    let base = -vec2(settings.base_acc_x, settings.base_acc_y);
+    if settings.gravity == 1 && settings.self_gravity == 1 {
+        return vec2(self_gravity[id].x, self_gravity[id].y) + base;
+    } else if settings.gravity == 1 && settings.planet_mode == 1  {
         let delta = (vec2(0.0, 0.0) - positions[id]);
-        velocities_buf[id] += delta/length(delta) * 9.81 * settings.gravity_acc * deltaTime;
+        return delta/length(delta) * 9.81 * length(vec2(settings.gravity_x, settings.gravity_y)) + base;
     } else if settings.gravity == 1 {
-        let gravity = 9.81 * settings.gravity_acc * deltaTime; // MTIF (9.81)
-        velocities_buf[id] += vec2(0.0, -gravity);
+        return 9.81 * vec2(settings.gravity_x, settings.gravity_y) + base; // MTIF (9.81)
+    }
+    return base;
+}
+
+// Same as Settings::damping_ratio
+fn contact_damping_ratio(restitution: f32) -> f32 {
+    if (settings.damping_model & 2) == 0 || restitution >= 1.0 {
+        return 0.0;
+    }
+    if restitution <= 0.0 {
+        return 1.0;
     }
+    let log_e = log(restitution);
+    return -log_e/sqrt(PI*PI + log_e*log_e);
 }
 
-fn walls(id: u32) {
+fn box_walls(id: u32) {
     // BS Walls
-    let pos = positions[id];
-    let rad = radii[id];
-    let elasticity = 0.5; // MTIF
+    // a shaking base moves the box with it, the bounce happens in the box's frame
+    let base_vel = vec2(settings.base_vel_x, settings.base_vel_y);
+    let base_offset = vec2(settings.base_offset_x, settings.base_offset_y);
+    let before = velocities_buf[id];
+    var rel_vel = velocities_buf[id] - base_vel;
+    let speed2 = dot(rel_vel, rel_vel);
+    let spin = rot_vel_buf[id];
+    let pos = positions[id] - base_offset;
+    let elasticity = settings.wall_elasticity;
     let anti_stick_coating = 0.01; // MTIF
     let yH = settings.vert_bound;
     let xW = settings.hor_bound;
+
+    // how far the particle reaches right, left, up and down
+    let right = extent(id, vec2(1.0, 0.0));
+    let left = extent(id, vec2(-1.0, 0.0));
+    let up = extent(id, vec2(0.0, 1.0));
+    let down = extent(id, vec2(0.0, -1.0));
     
-    if pos.x+rad > xW {
-        velocities_buf[id] = vec2(-velocities_buf[id].x, velocities_buf[id].y)*elasticity;
-        rot_vel_buf[id] = rot_vel_buf[id]*0.9;
-        positions[id] = vec2(xW-rad, pos.y);
-    } else if pos.x-rad < -xW {
-        velocities_buf[id] = vec2(-velocities_buf[id].x, velocities_buf[id].y)*elasticity;
-        rot_vel_buf[id] = rot_vel_buf[id]*0.9;
-        positions[id] = vec2(-xW+rad, pos.y);
-    }
-    if pos.y+rad > yH {
-        velocities_buf[id] = vec2(velocities_buf[id].x, -velocities_buf[id].y)*elasticity;
-        rot_vel_buf[id] = rot_vel_buf[id]*0.9;
-        positions[id] = vec2(pos.x, yH-rad - anti_stick_coating);
-    } else if pos.y-rad < -yH {
-        velocities_buf[id] = vec2(velocities_buf[id].x, -velocities_buf[id].y)*elasticity;
-        rot_vel_buf[id] = rot_vel_buf[id]*0.9;
-        positions[id] = vec2(pos.x, -yH+rad);
-    }
-}
-
-// fn stress_tensor(id: u32, force: vec2<f32>, delta: vec2<f32>) -> vec3<f32> {
-//     var tensor = vec3(0.0, 0.0, 0.0);
-//     let 
-    
-// }
\ No newline at end of file
+    // periodic axes don't have walls, 2D_LOM.wgsl wraps them instead
+    let x_walls = settings.boundary_x == 0;
+    let y_walls = settings.boundary_y == 0;
+    if x_walls && pos.x+right > xW {
+        rel_vel = vec2(-rel_vel.x, rel_vel.y)*elasticity;
+        rot_vel_buf[id] = rot_vel_buf[id]*settings.wall_rot_damping;
+        positions[id] = vec2(xW-right, pos.y) + base_offset;
+    } else if x_walls && pos.x-left < -xW {
+        rel_vel = vec2(-rel_vel.x, rel_vel.y)*elasticity;
+        rot_vel_buf[id] = rot_vel_buf[id]*settings.wall_rot_damping;
+        positions[id] = vec2(-xW+left, pos.y) + base_offset;
+    }
+    if y_walls && pos.y+up > yH {
+        rel_vel = vec2(rel_vel.x, -rel_vel.y)*elasticity;
+        rot_vel_buf[id] = rot_vel_buf[id]*settings.wall_rot_damping;
+        positions[id] = vec2(pos.x, yH-up - anti_stick_coating) + base_offset;
+    } else if y_walls && pos.y-down < -yH {
+        rel_vel = vec2(rel_vel.x, -rel_vel.y)*elasticity;
+        rot_vel_buf[id] = rot_vel_buf[id]*settings.wall_rot_damping;
+        positions[id] = vec2(pos.x, -yH+down) + base_offset;
+    }
+    velocities_buf[id] = rel_vel + base_vel;
+    // whatever the bounce took off in the box's frame goes to damping, a moving box can put some back as work
+    let mass = particle_mass(id);
+    let after2 = dot(rel_vel, rel_vel);
+    let lost = 0.5*mass*(speed2 - after2);
+    particle_energy.damping += lost + 0.5*rot_inertia(id)*(spin*spin - rot_vel_buf[id]*rot_vel_buf[id]);
+    particle_energy.work += 0.5*mass*((dot(velocities_buf[id], velocities_buf[id]) - after2) - (dot(before, before) - speed2));
+}
+
+// Kinetic and potential energy, and the work the applied forces did this step (forces are accelerations, see 2D_LOM.wgsl).
+// Gravity is measured from the corner of the box it points into, or from the center in planet mode, self gravity is zero at infinity.
+fn store_energy(id: u32) {
+    let pos = positions[id];
+    let mass = particle_mass(id);
+    let inertia = rot_inertia(id);
+    let vel = velocities[id];
+    particle_energy.kinetic = 0.5*mass*dot(vel, vel);
+    particle_energy.rotational = 0.5*inertia*rot_vel[id]*rot_vel[id];
+    let gravity = 9.81*vec2(settings.gravity_x, settings.gravity_y);
+    if settings.gravity == 1 && settings.self_gravity == 1 {
+        // every pair shows up at both ends
+        particle_energy.gravity = 0.5*mass*self_gravity[id].potential;
+    } else if settings.gravity == 1 && settings.planet_mode == 1 {
+        particle_energy.gravity = mass*length(gravity)*length(pos);
+    } else if settings.gravity == 1 {
+        let corner = sign(gravity)*vec2(settings.hor_bound, settings.vert_bound);
+        particle_energy.gravity = -mass*dot(gravity, pos - corner);
+    }
+    particle_energy.work += (mass*dot(vec2(forces[id].x, forces[id].y), vel) + inertia*forces[id].rot*rot_vel[id])*settings.delta_time;
+    particle_energy.work -= mass*dot(vec2(settings.base_acc_x, settings.base_acc_y), vel)*settings.delta_time;
+    // NaN particles would poison the sums
+    let check = particle_energy.kinetic + particle_energy.rotational + particle_energy.strain + particle_energy.gravity + particle_energy.friction + particle_energy.damping + particle_energy.work;
+    if check != check {
+        energy[id] = Energy();
+        return;
+    }
+    energy[id] = particle_energy;
+}
+
+fn add_energy(a: Energy, b: Energy) -> Energy {
+    return Energy(a.kinetic + b.kinetic, a.rotational + b.rotational, a.strain + b.strain, a.gravity + b.gravity, a.friction + b.friction, a.damping + b.damping, a.work + b.work);
+}
+
+var<workgroup> energy_sums: array<Energy, 256>;
+
+// One workgroup, dispatched after main every step.
+// The energies are overwritten, the dissipation and work get added on.
+@compute @workgroup_size(256)
+fn energy_budget(@builtin(local_invocation_id) local_id: vec3<u32>) {
+    var sum = Energy();
+    for(var id = local_id.x; id<u32(settings.particles); id += 256u){
+        sum = add_energy(sum, energy[id]);
+    }
+    energy_sums[local_id.x] = sum;
+    workgroupBarrier();
+
+    for(var offset = 128u; offset>0u; offset /= 2u){
+        if local_id.x < offset {
+            energy_sums[local_id.x] = add_energy(energy_sums[local_id.x], energy_sums[local_id.x + offset]);
+        }
+        workgroupBarrier();
+    }
+
+    if local_id.x == 0u {
+        let total = energy_sums[0];
+        energy_total.kinetic = total.kinetic;
+        energy_total.rotational = total.rotational;
+        energy_total.strain = total.strain;
+        energy_total.gravity = total.gravity;
+        energy_total.friction += total.friction;
+        energy_total.damping += total.damping;
+        energy_total.work += total.work;
+        overflow.most_contacts = max(overflow.most_contacts, atomicLoad(&overflow.contacts));
+        overflow.most_bonds = max(overflow.most_bonds, atomicLoad(&overflow.bonds));
+        atomicStore(&overflow.contacts, 0u);
+        atomicStore(&overflow.bonds, 0u);
+    }
+}
+
+fn particle_density(id: u32) -> f32 {
+    var density = 1.0; // MTIF
+    if material_pointers[id] != -1 {
+        density = materials[material_pointers[id]].density;
+    }
+    return density;
+}
+
+fn conductivity(id: u32) -> f32 {
+    var conductivity = 1.0; // MTIF
+    if material_pointers[id] != -1 {
+        conductivity = materials[material_pointers[id]].conductivity;
+    }
+    return conductivity;
+}
+
+fn specific_heat(id: u32) -> f32 {
+    var specific_heat = 1.0; // MTIF
+    if material_pointers[id] != -1 {
+        specific_heat = materials[material_pointers[id]].specific_heat;
+    }
+    return specific_heat;
+}
+
+fn particle_mass(id: u32) -> f32 {
+    if is_polygon(id) {
+        return particle_density(id) * polygon_area(id);
+    }
+    return particle_density(id) * PI * radii[id] * radii[id];
+}
+
+fn rot_inertia(id: u32) -> f32 {
+    if is_polygon(id) {
+        return particle_density(id) * polygon_inertia(id);
+    }
+    return 0.5 * particle_mass(id) * radii[id] * radii[id];
+}
+
+fn is_polygon(id: u32) -> bool {
+    return polygon_info[id].y > 2;
+}
+
+// corner k of a polygon in the world frame, relative to its position
+fn polygon_vertex(id: u32, k: i32) -> vec2<f32> {
+    return rotate(polygon_vertices[polygon_info[id].x + k], rot[id]);
+}
+
+fn polygon_area(id: u32) -> f32 {
+    let count = polygon_info[id].y;
+    var area = 0.0;
+    for(var k = 0; k < count; k++){
+        area += 0.5*cross_2d(polygon_vertices[polygon_info[id].x + k], polygon_vertices[polygon_info[id].x + (k + 1)%count]);
+    }
+    return area;
+}
+
+// second moment of area about the centroid
+fn polygon_inertia(id: u32) -> f32 {
+    let count = polygon_info[id].y;
+    var inertia = 0.0;
+    for(var k = 0; k < count; k++){
+        let a = polygon_vertices[polygon_info[id].x + k];
+        let b = polygon_vertices[polygon_info[id].x + (k + 1)%count];
+        inertia += cross_2d(a, b)*(dot(a, a) + dot(a, b) + dot(b, b))/12.0;
+    }
+    return inertia;
+}
+
+// The point of the particle farthest along dir, relative to its position
+fn support(id: u32, dir: vec2<f32>) -> vec2<f32> {
+    if !is_polygon(id) {
+        return dir*radii[id];
+    }
+    var best = polygon_vertex(id, 0);
+    for(var k = 1; k < polygon_info[id].y; k++){
+        let corner = polygon_vertex(id, k);
+        if dot(corner, dir) > dot(best, dir) {
+            best = corner;
+        }
+    }
+    return best;
+}
+
+// How far the particle reaches along dir (a unit vector)
+fn extent(id: u32, dir: vec2<f32>) -> f32 {
+    if !is_polygon(id) {
+        return radii[id];
+    }
+    return dot(support(id, dir), dir);
+}
+
+// Whether a and b are closer than range, the bounding circles have to be before a polygon is looked at
+fn touching(a: u32, b: u32, range: f32) -> bool {
+    if length(separation(a, b)) >= radii[a] + radii[b] + range {
+        return false;
+    }
+    return !(is_polygon(a) || is_polygon(b)) || shape_contact(a, b).overlap > -range;
+}
+
+// Settings::cohesion_range
+fn cohesion_range(a: u32, b: u32) -> f32 {
+    if material_pointers[a] == -1 || material_pointers[b] == -1 {
+        return 0.0;
+    }
+    let mat_a = materials[material_pointers[a]];
+    let mat_b = materials[material_pointers[b]];
+    let pair = material_pair(a, b);
+    if pair.cohesion_model == 1.0 {
+        let adhesion = pair.adhesion;
+        let eff_youngs = 1.0/((1.0 - mat_a.poisson_ratio*mat_a.poisson_ratio)/mat_a.youngs_modulus + (1.0 - mat_b.poisson_ratio*mat_b.poisson_ratio)/mat_b.youngs_modulus);
+        let eff_radius = radii[a]*radii[b]/(radii[a] + radii[b]);
+        return 0.75*pow(PI*PI*adhesion*adhesion*eff_radius/(eff_youngs*eff_youngs), 1.0/3.0);
+    } else if pair.cohesion_model == 3.0 {
+        return (1.0 + 0.5*radians(pair.contact_angle))*pow(pair.liquid_volume, 1.0/3.0);
+    }
+    return 0.0;
+}
+
+// Attraction between a and b at this gap (negative when they overlap), x is the force and y its potential energy, which is zero at the range.
+// JKR and liquid bridges need the pair to have touched, so they pull apart further than they come together.
+// JKR takes the contact radius from the overlap (jkr_radius) for the force 4*E*a^3/(3*R) - sqrt(8*pi*adhesion*E*a^3),
+// and pulls with whatever that's short of Hertz, so with Hertz-Mindlin contacts the normal force is JKR's and linear springs get the same pull.
+// It lets go at the neck, an overlap of -range. DMT pulls with 2*pi*adhesion*R only while touching,
+// and a liquid bridge follows Willett et al.'s 2*pi*R*tension*cos(angle)/(1 + 1.05*s + 2.5*s^2) with s = gap*sqrt(R/volume)
+// until it ruptures at Lian et al.'s (1 + angle/2)*volume^(1/3).
+// The model and its parameters come from the pair (see Settings::derive_pair_cohesion), so a mixed pair can have its own.
+fn cohesion(a: u32, b: u32, gap: f32, touched: bool) -> vec2<f32> {
+    let range = cohesion_range(a, b);
+    if material_pointers[a] == -1 || material_pointers[b] == -1 || gap >= range {
+        return vec2(0.0, 0.0);
+    }
+    let mat_a = materials[material_pointers[a]];
+    let mat_b = materials[material_pointers[b]];
+    let pair = material_pair(a, b);
+    let eff_radius = radii[a]*radii[b]/(radii[a] + radii[b]);
+    let adhesion = pair.adhesion;
+    if pair.cohesion_model == 1.0 {
+        if !touched {
+            return vec2(0.0, 0.0);
+        }
+        let eff_youngs = 1.0/((1.0 - mat_a.poisson_ratio*mat_a.poisson_ratio)/mat_a.youngs_modulus + (1.0 - mat_b.poisson_ratio*mat_b.poisson_ratio)/mat_b.youngs_modulus);
+        let overlap = -gap;
+        let c = sqrt(2.0*PI*adhesion/eff_youngs);
+        let contact_radius = jkr_radius(overlap, eff_radius, c);
+        let jkr = 4.0*eff_youngs*pow(contact_radius, 3.0)/(3.0*eff_radius) - 2.0*eff_youngs*c*pow(contact_radius, 1.5);
+        let squeeze = max(overlap, 0.0);
+        let hertz = 4.0/3.0*eff_youngs*sqrt(eff_radius*squeeze)*squeeze;
+        // the work done squeezing it in from the neck, less the Hertz part the contact model already has
+        let neck = pow(PI*adhesion*eff_radius*eff_radius/(8.0*eff_youngs), 1.0/3.0);
+        let work = jkr_work(contact_radius, eff_radius, c, eff_youngs) - jkr_work(neck, eff_radius, c, eff_youngs);
+        return vec2(hertz - jkr, work - 0.4*hertz*squeeze);
+    } else if pair.cohesion_model == 2.0 {
+        let pull_off = 2.0*PI*adhesion*eff_radius;
+        return vec2(pull_off, pull_off*gap);
+    } else if pair.cohesion_model == 3.0 {
+        let volume = pair.liquid_volume;
+        if volume <= 0.0 || !touched {
+            return vec2(0.0, 0.0);
+        }
+        let angle = radians(pair.contact_angle);
+        let bridge = 2.0*PI*eff_radius*pair.surface_tension*cos(angle);
+        let k = sqrt(eff_radius/volume);
+        let s = k*max(gap, 0.0);
+        // the force integrates to an arctangent, q = sqrt(4*2.5 - 1.05^2)
+        let q = sqrt(10.0 - 1.05*1.05);
+        let potential = -bridge*2.0/(k*q)*(atan((5.0*k*range + 1.05)/q) - atan((5.0*s + 1.05)/q));
+        return vec2(bridge/(1.0 + 1.05*s + 2.5*s*s), potential + bridge*min(gap, 0.0));
+    }
+    return vec2(0.0, 0.0);
+}
+
+// JKR contact radius at this overlap, solving overlap = a^2/R - c*sqrt(a) with c = sqrt(2*pi*adhesion/E).
+// Newton's method from the right of the root, where the curve rises and is convex, closes in from one side.
+// The neck, where the curve turns, is as small as it gets.
+fn jkr_radius(overlap: f32, eff_radius: f32, c: f32) -> f32 {
+    let neck = pow(eff_radius*eff_radius*c*c/16.0, 1.0/3.0);
+    var contact_radius = pow(4.5*eff_radius*eff_radius*c*c, 1.0/3.0) + sqrt(eff_radius*max(overlap, 0.0));
+    for(var k = 0; k < 16; k++){
+        let slope = 2.0*contact_radius/eff_radius - 0.5*c/sqrt(contact_radius);
+        if slope <= 0.0 {
+            break;
+        }
+        let miss = contact_radius*contact_radius/eff_radius - c*sqrt(contact_radius) - overlap;
+        contact_radius = max(contact_radius - miss/slope, neck);
+    }
+    return contact_radius;
+}
+
+// The JKR force integrated over the overlap, as a function of the contact radius (up to a constant)
+fn jkr_work(contact_radius: f32, eff_radius: f32, c: f32, eff_youngs: f32) -> f32 {
+    let a = contact_radius;
+    return eff_youngs*(8.0*pow(a, 5.0)/(15.0*eff_radius*eff_radius) - 4.0*c*pow(a, 3.5)/(3.0*eff_radius) + 0.5*c*c*a*a);
+}
+
+fn shape_contact(a: u32, b: u32) -> Shape_Contact {
+    let offset = separation(a, b);
+    if is_polygon(a) && is_polygon(b) {
+        return polygon_polygon(a, b, offset);
+    } else if is_polygon(a) {
+        return polygon_disc(a, b, offset);
+    } else if is_polygon(b) {
+        let flipped = polygon_disc(b, a, -offset);
+        return Shape_Contact(-flipped.normal, flipped.point + offset, flipped.overlap);
+    }
+    let normal = -normalize(offset);
+    let overlap = radii[a] + radii[b] - length(offset);
+    return Shape_Contact(normal, -normal*(radii[a] - 0.5*overlap), overlap);
+}
+
+// Polygon p against disc d, whose center is at offset from p.
+// Inside the polygon the disc is pushed out through the nearest face, outside it's the closest point on the boundary.
+fn polygon_disc(p: u32, d: u32, offset: vec2<f32>) -> Shape_Contact {
+    let count = polygon_info[p].y;
+    var face_distance = -1e30;
+    var face_normal = vec2(0.0, 0.0);
+    for(var k = 0; k < count; k++){
+        let v = polygon_vertex(p, k);
+        let edge = polygon_vertex(p, (k + 1)%count) - v;
+        let n = normalize(vec2(edge.y, -edge.x));
+        let s = dot(offset - v, n);
+        if s > face_distance {
+            face_distance = s;
+            face_normal = n;
+        }
+    }
+    var n = face_normal;
+    var closest = offset - face_normal*face_distance;
+    var overlap = radii[d] - face_distance;
+    if face_distance > 0.0 {
+        var distance = 1e30;
+        for(var k = 0; k < count; k++){
+            let v = polygon_vertex(p, k);
+            let edge = polygon_vertex(p, (k + 1)%count) - v;
+            let q = v + edge*clamp(dot(offset - v, edge)/dot(edge, edge), 0.0, 1.0);
+            if length(offset - q) < distance {
+                distance = length(offset - q);
+                closest = q;
+            }
+        }
+        n = (offset - closest)/distance;
+        overlap = radii[d] - distance;
+    }
+    return Shape_Contact(-n, closest - 0.5*overlap*n, overlap);
+}
+
+// Separating axis test, b is at offset from a. The face normal (of either) with the least overlap is the contact normal.
+// The other polygon's deepest corners are the contact, averaged so two flat faces push along their middle.
+fn polygon_polygon(a: u32, b: u32, offset: vec2<f32>) -> Shape_Contact {
+    let count_a = polygon_info[a].y;
+    let count_b = polygon_info[b].y;
+    var overlap = 1e30;
+    var axis = vec2(0.0, 0.0);
+    var reference_a = true;
+    for(var k = 0; k < count_a; k++){
+        let v = polygon_vertex(a, k);
+        let edge = polygon_vertex(a, (k + 1)%count_a) - v;
+        let n = normalize(vec2(edge.y, -edge.x));
+        var deepest = 1e30;
+        for(var j = 0; j < count_b; j++){
+            deepest = min(deepest, dot(polygon_vertex(b, j) + offset, n));
+        }
+        if dot(v, n) - deepest < overlap {
+            overlap = dot(v, n) - deepest;
+            axis = n;
+            reference_a = true;
+        }
+    }
+    for(var k = 0; k < count_b; k++){
+        let v = polygon_vertex(b, k) + offset;
+        let edge = polygon_vertex(b, (k + 1)%count_b) + offset - v;
+        let n = normalize(vec2(edge.y, -edge.x));
+        var deepest = 1e30;
+        for(var j = 0; j < count_a; j++){
+            deepest = min(deepest, dot(polygon_vertex(a, j), n));
+        }
+        if dot(v, n) - deepest < overlap {
+            overlap = dot(v, n) - deepest;
+            axis = n;
+            reference_a = false;
+        }
+    }
+    // the corners on the other side of the reference face
+    var incident = b;
+    var shift = offset;
+    if !reference_a {
+        incident = a;
+        shift = vec2(0.0, 0.0);
+    }
+    let count = polygon_info[incident].y;
+    var deepest = 1e30;
+    for(var j = 0; j < count; j++){
+        deepest = min(deepest, dot(polygon_vertex(incident, j) + shift, axis));
+    }
+    let tolerance = 0.05*radii[incident]; // MTIF
+    var point = vec2(0.0, 0.0);
+    var corners = 0.0;
+    for(var j = 0; j < count; j++){
+        let corner = polygon_vertex(incident, j) + shift;
+        if dot(corner, axis) <= deepest + tolerance {
+            point += corner;
+            corners += 1.0;
+        }
+    }
+    point = point/corners + 0.5*overlap*axis;
+    // axis points out of the reference polygon
+    if reference_a {
+        return Shape_Contact(-axis, point, overlap);
+    }
+    return Shape_Contact(axis, point, overlap);
+}
+
+// Line segment walls, returns the force (xy) and moment (z) on the particle.
+fn segment_walls(id: u32) -> vec3<f32> {
+    let mass = particle_mass(id);
+    var wall_force = vec3(0.0, 0.0, 0.0);
+    for(var w = 0; w<settings.wall_count; w++){
+        let contact = wall_contact(id, w, mass);
+        if contact.x != 0.0 || contact.y != 0.0 {
+            let seg = walls[w].end - walls[w].start;
+            let point = walls[w].start + seg*wall_t(walls[w], positions[id]);
+            add_stress(radii[id]*normalize(point - positions[id]), contact.xy);
+        }
+        wall_force += contact;
+        wall_heat(id, w);
+    }
+    return wall_force;
+}
+
+// how far along the wall the closest point to pos is, 0 at the start and 1 at the end
+fn wall_t(wall: Wall, pos: vec2<f32>) -> f32 {
+    let seg = wall.end - wall.start;
+    if dot(seg, seg) > 0.0 {
+        return clamp(dot(pos - wall.start, seg)/dot(seg, seg), 0.0, 1.0);
+    }
+    return 0.0;
+}
+
+// Normal is a linear spring with a dashpot picked so a head-on bounce comes out at the wall's restitution.
+// Tangential is Coulomb friction, capped at what would stop the contact point sliding this step.
+// Both act on the velocity relative to the wall, platens move along their left normal.
+fn wall_contact(id: u32, w: i32, mass: f32) -> vec3<f32> {
+    let pos = positions[id];
+    let base_vel = vec2(settings.base_vel_x, settings.base_vel_y);
+    var rad = radii[id];
+    let wall = walls[w];
+    let seg = wall.end - wall.start;
+    let t = wall_t(wall, pos);
+    wall_overlap = 0.0;
+    // the shared corner of a polyline belongs to the previous wall, and an outflow wall lets everything through to be taken out
+    if (wall.joined == 1.0 && t == 0.0) || wall.control == 3.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let delta = pos - (wall.start + seg*t);
+    let dist = length(delta);
+    // a polygon reaches the wall with whichever corner is farthest toward it
+    var arm = vec2(0.0, 0.0);
+    if is_polygon(id) && dist > 0.0 {
+        arm = support(id, -delta/dist);
+        rad = dot(arm, -delta/dist);
+    }
+    let overlap = rad - dist;
+    // written so a NaN position doesn't count as a contact
+    if !(overlap > 0.0) || dist == 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    wall_overlap = overlap;
+    let normal = delta/dist;
+    let tangent = vec2(-normal.y, normal.x);
+    var velocity = velocities[id] - base_vel;
+    if wall.speed != 0.0 {
+        velocity -= vec2(-seg.y, seg.x)/length(seg)*wall.speed;
+    }
+
+    var damping_ratio = 1.0;
+    if wall.restitution > 0.0 {
+        let log_e = log(wall.restitution);
+        damping_ratio = -log_e/sqrt(PI*PI + log_e*log_e);
+    }
+    let dashpot = 2.0*damping_ratio*sqrt(wall.stiffness*mass);
+    let normal_force = max(wall.stiffness*overlap - dashpot*dot(velocity, normal), 0.0);
+    // walls don't move in response, so the particle books all of it (the platens pass just throws this away)
+    particle_energy.strain += 0.5*wall.stiffness*overlap*overlap;
+    if normal_force > 0.0 {
+        particle_energy.damping += dashpot*dot(velocity, normal)*dot(velocity, normal)*settings.delta_time;
+    }
+
+    // velocity of the contact point along the wall, a disc resists sliding with a third of its mass
+    var slip = dot(velocity, tangent) - rot_vel[id]*rad;
+    if is_polygon(id) {
+        slip = dot(velocity + rot_vel[id]*vec2(-arm.y, arm.x), tangent);
+    }
+    var friction_limit = wall.friction*normal_force;
+    if settings.friction == 0 {
+        friction_limit = 0.0;
+    }
+    let tangent_force = -sign(slip)*min(friction_limit, mass*abs(slip)/(3.0*settings.delta_time));
+    particle_energy.friction += abs(tangent_force*slip)*settings.delta_time;
+    // a shaking base does work through the wall
+    particle_energy.work += dot(normal*normal_force + tangent*tangent_force, base_vel)*settings.delta_time;
+
+    if is_polygon(id) {
+        return vec3(normal*normal_force + tangent*tangent_force, cross_2d(arm, normal*normal_force + tangent*tangent_force));
+    }
+    return vec3(normal*normal_force + tangent*tangent_force, -rad*tangent_force);
+}
+
+// Batchelor-O'Brien conduction, H = 2*k*a for a contact half-width a, k being the harmonic mean of the two conductivities.
+// Both particles see the contact (or bond), each books the heat going its own way so the total is conserved.
+fn conduct(a: u32, b: u32, half_width: f32) {
+    if settings.heat_conduction != 1 {
+        return;
+    }
+    let k_a = conductivity(a);
+    let k_b = conductivity(b);
+    if k_a + k_b <= 0.0 {
+        return;
+    }
+    let k = 2.0*k_a*k_b/(k_a + k_b);
+    heat += 2.0*k*half_width*(temperature[b] - temperature[a]);
+}
+
+// Thermal walls, fixed temperature conducts like a contact with the particle's own conductivity, heat flux is per unit length of the contact.
+// Uses the overlap from the wall_contact call just before it.
+fn wall_heat(id: u32, w: i32) {
+    if settings.heat_conduction != 1 || wall_overlap <= 0.0 {
+        return;
+    }
+    let half_width = sqrt(radii[id]*wall_overlap);
+    if walls[w].thermal == 1.0 {
+        heat += 2.0*conductivity(id)*half_width*(walls[w].heat - temperature[id]);
+    } else if walls[w].thermal == 2.0 {
+        heat += 2.0*half_width*walls[w].heat;
+    }
+}
+
+fn store_temperature(id: u32) {
+    temperature_buf[id] = temperature[id];
+    if settings.heat_conduction != 1 {
+        return;
+    }
+    let capacity = particle_mass(id)*specific_heat(id);
+    if capacity > 0.0 {
+        temperature_buf[id] += heat*settings.delta_time/capacity;
+    }
+}
+
+// Slides the Lees-Edwards images along, dispatched once after everything else in the step.
+// Kept modulo the width, separation only cares about where the image is, not how far it's gone.
+@compute @workgroup_size(1)
+fn lees_edwards() {
+    let width = 2.0*settings.hor_bound;
+    shear_offset += settings.shear_velocity*settings.delta_time;
+    shear_offset -= round(shear_offset/width)*width;
+}
+
+// Rigid clumps, one invocation per clump, dispatched right after the laws of motion.
+// Every member has just taken its own step, so pooling their momentum gives the step the clump would have taken as one body:
+// the center of mass moves with the total momentum, and the spin is the total angular momentum about it over the clump's inertia.
+// Then the members are put back in formation around the new center at the new angle.
+// Pooling does lose the energy of the members' relative motion (second order in the timestep), which the budget doesn't book.
+// Fixity and applied forces still act on each member, and the clump shares them out.
+@compute @workgroup_size(256)
+fn clump(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let c = global_id.x;
+    if c >= arrayLength(&clumps) || clumps[c].y < 2 {
+        return;
+    }
+    let deltaTime = settings.delta_time;
+    let start = u32(clumps[c].x);
+    let end = start + u32(clumps[c].y);
+    // measured from the first member, so a clump can sit across a periodic seam
+    let first = u32(clump_members[start].id);
+    let origin = positions[first];
+    var mass = 0.0;
+    var center = vec2(0.0, 0.0);
+    var momentum = vec2(0.0, 0.0);
+    for(var i = start; i < end; i++){
+        let id = u32(clump_members[i].id);
+        let m = particle_mass(id);
+        mass += m;
+        center += m*separation(first, id);
+        momentum += m*image_velocity(first, id);
+    }
+    center /= mass;
+    let velocity = momentum/mass;
+    var angular_momentum = 0.0;
+    var inertia = 0.0;
+    for(var i = start; i < end; i++){
+        let id = u32(clump_members[i].id);
+        let m = particle_mass(id);
+        let arm = separation(first, id) - center;
+        let body = vec2(clump_members[i].x, clump_members[i].y);
+        angular_momentum += rot_inertia(id)*rot_vel[id] + m*cross_2d(arm, image_velocity(first, id) - velocity);
+        inertia += rot_inertia(id) + m*dot(body, body);
+    }
+    let spin = angular_momentum/inertia;
+    // the first member's rotation before its step, less its offset, is where the clump was
+    let angle = rot[first] - rot_vel[first]*deltaTime - clump_members[start].rot + spin*deltaTime;
+    for(var i = start; i < end; i++){
+        let id = u32(clump_members[i].id);
+        let arm = rotate(vec2(clump_members[i].x, clump_members[i].y), angle);
+        positions[id] = origin + center + arm;
+        velocities[id] = velocity + spin*vec2(-arm.y, arm.x);
+        velocities_buf[id] = velocities[id];
+        rot[id] = (angle + clump_members[i].rot)%(2.0*PI);
+        rot_vel[id] = spin;
+        rot_vel_buf[id] = spin;
+        wrap(id);
+    }
+}
+
+fn cross_2d(a: vec2<f32>, b: vec2<f32>) -> f32 {
+    return a.x*b.y - a.y*b.x;
+}
+
+fn rotate(v: vec2<f32>, angle: f32) -> vec2<f32> {
+    return vec2(v.x*cos(angle) - v.y*sin(angle), v.x*sin(angle) + v.y*cos(angle));
+}
+
+// wrap() in 2D_LOM.wgsl, a member put back in formation can land outside a periodic box
+fn wrap(id: u32) {
+    if settings.boundary_y != 0 {
+        let height = 2.0*settings.vert_bound;
+        let shift = floor((positions[id].y + settings.vert_bound)/height);
+        if shift != 0.0 {
+            positions[id].y -= shift*height;
+            if settings.boundary_y == 2 {
+                positions[id].x -= shift*shear_offset;
+                velocities[id].x -= shift*settings.shear_velocity;
+                velocities_buf[id].x -= shift*settings.shear_velocity;
+            }
+        }
+    }
+    if settings.boundary_x != 0 {
+        let width = 2.0*settings.hor_bound;
+        positions[id].x -= floor((positions[id].x + settings.hor_bound)/width)*width;
+    }
+}
+
+var<workgroup> platen_force: array<vec2<f32>, 256>;
+var<workgroup> platen_centroid: array<vec3<f32>, 256>;
+
+// One workgroup per wall, dispatched after main.
+// Sums the force every particle feels from the wall (the reaction is the opposite), then moves the platen for the next step.
+// Strain rate platens move at setpoint times their distance from the centroid of the particles, so two opposing platens close at setpoint*L.
+// Servo platens move at gain*(setpoint - load/length).
+@compute @workgroup_size(256)
+fn platens(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_id) local_id: vec3<u32>) {
+    let w = i32(workgroup_id.x);
+    let count = u32(settings.particles);
+    var force = vec2(0.0, 0.0);
+    var centroid = vec3(0.0, 0.0, 0.0);
+    // only the broadphase cells around the wall, a cell is at least a particle across so one more all round reaches everything touching it
+    let first = max(cell_coords(min(walls[w].start, walls[w].end)) - vec2(1, 1), vec2(0, 0));
+    let last = min(cell_coords(max(walls[w].start, walls[w].end)) + vec2(1, 1), vec2(settings.coll_grid_w - 1, settings.coll_grid_h - 1));
+    let span = last - first + vec2(1, 1);
+    for(var k = i32(local_id.x); k<span.x*span.y; k += 256){
+        let c = u32((first.y + k/span.x)*settings.coll_grid_w + first.x + k%span.x);
+        for(var s = cell_starts[c]; s<cell_starts[c+1u]; s++){
+            let id = sorted_particles[s];
+            force += wall_contact(id, w, particle_mass(id)).xy;
+        }
+    }
+    // the servo aims at the middle of everything, so that one still goes through every particle
+    if walls[w].joined != 1.0 && walls[w].control == 1.0 {
+        for(var id = local_id.x; id<count; id += 256u){
+            // only particles inside the box, which also skips any NaNs
+            if abs(positions[id].x) <= settings.hor_bound && abs(positions[id].y) <= settings.vert_bound {
+                centroid += vec3(positions[id], 1.0);
+            }
+        }
+    }
+    platen_force[local_id.x] = force;
+    platen_centroid[local_id.x] = centroid;
+    workgroupBarrier();
+
+    for(var offset = 128u; offset>0u; offset /= 2u){
+        if local_id.x < offset {
+            platen_force[local_id.x] += platen_force[local_id.x + offset];
+            platen_centroid[local_id.x] += platen_centroid[local_id.x + offset];
+        }
+        workgroupBarrier();
+    }
+
+    if local_id.x == 0u {
+        // a shaking base carries every wall along with it, as far as its displacement says (see Excitation::carry_walls)
+        let base_step = vec2(settings.base_step_x, settings.base_step_y);
+        walls[w].start += base_step;
+        walls[w].end += base_step;
+        let seg = walls[w].end - walls[w].start;
+        let seg_length = length(seg);
+        if seg_length == 0.0 {
+            return;
+        }
+        let normal = vec2(-seg.y, seg.x)/seg_length;
+        walls[w].load = dot(platen_force[0], normal);
+        walls[w].shear = dot(platen_force[0], seg/seg_length);
+
+        var speed = 0.0;
+        if walls[w].joined != 1.0 && walls[w].control == 1.0 && platen_centroid[0].z > 0.0 {
+            let middle = (walls[w].start + walls[w].end)/2.0;
+            speed = walls[w].setpoint*dot(platen_centroid[0].xy/platen_centroid[0].z - middle, normal);
+        } else if walls[w].joined != 1.0 && walls[w].control == 2.0 {
+            speed = walls[w].gain*(walls[w].setpoint - walls[w].load/seg_length);
+        }
+        walls[w].speed = speed;
+        walls[w].start += normal*speed*settings.delta_time;
+        walls[w].end += normal*speed*settings.delta_time;
+    }
+}
@@ -0,0 +1,140 @@
+struct Settings {
+    hor_bound: f32,
+    vert_bound: f32,
+    gravity: i32,
+    planet_mode: i32,
+    bonds: i32,
+    collisions: i32,
+    friction: i32,
+    friction_coefficient: f32,
+    rotation: i32,
+    linear_contact_bonds: i32,
+    gravity_x: f32,
+    gravity_y: f32,
+    stiffness: f32,
+    bonds_tear: i32,
+    bond_force_limit: f32,
+    force_scale: f32,
+    bond_shear_lim: f32,
+    coll_cell_size: f32,
+    coll_grid_w: i32,
+    coll_grid_h: i32,
+    delta_time: f32,
+    contact_model: i32,
+    rolling_model: i32,
+    rolling_coefficient: f32,
+    wall_count: i32,
+    wall_elasticity: f32,
+    wall_rot_damping: f32,
+    bond_normal_stiffness: f32,
+    bond_shear_stiffness: f32,
+    bond_radius_multiplier: f32,
+    bond_tensile_strength: f32,
+    bond_cohesion: f32,
+    bond_friction_angle: f32,
+    boundary_x: i32,
+    boundary_y: i32,
+    shear_velocity: f32,
+    damping_model: i32,
+    local_damping: f32,
+    contact_restitution: f32,
+    base_acc_x: f32,
+    base_acc_y: f32,
+    base_vel_x: f32,
+    base_vel_y: f32,
+    base_offset_x: f32,
+    base_offset_y: f32,
+    base_step_x: f32,
+    base_step_y: f32,
+    self_gravity: i32,
+    heat_conduction: i32,
+    fluid: i32,
+    fluid_density: f32,
+    particles: i32
+}
+
+@group(0) @binding(0) var<storage, read_write> positions: array<vec2<f32>>;
+@group(1) @binding(5) var<storage, read_write> cell_counts: array<atomic<u32>>;
+@group(1) @binding(6) var<storage, read_write> cell_starts: array<u32>;
+@group(1) @binding(7) var<storage, read_write> sorted_particles: array<u32>;
+@group(2) @binding(0) var<uniform> settings: Settings;
+
+// Broadphase grid, rebuilt every generation between LOM and Simulation.
+// clear -> count -> scan -> sort, each is its own pass so they run in order.
+// Afterwards the particles in cell c are sorted_particles[cell_starts[c]..cell_starts[c+1]].
+// grid.rs builds the same thing on the CPU.
+
+var<workgroup> partial_sums: array<u32, 256>;
+
+fn cell_index(pos: vec2<f32>) -> u32 {
+    let cell = vec2<i32>(floor((pos + vec2(settings.hor_bound, settings.vert_bound))/settings.coll_cell_size));
+    let clamped = clamp(cell, vec2(0, 0), vec2(settings.coll_grid_w - 1, settings.coll_grid_h - 1));
+    return u32(clamped.y*settings.coll_grid_w + clamped.x);
+}
+
+@compute @workgroup_size(256)
+fn clear(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let c: u32 = global_id.x;
+    if c >= u32(settings.coll_grid_w*settings.coll_grid_h) {
+        return;
+    }
+    atomicStore(&cell_counts[c], 0u);
+}
+
+@compute @workgroup_size(256)
+fn count(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let id: u32 = global_id.x;
+    if id >= u32(settings.particles) {
+        return;
+    }
+    atomicAdd(&cell_counts[cell_index(positions[id])], 1u);
+}
+
+// Exclusive prefix sum over the cell counts, dispatched as a single workgroup.
+// Each thread sums a contiguous chunk of cells, the chunk totals get scanned in workgroup memory, then each thread writes out its chunk.
+// cell_counts is left holding the start of each cell so sort can use it as a cursor.
+@compute @workgroup_size(256)
+fn scan(@builtin(local_invocation_id) local_id: vec3<u32>) {
+    let cells = u32(settings.coll_grid_w*settings.coll_grid_h);
+    let chunk = (cells + 255u)/256u;
+    let start = min(local_id.x*chunk, cells);
+    let end = min(start + chunk, cells);
+
+    var sum = 0u;
+    for(var c = start; c<end; c++){
+        sum += atomicLoad(&cell_counts[c]);
+    }
+    partial_sums[local_id.x] = sum;
+    workgroupBarrier();
+
+    for(var offset = 1u; offset<256u; offset *= 2u){
+        var value = 0u;
+        if local_id.x >= offset {
+            value = partial_sums[local_id.x - offset];
+        }
+        workgroupBarrier();
+        partial_sums[local_id.x] += value;
+        workgroupBarrier();
+    }
+
+    var running = partial_sums[local_id.x] - sum;
+    for(var c = start; c<end; c++){
+        let cell_count = atomicLoad(&cell_counts[c]);
+        cell_starts[c] = running;
+        atomicStore(&cell_counts[c], running);
+        running += cell_count;
+    }
+    if local_id.x == 255u {
+        cell_starts[cells] = partial_sums[255];
+    }
+}
+
+@compute @workgroup_size(256)
+fn sort(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let id: u32 = global_id.x;
+    if id >= u32(settings.particles) {
+        return;
+    }
+    let slot = atomicAdd(&cell_counts[cell_index(positions[id])], 1u);
+    sorted_particles[slot] = id;
+}
@@ -4,7 +4,7 @@ struct Input {
     delX: f32,
     delY: f32,
     ticks: i32,
-    temp1: f32,
+    delta_time: f32,
     temp2: f32,
     temp3: f32,
 }
@@ -36,8 +36,6 @@ struct Forces {
 @group(2) @binding(7) var<storage, read_write> forces: array<Forces>;
 @group(3) @binding(0) var<storage, read_write> click_info: array<i32>;
 
-const deltaTime: f32 = 0.0000390625;
-
 @compute @workgroup_size(256)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let id: u32 = global_id.x;
@@ -49,8 +47,8 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
             0,
         );
         if click_info[0] == 1 {
-            velocities[id] = vec2(input.delX, input.delY)/(deltaTime*f32(input.ticks));
-            velocities_buf[id] = vec2(input.delX, input.delY)/(deltaTime*f32(input.ticks));
+            velocities[id] = vec2(input.delX, input.delY)/(input.delta_time*f32(input.ticks));
+            velocities_buf[id] = vec2(input.delX, input.delY)/(input.delta_time*f32(input.ticks));
         }
     }
     click_info[0] = 0;
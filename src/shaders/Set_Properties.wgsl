@@ -15,6 +15,8 @@ struct Input {
     y_fixity: i32,
     rot_fixity: i32,
     radius: f32,
+    set_temperature: i32,
+    temperature: f32,
 }
 
 struct Particle_Settings {
@@ -41,6 +43,8 @@ struct Forces {
 @group(1) @binding(5) var<storage, read_write> acc: array<vec3<f32>>;
 @group(1) @binding(6) var<storage, read_write> fixity: array<Particle_Settings>;
 @group(1) @binding(7) var<storage, read_write> forces: array<Forces>;
+@group(1) @binding(10) var<storage, read_write> temperature: array<f32>;
+@group(1) @binding(11) var<storage, read_write> temperature_buf: array<f32>;
 @group(2) @binding(0) var<storage, read_write> radii: array<f32>;
 @group(3) @binding(3) var<storage, read_write> contact_pointers: array<i32>;
 @group(3) @binding(4) var<storage, read_write> material_pointers: array<i32>;
@@ -61,5 +65,6 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         if input.set_y_fixity == 1 { fixity[id].y_vel = input.y_fixity; } 
         if input.set_rot_fixity == 1 { fixity[id].rot_vel = input.rot_fixity; } 
         if input.set_material == 1 { material_pointers[id] = input.material; } 
+        if input.set_temperature == 1 { temperature[id] = input.temperature; temperature_buf[id] = input.temperature; } 
     }
 }
\ No newline at end of file
@@ -7,7 +7,7 @@ use std::path::PathBuf;
 use bytemuck::{bytes_of, cast_slice};
 use rand::Rng;
 use crate::settings;
-use crate::settings::Structure;
+use crate::settings::{Structure, ParticleShape};
 use crate::setup;
 // use crate::
 // use winit::*;
@@ -15,6 +15,8 @@ use crate::wgpu_structs::*;
 use crate::wgpu_config::*;
 use crate::wgpu_prog::*;
 use crate::setup::*;
+use crate::grid::Grid;
+use crate::fluid;
 
 use wgpu::util::DeviceExt;
 
@@ -27,6 +29,41 @@ extern crate flatbuffers;
 mod schema_generated;
 pub use schema_generated::*;
 
+// Words per bond: index, angle, length, then the parallel bond's normal force, shear force and moment (as f32 bits)
+pub const BOND_SIZE: usize = 6;
+
+// Words per contact: a, b, tangent force, bonded, rolling moment and touched, see Contact in 2D_Simulation.wgsl
+pub const CONTACT_SIZE: usize = 6;
+
+// Energy terms: kinetic, rotational, strain, gravity, then the running totals of friction, damping and work done by forces.
+// Matches Energy in 2D_Simulation.wgsl
+pub const ENERGY_SIZE: usize = 7;
+
+// Floats per wall record, see Settings::add_wall. Matches Wall in 2D_Simulation.wgsl
+pub const WALL_SIZE: usize = 16;
+
+// Floats per emitter and sink record, see Settings::add_emitter and Settings::add_sink
+pub const EMITTER_SIZE: usize = 10;
+pub const SINK_SIZE: usize = 4;
+
+// Floats per material pair record, see Settings::derive_pair. Matches Material_Pair in 2D_Simulation.wgsl
+pub const PAIR_SIZE: usize = 10;
+
+// Breakages the GPU log can hold between drains, anything past this is still counted but not recorded
+pub const MAX_BOND_EVENTS: usize = 4096;
+
+// One torn bond, matches Bond_Event in 2D_Simulation.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BondEvent {
+    pub step: u32,
+    pub a: i32,
+    pub b: i32,
+    pub x: f32,
+    pub y: f32,
+    pub mode: i32, // 1 tension, 2 shear
+}
+
 pub struct State {
     pub p_count: usize,
     pub pos: Vec<f32>,
@@ -40,15 +77,41 @@ pub struct State {
     pub bonds: Vec<i32>,
     pub bond_info: Vec<i32>,
     pub material_pointers: Vec<i32>,
+    // which rigid clump each particle belongs to, -1 for a free disc, see State::clump_buffers
+    pub clump_pointers: Vec<i32>,
+    // start and count of each particle's run of polygon_vertices, -1 and 0 for a disc
+    pub polygon_info: Vec<i32>,
+    // x, y of each corner, counterclockwise about the centroid, in the particle's frame (rotate by rot for the world)
+    pub polygon_vertices: Vec<f32>,
+    // heat capacity is mass times the material's specific heat, see conduct() in 2D_Simulation.wgsl
+    pub temperature: Vec<f32>,
     pub selections: Vec<i32>,
     pub data: Vec<f32>,
+    pub energy: Vec<f32>,
+    // how far the Lees-Edwards image above has slid along, only kept while running (not saved)
+    pub shear_offset: f32,
+    pub walls: Vec<f32>,
+    // damping model, local damping and contact restitution, see Settings::set_damping
+    pub damping_model: i32,
+    pub local_damping: f32,
+    pub contact_restitution: f32,
+    // the run's materials, names and pair table, see Settings::set_materials. Empty for files saved before they were embedded
+    pub materials: Vec<f32>,
+    pub material_pairs: Vec<f32>,
+    pub material_names: Vec<String>,
+    pub bond_events: Vec<BondEvent>,
+    pub bond_breaks: usize,
     pub flatbuffer: Vec<u8>
 }
 
 impl State {
     pub fn new(config: &mut WGPUConfig) -> Self {
+        return State::from_settings(&mut config.prog_settings);
+    }
+
+    pub fn from_settings(settings: &mut settings::Settings) -> Self {
         // Create empty arrays for particle data
-        let p_count = setup::p_count(&mut config.prog_settings);
+        let p_count = setup::p_count(settings);
         let mut pos = vec![0.0 as f32; p_count*2];
         let mut vel = vec![0.0 as f32; p_count*2];
         let mut acc = vec![0.0 as f32; p_count*3];
@@ -57,58 +120,70 @@ impl State {
         let mut forces = vec![0.0 as f32; p_count*6];
         let mut radii = vec![0.0 as f32; p_count];
         let mut fixity = vec![0; p_count*3];
-        let mut bonds = vec![-1; 1];
+        let mut bonds = vec![-1; BOND_SIZE];
         let mut bond_info = vec![-1; 1];
         let mut material_pointers = vec![0; p_count];
+        let mut clump_pointers = vec![-1; p_count];
         let mut selections = vec![0; p_count];
         let mut data = vec![0.0; p_count * 4];
+        let walls = settings.walls.clone();
         let flatbuffer = vec![0 as u8; 1];
 
         // Setup initial state, Fill with random values
-        match config.prog_settings.structure {
+        match settings.structure {
             Structure::Grid => {
-                let bond_vecs = setup::grid(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::grid(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Exp1 => {
-                let bond_vecs = setup::exp1(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::exp1(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Exp2 => {
-                let bond_vecs = setup::exp2(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::exp2(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Exp3 => {
-                let bond_vecs = setup::exp3(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::exp3(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Exp4 => {
-                let bond_vecs = setup::exp4(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::exp4(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Exp5 => {
-                let bond_vecs = setup::exp5(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::exp5(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Exp6 => {
-                let bond_vecs = setup::exp6(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::exp6(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Mats => {
-                let bond_vecs = setup::mats(&mut config.prog_settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                let bond_vecs = setup::mats(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers);
+                bonds = bond_vecs.0;
+                bond_info = bond_vecs.1;
+            },
+            Structure::Clumps => {
+                let bond_vecs = setup::clumps(settings, &mut pos, &mut vel, &mut rot, &mut rot_vel, &mut radii, &mut fixity, &mut forces, &mut material_pointers, &mut clump_pointers);
                 bonds = bond_vecs.0;
                 bond_info = bond_vecs.1;
             },
             Structure::Random => {},
         }
 
+        let (polygon_info, polygon_vertices) = match settings.particle_shape {
+            ParticleShape::Polygon => setup::polygons(settings, &radii),
+            ParticleShape::Disc => ([-1, 0].repeat(p_count), vec![]),
+        };
+
         let mut state = State {
             p_count,
             pos,
@@ -122,12 +197,27 @@ impl State {
             bonds,
             bond_info,
             material_pointers,
+            clump_pointers,
+            polygon_info,
+            polygon_vertices,
+            temperature: vec![settings.initial_temperature; p_count],
             selections,
             data,
+            energy: vec![0.0; ENERGY_SIZE],
+            shear_offset: 0.0,
+            walls,
+            damping_model: settings.damping_model,
+            local_damping: settings.local_damping,
+            contact_restitution: settings.contact_restitution,
+            materials: settings.materials.clone(),
+            material_pairs: settings.material_pairs.clone(),
+            material_names: settings.material_names.clone(),
+            bond_events: vec![],
+            bond_breaks: 0,
             flatbuffer,
         };
 
-        state.save(config);
+        state.save();
 
         return state;
     }
@@ -184,24 +274,31 @@ impl State {
     pub fn regen_bonds(&mut self, config: &mut WGPUConfig) {
 
         let MAX_BONDS = config.prog_settings.max_bonds;
-        let mut bonds = vec![-1; self.p_count*MAX_BONDS*3];
+        let mut bonds = vec![-1; self.p_count*MAX_BONDS*BOND_SIZE];
         let mut bond_info = vec![-1; self.p_count*2];
         let mut found_bonds = true;
+        let grid = Grid::around(&self.pos, 2.0*self.largest_radius()*1.02);
         for i in 0..self.p_count {
             let mut col_num = 0;
-            for j in 0..self.p_count {
-                if j != i {
+            // sorted so bonds come out in the same order as a search over every particle
+            let mut candidates = grid.neighbors(self.pos[i*2], self.pos[i*2+1]);
+            candidates.sort();
+            for j in candidates {
+                if j != i && !self.same_clump(i, j) {
                     if ((self.pos[j*2] - self.pos[i*2]).powf(2.0) + (self.pos[j*2+1] - self.pos[i*2+1]).powf(2.0)).powf(0.5) < (self.radii[i] + self.radii[j])*1.02 {
-                        if col_num < MAX_BONDS && bonds[(i*MAX_BONDS+col_num)*3] == -1 {
-                            bonds[(i*MAX_BONDS+col_num)*3] = j as i32;
+                        if col_num < MAX_BONDS && bonds[(i*MAX_BONDS+col_num)*BOND_SIZE] == -1 {
+                            bonds[(i*MAX_BONDS+col_num)*BOND_SIZE] = j as i32;
                             let delta = (self.pos[j*2] - self.pos[i*2], self.pos[j*2+1] - self.pos[i*2+1]);
                             let magnitude = (delta.0*delta.0 + delta.1*delta.1).powf(0.5);
                             let normalized_delta = (delta.0/magnitude, delta.1/magnitude);
                             let angle = normalized_delta.0.atan2(normalized_delta.1);
                             // println!("({}, {}) vs ({}, {})", normalized_delta.0, normalized_delta.1, angle.sin(), angle.cos());
-                            bonds[(i*MAX_BONDS+col_num)*3+1] = (angle).to_bits() as i32;
-                            bonds[(i*MAX_BONDS+col_num)*3+2] = (magnitude).to_bits() as i32;
-                            // println!("{}, {}, {}", bonds[(i*MAX_BONDS+col_num)*3], angle, magnitude);
+                            bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+1] = (angle).to_bits() as i32;
+                            bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+2] = (magnitude).to_bits() as i32;
+                            bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+3] = 0;
+                            bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+4] = 0;
+                            bonds[(i*MAX_BONDS+col_num)*BOND_SIZE+5] = 0;
+                            // println!("{}, {}, {}", bonds[(i*MAX_BONDS+col_num)*BOND_SIZE], angle, magnitude);
                             col_num += 1;
                             found_bonds = true;
                         } else if col_num == MAX_BONDS{
@@ -217,7 +314,7 @@ impl State {
             let start = index;
             let mut length = 0;
             for j in 0..MAX_BONDS {
-                if bonds[(i*MAX_BONDS+j)*3] != -1 {
+                if bonds[(i*MAX_BONDS+j)*BOND_SIZE] != -1 {
                     length += 1;
                     index += 1;
                 }
@@ -237,7 +334,7 @@ impl State {
         self.bond_info = bond_info;
     }
 
-    pub fn save(&mut self, config: &mut WGPUConfig) {
+    pub fn save(&mut self) {
         let mut builder = flatbuffers::FlatBufferBuilder::new();
 
         let pos = builder.create_vector(&self.pos);
@@ -251,6 +348,15 @@ impl State {
         let bonds = builder.create_vector(&self.bonds);
         let bond_info = builder.create_vector(&self.bond_info);
         let material_pointers = builder.create_vector(&self.material_pointers);
+        let clump_pointers = builder.create_vector(&self.clump_pointers);
+        let polygon_info = builder.create_vector(&self.polygon_info);
+        let polygon_vertices = builder.create_vector(&self.polygon_vertices);
+        let temperature = builder.create_vector(&self.temperature);
+        let walls = builder.create_vector(&self.walls);
+        let materials = builder.create_vector(&self.materials);
+        let material_pairs = builder.create_vector(&self.material_pairs);
+        let names: Vec<_> = self.material_names.iter().map(|name| builder.create_string(name)).collect();
+        let material_names = builder.create_vector(&names);
 
         let state = schema_generated::State::create(&mut builder, &schema_generated::StateArgs{
             particles: self.p_count as i32,
@@ -265,6 +371,17 @@ impl State {
             bonds: Some(bonds),
             bond_info: Some(bond_info),
             material_pointers: Some(material_pointers),
+            walls: Some(walls),
+            damping_model: self.damping_model,
+            local_damping: self.local_damping,
+            contact_restitution: self.contact_restitution,
+            clump_pointers: Some(clump_pointers),
+            polygon_info: Some(polygon_info),
+            polygon_vertices: Some(polygon_vertices),
+            temperature: Some(temperature),
+            materials: Some(materials),
+            material_pairs: Some(material_pairs),
+            material_names: Some(material_names),
         });
 
         builder.finish(state, None);
@@ -279,6 +396,26 @@ impl State {
         Ok(())
     }
 
+    // step, a, b, x, y, mode as CSV, one row per torn bond
+    pub fn export_bond_events(&self, path: PathBuf) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "step,a,b,x,y,mode")?;
+        for event in &self.bond_events {
+            let mode = match event.mode {
+                1 => "tension",
+                2 => "shear",
+                _ => "unknown",
+            };
+            writeln!(file, "{},{},{},{},{},{}", event.step, event.a, event.b, event.x, event.y, mode)?;
+        }
+        Ok(())
+    }
+
+    // Empty log for the GPU: count, step, then MAX_BOND_EVENTS records
+    pub fn bond_log() -> Vec<u32> {
+        return vec![0; 2 + MAX_BOND_EVENTS*mem::size_of::<BondEvent>()/4];
+    }
+
     pub fn load_from_file(&mut self, path: PathBuf) -> std::io::Result<()> {
         let mut file = File::open(path)?;
         let mut buffer = Vec::new();
@@ -301,10 +438,558 @@ impl State {
         self.bonds = State::i32_vec_from_vector(state.bonds());
         self.bond_info = State::i32_vec_from_vector(state.bond_info());
         self.material_pointers = State::i32_vec_from_vector(state.material_pointers());
+        // files saved before walls were added don't have any
+        self.walls = match state.walls() {
+            Some(_) => State::f32_vec_from_vector(state.walls()),
+            None => vec![],
+        };
+        // older files come back with no damping, see schema.fbs for the defaults
+        self.damping_model = state.damping_model();
+        self.local_damping = state.local_damping();
+        self.contact_restitution = state.contact_restitution();
+        // files saved before clumps were added only have free discs
+        self.clump_pointers = match state.clump_pointers() {
+            Some(_) => State::i32_vec_from_vector(state.clump_pointers()),
+            None => vec![-1; self.p_count],
+        };
+        // and only discs
+        (self.polygon_info, self.polygon_vertices) = match state.polygon_info() {
+            Some(_) => (State::i32_vec_from_vector(state.polygon_info()), State::f32_vec_from_vector(state.polygon_vertices())),
+            None => ([-1, 0].repeat(self.p_count), vec![]),
+        };
+        // files saved before materials were embedded have none, Settings::set_materials keeps the current ones
+        self.materials = match state.materials() {
+            Some(_) => State::f32_vec_from_vector(state.materials()),
+            None => vec![],
+        };
+        self.material_pairs = match state.material_pairs() {
+            Some(_) => State::f32_vec_from_vector(state.material_pairs()),
+            None => vec![],
+        };
+        self.material_names = match state.material_names() {
+            Some(names) => names.iter().map(|name| name.to_string()).collect(),
+            None => vec![],
+        };
+        // files saved before heat conduction start out at 20 degrees, and their walls don't have the thermal fields
+        self.temperature = match state.temperature() {
+            Some(_) => State::f32_vec_from_vector(state.temperature()),
+            None => {
+                self.widen_walls();
+                vec![20.0; self.p_count]
+            },
+        };
+        self.widen_bonds();
+        // a file written elsewhere may skip clump numbers or have one-disc clumps
+        self.tidy_clumps();
+        // none of these are saved, a loaded state starts them over
+        self.selections = vec![0; self.p_count];
+        self.data = vec![0.0; self.p_count*4];
+        self.energy = vec![0.0; ENERGY_SIZE];
+        self.shear_offset = 0.0;
+    }
+
+    // Pads each 14 float wall record out to WALL_SIZE with an adiabatic thermal setting
+    fn widen_walls(&mut self) {
+        let walls = self.walls.chunks(14).flat_map(|wall| {
+            let mut wall = wall.to_vec();
+            wall.resize(WALL_SIZE, 0.0);
+            return wall;
+        }).collect();
+        self.walls = walls;
+    }
+
+    // Files saved before the parallel bond only have index, angle and length, so give those bonds zeroed forces
+    fn widen_bonds(&mut self) {
+        let mut bond_count = 0;
+        for i in 0..self.bond_info.len()/2 {
+            if self.bond_info[i*2] != -1 {
+                bond_count = bond_count.max((self.bond_info[i*2] + self.bond_info[i*2+1]) as usize);
+            }
+        }
+        if bond_count == 0 || self.bonds.len() != bond_count*3 {
+            return;
+        }
+        let mut bonds = vec![0; bond_count*BOND_SIZE];
+        for i in 0..bond_count {
+            bonds[i*BOND_SIZE..i*BOND_SIZE+3].copy_from_slice(&self.bonds[i*3..i*3+3]);
+        }
+        self.bonds = bonds;
+    }
+
+    pub fn same_clump(&self, a: usize, b: usize) -> bool {
+        return self.clump_pointers[a] != -1 && self.clump_pointers[a] == self.clump_pointers[b];
+    }
+
+    // Makes the selected particles one rigid clump, taking them out of any clump they were in
+    pub fn clump_selected(&mut self) {
+        let clump = self.clump_count() as i32;
+        for i in 0..self.p_count {
+            if self.selections[i] == 1 {
+                self.clump_pointers[i] = clump;
+            }
+        }
+        self.tidy_clumps();
+    }
+
+    // Frees the selected particles from their clumps
+    pub fn release_selected(&mut self) {
+        for i in 0..self.p_count {
+            if self.selections[i] == 1 {
+                self.clump_pointers[i] = -1;
+            }
+        }
+        self.tidy_clumps();
+    }
+
+    // Numbers the clumps 0, 1, 2... in order of their first member, a clump left with one disc is just a disc
+    fn tidy_clumps(&mut self) {
+        let mut sizes = std::collections::HashMap::new();
+        for &clump in &self.clump_pointers {
+            *sizes.entry(clump).or_insert(0) += 1;
+        }
+        let mut numbers = std::collections::HashMap::new();
+        for i in 0..self.p_count {
+            let clump = self.clump_pointers[i];
+            if clump == -1 || sizes[&clump] < 2 {
+                self.clump_pointers[i] = -1;
+                continue;
+            }
+            let next = numbers.len() as i32;
+            self.clump_pointers[i] = *numbers.entry(clump).or_insert(next);
+        }
+    }
+
+    // Takes out every particle that isn't kept and closes up the gaps, returning where each old particle ended up (-1 if it's gone).
+    // Bonds to a removed particle go with it. Anything else held per particle (the contacts) has to be moved along using the map.
+    pub fn remove_particles(&mut self, keep: &[bool]) -> Vec<i32> {
+        let mut map = vec![-1; self.p_count];
+        let mut count = 0;
+        for i in 0..self.p_count {
+            if keep[i] {
+                map[i] = count;
+                count += 1;
+            }
+        }
+        // bond_info is a placeholder when nothing was ever bonded, see State::from_settings
+        if self.bond_info.len() == self.p_count*2 {
+            let mut bonds = vec![];
+            let mut bond_info = vec![];
+            for i in 0..self.p_count {
+                if !keep[i] {
+                    continue;
+                }
+                let (start, length) = (self.bond_info[i*2], self.bond_info[i*2+1]);
+                let first = bonds.len()/BOND_SIZE;
+                for b in start.max(0)..start.max(0) + length.max(0) {
+                    let bond = &self.bonds[b as usize*BOND_SIZE..(b as usize + 1)*BOND_SIZE];
+                    // torn bonds (and empty -1 slots) are left behind, a torn bond is its partner's index negated
+                    // and one to whichever particle ends up as 0 would come back as a live bond
+                    if bond[0] < 0 {
+                        continue;
+                    }
+                    let other = bond[0] as usize;
+                    if other >= self.p_count || !keep[other] {
+                        continue;
+                    }
+                    bonds.extend_from_slice(bond);
+                    let last = bonds.len() - BOND_SIZE;
+                    bonds[last] = map[other];
+                }
+                let kept = (bonds.len()/BOND_SIZE - first) as i32;
+                if kept > 0 {
+                    bond_info.extend_from_slice(&[first as i32, kept]);
+                } else {
+                    bond_info.extend_from_slice(&[-1, -1]);
+                }
+            }
+            if bonds.is_empty() {
+                bonds = vec![-1; BOND_SIZE];
+            }
+            self.bonds = bonds;
+            self.bond_info = bond_info;
+        }
+        let mut polygon_info = vec![];
+        let mut polygon_vertices = vec![];
+        for i in 0..self.p_count {
+            if !keep[i] {
+                continue;
+            }
+            if self.is_polygon(i) {
+                let (start, corners) = (self.polygon_info[i*2] as usize, self.polygon_info[i*2+1] as usize);
+                polygon_info.extend_from_slice(&[(polygon_vertices.len()/2) as i32, corners as i32]);
+                polygon_vertices.extend_from_slice(&self.polygon_vertices[start*2..(start + corners)*2]);
+            } else {
+                polygon_info.extend_from_slice(&[-1, 0]);
+            }
+        }
+        self.polygon_info = polygon_info;
+        self.polygon_vertices = polygon_vertices;
+        State::compact(&mut self.pos, keep, 2);
+        State::compact(&mut self.vel, keep, 2);
+        State::compact(&mut self.acc, keep, 3);
+        State::compact(&mut self.rot, keep, 1);
+        State::compact(&mut self.rot_vel, keep, 1);
+        State::compact(&mut self.forces, keep, 6);
+        State::compact(&mut self.radii, keep, 1);
+        State::compact(&mut self.fixity, keep, 3);
+        State::compact(&mut self.material_pointers, keep, 1);
+        State::compact(&mut self.clump_pointers, keep, 1);
+        State::compact(&mut self.temperature, keep, 1);
+        State::compact(&mut self.selections, keep, 1);
+        State::compact(&mut self.data, keep, 4);
+        self.p_count = count as usize;
+        self.tidy_clumps();
+        return map;
+    }
+
+    // What gets embedded on the next save
+    pub fn copy_materials(&mut self, settings: &settings::Settings) {
+        self.materials = settings.materials.clone();
+        self.material_pairs = settings.material_pairs.clone();
+        self.material_names = settings.material_names.clone();
+    }
+
+    // After Settings::remove_material, particles of the removed material go over to the first one left and the rest shift down
+    pub fn remove_material(&mut self, material: usize) {
+        let material = material as i32;
+        for pointer in self.material_pointers.iter_mut() {
+            if *pointer == material {
+                *pointer = 0;
+            } else if *pointer > material {
+                *pointer -= 1;
+            }
+        }
+    }
+
+    // Moves each kept run of stride values down over the ones that aren't
+    fn compact<T: Copy>(vector: &mut Vec<T>, keep: &[bool], stride: usize) {
+        let mut count = 0;
+        for i in 0..keep.len() {
+            if keep[i] {
+                vector.copy_within(i*stride..(i + 1)*stride, count*stride);
+                count += 1;
+            }
+        }
+        vector.truncate(count*stride);
+    }
+
+    // Appends a free, unbonded particle, a random polygon of the setup's sides if the setup is polygons.
+    pub fn add_particle(&mut self, settings: &settings::Settings, pos: (f32, f32), vel: (f32, f32), radius: f32, material: i32) {
+        if self.bond_info.len() == self.p_count*2 {
+            self.bond_info.extend_from_slice(&[-1, -1]);
+        }
+        match settings.particle_shape {
+            ParticleShape::Polygon => {
+                let (info, vertices) = setup::polygons(settings, &vec![radius]);
+                self.polygon_info.extend_from_slice(&[(self.polygon_vertices.len()/2) as i32, info[1]]);
+                self.polygon_vertices.extend_from_slice(&vertices);
+            },
+            ParticleShape::Disc => self.polygon_info.extend_from_slice(&[-1, 0]),
+        }
+        self.pos.extend_from_slice(&[pos.0, pos.1]);
+        self.vel.extend_from_slice(&[vel.0, vel.1]);
+        self.acc.extend_from_slice(&[0.0; 3]);
+        self.rot.push(0.0);
+        self.rot_vel.push(0.0);
+        self.forces.extend_from_slice(&[0.0; 6]);
+        self.radii.push(radius);
+        self.fixity.extend_from_slice(&[0; 3]);
+        self.material_pointers.push(material);
+        self.clump_pointers.push(-1);
+        self.temperature.push(settings.initial_temperature);
+        self.selections.push(0);
+        self.data.extend_from_slice(&[0.0; 4]);
+        self.p_count += 1;
+    }
+
+    // Shortest vector from a to b, to whichever periodic image of b is closest (separation() in 2D_Simulation.wgsl)
+    pub fn separation(&self, a: usize, b: usize, settings: &settings::Settings) -> (f32, f32) {
+        let pos = &self.pos;
+        let mut delta = (pos[b*2] - pos[a*2], pos[b*2+1] - pos[a*2+1]);
+        let size = (2.0*settings.hor_bound, 2.0*settings.vert_bound);
+        if settings.boundary_y != 0 {
+            let shift = (delta.1/size.1).round();
+            delta.1 -= shift*size.1;
+            if settings.boundary_y == 2 {
+                delta.0 -= shift*self.shear_offset;
+            }
+        }
+        if settings.boundary_x != 0 {
+            delta.0 -= (delta.0/size.0).round()*size.0;
+        }
+        return delta;
+    }
+
+    pub fn clump_count(&self) -> usize {
+        return (self.clump_pointers.iter().fold(-1, |a, &b| a.max(b)) + 1) as usize;
+    }
+
+    // The clumps for the GPU, matching clumps and clump_members in 2D_Simulation.wgsl.
+    // clumps is the start and count of each clump's run of members, a member is its particle then where it sits in the clump (as f32 bits):
+    // x and y from the center of mass, and its rotation less the clump's.
+    // The body frame is wherever the clump is now, so a clump's angle starts at zero and each member keeps its own rotation as the offset.
+    // Both get a dummy entry when there aren't any clumps, the GPU can't have empty buffers.
+    pub fn clump_buffers(&self, settings: &settings::Settings) -> (Vec<i32>, Vec<i32>) {
+        let count = self.clump_count();
+        let mut members = vec![vec![]; count];
+        for i in 0..self.p_count {
+            if self.clump_pointers[i] != -1 {
+                members[self.clump_pointers[i] as usize].push(i);
+            }
+        }
+        let masses = self.masses(settings);
+        let mut clumps = vec![];
+        let mut clump_members = vec![];
+        for c in 0..count {
+            clumps.extend_from_slice(&[(clump_members.len()/4) as i32, members[c].len() as i32]);
+            // a number nothing points to, tidy_clumps() shouldn't leave any but it's just a clump with no members
+            if members[c].is_empty() {
+                continue;
+            }
+            // measured from the first member, so a clump can sit across a periodic seam
+            let first = members[c][0];
+            let (mut mass, mut center) = (0.0, (0.0, 0.0));
+            for &i in &members[c] {
+                let delta = self.separation(first, i, settings);
+                mass += masses[i];
+                center = (center.0 + masses[i]*delta.0, center.1 + masses[i]*delta.1);
+            }
+            center = (center.0/mass, center.1/mass);
+            for &i in &members[c] {
+                let delta = self.separation(first, i, settings);
+                clump_members.extend_from_slice(&[i as i32, (delta.0 - center.0).to_bits() as i32, (delta.1 - center.1).to_bits() as i32, self.rot[i].to_bits() as i32]);
+            }
+        }
+        if count == 0 {
+            clumps = vec![0, 0];
+            clump_members = vec![-1, 0, 0, 0];
+        }
+        return (clumps, clump_members);
+    }
+
+    pub fn masses(&self, settings: &settings::Settings) -> Vec<f32> {
+        let mut masses = vec![0.0; self.p_count];
+        for i in 0..self.p_count {
+            let mut density = 1.0; // MTIF
+            let mat_id = self.material_pointers[i];
+            if mat_id != -1 {
+                density = settings.materials[mat_id as usize*settings.material_size + 3];
+            }
+            masses[i] = density*std::f32::consts::PI*self.radii[i]*self.radii[i];
+            if self.is_polygon(i) {
+                masses[i] = density*self.polygon_area(i);
+            }
+        }
+        return masses;
+    }
+
+    pub fn densities(&self, settings: &settings::Settings) -> Vec<f32> {
+        let mut densities = vec![1.0; self.p_count]; // MTIF
+        for i in 0..self.p_count {
+            let mat_id = self.material_pointers[i];
+            if mat_id != -1 {
+                densities[i] = settings.materials[mat_id as usize*settings.material_size + 3];
+            }
+        }
+        return densities;
+    }
+
+    pub fn areas(&self) -> Vec<f32> {
+        let mut areas = vec![0.0; self.p_count];
+        for i in 0..self.p_count {
+            areas[i] = std::f32::consts::PI*self.radii[i]*self.radii[i];
+            if self.is_polygon(i) {
+                areas[i] = self.polygon_area(i);
+            }
+        }
+        return areas;
+    }
+
+    // Drag coupling for fluid.rs, fluid velocity x, y and drag rate per particle.
+    pub fn fluid_drag(&self, settings: &settings::Settings) -> Vec<f32> {
+        return fluid::drag(&settings.fluid, (settings.hor_bound, settings.vert_bound), &self.pos, &self.vel, &self.areas(), &self.densities(settings));
+    }
+
+    pub fn is_polygon(&self, i: usize) -> bool {
+        return self.polygon_info[i*2+1] > 2;
+    }
+
+    // corner k of particle i in its own frame
+    fn body_vertex(&self, i: usize, k: usize) -> (f32, f32) {
+        let v = (self.polygon_info[i*2] as usize + k)*2;
+        return (self.polygon_vertices[v], self.polygon_vertices[v+1]);
+    }
+
+    // corner k of particle i in the world frame, relative to its position
+    pub fn polygon_vertex(&self, i: usize, k: usize) -> (f32, f32) {
+        let (x, y) = self.body_vertex(i, k);
+        let (sin, cos) = self.rot[i].sin_cos();
+        return (x*cos - y*sin, x*sin + y*cos);
+    }
+
+    pub fn polygon_area(&self, i: usize) -> f32 {
+        let count = self.polygon_info[i*2+1] as usize;
+        let mut area = 0.0;
+        for k in 0..count {
+            let (a, b) = (self.body_vertex(i, k), self.body_vertex(i, (k + 1)%count));
+            area += 0.5*(a.0*b.1 - b.0*a.1);
+        }
+        return area;
+    }
+
+    // Second moment of area about the centroid, times density it's the moment of inertia
+    pub fn polygon_inertia(&self, i: usize) -> f32 {
+        let count = self.polygon_info[i*2+1] as usize;
+        let mut inertia = 0.0;
+        for k in 0..count {
+            let (a, b) = (self.body_vertex(i, k), self.body_vertex(i, (k + 1)%count));
+            inertia += (a.0*b.1 - b.0*a.1)*(a.0*a.0 + a.1*a.1 + a.0*b.0 + a.1*b.1 + b.0*b.0 + b.1*b.1)/12.0;
+        }
+        return inertia;
+    }
+
+    // polygon_vertices for the GPU, which can't have an empty buffer when they're all discs
+    pub fn polygon_vertex_buffer(&self) -> Vec<f32> {
+        if self.polygon_vertices.is_empty() {
+            return vec![0.0; 2];
+        }
+        return self.polygon_vertices.clone();
     }
 
-    pub fn get_datum(&self, prop: &crate::settings::Property) -> Option<[f64;10]> {
-        let mut sums = [0.0; 10];
+    // Scales each polygon so its farthest corner is on its radius again, after the radius was set from the Properties window
+    pub fn fit_polygons(&mut self) {
+        for i in 0..self.p_count {
+            if !self.is_polygon(i) {
+                continue;
+            }
+            let (start, count) = (self.polygon_info[i*2] as usize, self.polygon_info[i*2+1] as usize);
+            let corners = &mut self.polygon_vertices[start*2..(start + count)*2];
+            let reach = corners.chunks(2).fold(0.0, |a: f32, c| a.max((c[0]*c[0] + c[1]*c[1]).sqrt()));
+            if reach > 0.0 {
+                for c in corners.iter_mut() {
+                    *c *= self.radii[i]/reach;
+                }
+            }
+        }
+    }
+
+    pub fn largest_radius(&self) -> f32 {
+        return self.radii.iter().fold(0.0, |a: f32, &b| a.max(b));
+    }
+
+    // dt = sqrt(min_particle_mass/max_stiffness), the period of the fastest contact spring over 2 PI.
+    // The shaders scale every contact and bond force by settings.force_scale, so that goes into the stiffness as well.
+    // Hertz-Mindlin has no fixed stiffness, so that uses the Rayleigh timestep PI*r*sqrt(density/G)/(0.1631*v + 0.8766) instead.
+    // Viscous contact damping shortens it by sqrt(1 + z^2) - z, z being the largest contact damping ratio.
+    // With heat conduction on it's no longer than thermal_delta_time either.
+    pub fn critical_delta_time(&self, settings: &settings::Settings) -> f32 {
+        let thermal = self.thermal_delta_time(settings);
+        let mut min_mass = f32::INFINITY;
+        let mut max_stiffness = 0.0;
+        let mut rayleigh = f32::INFINITY;
+        let masses = self.masses(settings);
+        for i in 0..self.p_count {
+            let mut density = 1.0; // MTIF
+            let mut normal_stiffness = 10.0; // MTIF
+            let mat_id = self.material_pointers[i];
+            if mat_id != -1 {
+                let mat = mat_id as usize*settings.material_size;
+                density = settings.materials[mat + 3];
+                normal_stiffness = settings.materials[mat + 4];
+                let (youngs, poisson) = (settings.materials[mat + 6], settings.materials[mat + 7]);
+                let shear_modulus = youngs/(2.0*(1.0 + poisson))*settings.force_scale;
+                rayleigh = f32::min(rayleigh, std::f32::consts::PI*self.radii[i]*(density/shear_modulus).sqrt()/(0.1631*poisson + 0.8766));
+            }
+            min_mass = f32::min(min_mass, masses[i]);
+            max_stiffness = f32::max(max_stiffness, normal_stiffness);
+        }
+        // a pair can be stiffer than either of its materials
+        let mut used = vec![false; settings.material_count()];
+        for &mat_id in self.material_pointers.iter().filter(|&&mat_id| mat_id != -1) {
+            used[mat_id as usize] = true;
+        }
+        for a in 0..used.len() {
+            for b in a..used.len() {
+                if used[a] && used[b] {
+                    max_stiffness = f32::max(max_stiffness, settings.pair(a, b)[0]);
+                }
+            }
+        }
+        // wall contacts are linear springs and aren't scaled by force_scale
+        let mut wall_stiffness = 0.0;
+        for i in 0..settings.wall_count() {
+            wall_stiffness = f32::max(wall_stiffness, settings.walls[i*settings.wall_size + 4]);
+        }
+        let ratio = settings.contact_damping_ratio();
+        let viscous = (1.0 + ratio*ratio).sqrt() - ratio;
+        // the parallel bond's normal spring is its stiffness per unit area times the bond's cross section
+        let mut bond_stiffness = 0.0;
+        if settings.bonds == 3 {
+            bond_stiffness = settings.bond_normal_stiffness*2.0*settings.bond_radius_multiplier*self.largest_radius()*settings.force_scale;
+        }
+        if settings.contact_model == 1 && rayleigh.is_finite() {
+            if wall_stiffness > 0.0 {
+                rayleigh = f32::min(rayleigh, (min_mass/wall_stiffness).sqrt());
+            }
+            if bond_stiffness > 0.0 {
+                rayleigh = f32::min(rayleigh, (min_mass/bond_stiffness).sqrt());
+            }
+            return f32::min(rayleigh*viscous, thermal);
+        }
+        if settings.bonds == 1 || settings.bonds == 2 {
+            max_stiffness = f32::max(max_stiffness, settings.stiffness);
+        }
+        max_stiffness = f32::max(max_stiffness*settings.force_scale, f32::max(wall_stiffness, bond_stiffness));
+        if max_stiffness <= 0.0 || !min_mass.is_finite() {
+            return thermal;
+        }
+        return f32::min((min_mass/max_stiffness).sqrt()*viscous, thermal);
+    }
+
+    // Conduction is stepped explicitly (store_temperature in 2D_Simulation.wgsl), which only settles while dt < m*c/H,
+    // H being the sum of the conductances 2*k*a into the particle.
+    // Every contact slot and fixed temperature wall is counted as touching with the most conductive material there is,
+    // and each contact's half width a as the radius (or the bond's cement where that's wider), so this errs short.
+    pub fn thermal_delta_time(&self, settings: &settings::Settings) -> f32 {
+        if !settings.heat_conduction {
+            return f32::INFINITY;
+        }
+        let mut conductivity: f32 = 0.0;
+        for &mat_id in self.material_pointers.iter() {
+            let mut k = 1.0; // MTIF
+            if mat_id != -1 {
+                k = settings.materials[mat_id as usize*settings.material_size + 14];
+            }
+            conductivity = conductivity.max(k);
+        }
+        let mut neighbors = settings.max_contacts;
+        for w in 0..settings.wall_count() {
+            if settings.walls[w*settings.wall_size + 14] == 1.0 {
+                neighbors += 1;
+            }
+        }
+        let mut width = 1.0;
+        if settings.bonds != 0 {
+            width = settings.bond_radius_multiplier.max(1.0);
+        }
+        let masses = self.masses(settings);
+        let mut delta_time = f32::INFINITY;
+        for i in 0..self.p_count {
+            let mut specific_heat = 1.0; // MTIF
+            let mat_id = self.material_pointers[i];
+            if mat_id != -1 {
+                specific_heat = settings.materials[mat_id as usize*settings.material_size + 15];
+            }
+            let conductance = neighbors as f32*2.0*conductivity*width*self.radii[i];
+            if conductance > 0.0 {
+                delta_time = delta_time.min(masses[i]*specific_heat/conductance);
+            }
+        }
+        return delta_time;
+    }
+
+    pub fn get_datum(&self, prop: &crate::settings::Property) -> Option<[f64;11]> {
+        let mut sums = [0.0; 11];
         let mut count = 0;
         for i in 0..self.selections.len() {
             if self.selections[i] == 1 {
@@ -315,10 +1000,12 @@ impl State {
                 sums[3] += self.vel[i*2+1] as f64;
                 sums[4] += self.rot[i]     as f64;
                 sums[5] += self.rot_vel[i] as f64;
+                // stress tensor, see store_stress in 2D_Simulation.wgsl
                 sums[6] += self.data[i*4] as f64;
                 sums[7] += self.data[i*4+1] as f64;
                 sums[8] += self.data[i*4+2] as f64;
                 sums[9] += self.data[i*4+3] as f64;
+                sums[10] += self.temperature[i] as f64;
             }
         }
 
@@ -364,11 +1051,43 @@ impl State {
         State::update_f32(config, &mut self.acc, &mut buffers.mov_buffers.buffers[5]);
         State::update_i32(config, &mut self.fixity, &mut buffers.mov_buffers.buffers[6]);
         State::update_f32(config, &mut self.forces, &mut buffers.mov_buffers.buffers[7]);
+        let mut shear_offset = vec![self.shear_offset];
+        State::update_f32(config, &mut shear_offset, &mut buffers.mov_buffers.buffers[8]);
+        self.shear_offset = shear_offset[0];
+        State::update_f32(config, &mut self.temperature, &mut buffers.mov_buffers.buffers[10]);
         State::update_i32(config, &mut self.bonds, &mut buffers.contact_buffers.buffers[0]);
         State::update_i32(config, &mut self.bond_info, &mut buffers.contact_buffers.buffers[1]);
         State::update_i32(config, &mut self.material_pointers, &mut buffers.contact_buffers.buffers[4]);
         State::update_i32(config, &mut self.selections, &mut buffers.selections.buffer);
-        State::update_f32(config, &mut self.data, &mut buffers.data_buffer.buffer);
+        State::update_f32(config, &mut self.data, &mut buffers.data_buffers.buffers[0]);
+        State::update_f32(config, &mut self.energy, &mut buffers.data_buffers.buffers[2]);
+        self.damping_model = config.prog_settings.damping_model;
+        self.local_damping = config.prog_settings.local_damping;
+        self.contact_restitution = config.prog_settings.contact_restitution;
+        self.copy_materials(&config.prog_settings);
+        // platens move on the GPU, so the walls get read back as well
+        self.walls = config.prog_settings.walls.clone();
+        if config.prog_settings.wall_count() > 0 {
+            State::update_f32(config, &mut self.walls, &mut buffers.wall_buffer.buffer);
+            config.prog_settings.walls = self.walls.clone();
+        }
+        // drain the bond breakage log, the header is read first so the records only get copied when there are some
+        let mut header = vec![0; 2];
+        State::update_i32(config, &mut header, &mut buffers.contact_buffers.buffers[8]);
+        let count = header[0] as usize;
+        if count > 0 {
+            let mut log = vec![0; 2 + count.min(MAX_BOND_EVENTS)*mem::size_of::<BondEvent>()/4];
+            State::update_i32(config, &mut log, &mut buffers.contact_buffers.buffers[8]);
+            self.bond_events.extend_from_slice(bytemuck::cast_slice(&log[2..]));
+            self.bond_breaks += count;
+            config.queue.write_buffer(&buffers.contact_buffers.buffers[8], 0, bytemuck::cast_slice(&[0 as u32]));
+        }
+        config.prog_settings.bond_breaks = self.bond_breaks;
+        // the most particles that hit their contact or bond cap in a step since the last read, see Overflow in 2D_Simulation.wgsl
+        let mut overflow = vec![0; 4];
+        State::update_i32(config, &mut overflow, &mut buffers.data_buffers.buffers[3]);
+        config.prog_settings.overflows = [overflow[2] as usize, overflow[3] as usize];
+        config.queue.write_buffer(&buffers.data_buffers.buffers[3], 0, bytemuck::cast_slice(&[0 as u32; 4]));
 
     }
 
@@ -483,3 +1202,142 @@ impl State {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{Settings, Structure};
+
+    // count unbonded particles at the origin
+    fn particles(count: usize) -> State {
+        let mut settings = Settings::new();
+        settings.structure = Structure::Random;
+        settings.particles = count;
+        let mut state = State::from_settings(&mut settings);
+        state.bond_info = vec![-1; count*2];
+        return state;
+    }
+
+    // bonds[i] lists who particle i is bonded to, negative for torn
+    fn bond(state: &mut State, bonds: &[&[i32]]) {
+        state.bonds = vec![];
+        for (i, partners) in bonds.iter().enumerate() {
+            if partners.is_empty() {
+                continue;
+            }
+            state.bond_info[i*2] = (state.bonds.len()/BOND_SIZE) as i32;
+            state.bond_info[i*2+1] = partners.len() as i32;
+            for &partner in partners.iter() {
+                state.bonds.extend_from_slice(&[partner, 0, 0, 0, 0, 0]);
+            }
+        }
+    }
+
+    fn partners(state: &State, i: usize) -> Vec<i32> {
+        let (start, length) = (state.bond_info[i*2], state.bond_info[i*2+1]);
+        if start == -1 {
+            return vec![];
+        }
+        return (start..start + length).map(|b| state.bonds[b as usize*BOND_SIZE]).collect();
+    }
+
+    #[test]
+    fn thermal_timestep_shortens_with_conductivity() {
+        let mut settings = Settings::new();
+        let mut state = particles(2);
+        state.radii = vec![0.01, 0.02];
+        assert_eq!(state.thermal_delta_time(&settings), f32::INFINITY);
+        settings.heat_conduction = true;
+        let slow = state.thermal_delta_time(&settings);
+        assert!(slow.is_finite() && slow > 0.0);
+        settings.materials[14] *= 100.0;
+        let fast = state.thermal_delta_time(&settings);
+        assert!((fast*100.0 - slow).abs() < 1e-3*slow);
+        // and the critical timestep goes along with it
+        assert!(state.critical_delta_time(&settings) <= fast);
+    }
+
+    #[test]
+    fn removing_particle_0_keeps_torn_bonds_torn() {
+        let mut state = particles(4);
+        // 1 and 2 were bonded and tore, 2 and 3 still are, 3 has an empty slot
+        bond(&mut state, &[&[], &[-2], &[-1, 3], &[2, -1]]);
+        let map = state.remove_particles(&[false, true, true, true]);
+        assert_eq!(map, vec![-1, 0, 1, 2]);
+        assert_eq!(state.p_count, 3);
+        // old 1 is now 0, its torn bond must not come back as a bond to 0
+        assert_eq!(partners(&state, 0), Vec::<i32>::new());
+        assert_eq!(partners(&state, 1), vec![2]);
+        assert_eq!(partners(&state, 2), vec![1]);
+    }
+
+    #[test]
+    fn removing_particles_drops_their_bonds() {
+        let mut state = particles(3);
+        bond(&mut state, &[&[1, 2], &[0], &[0]]);
+        state.remove_particles(&[true, false, true]);
+        assert_eq!(partners(&state, 0), vec![1]);
+        assert_eq!(partners(&state, 1), vec![0]);
+        assert_eq!(state.pos.len(), 4);
+        assert_eq!(state.radii.len(), 2);
+    }
+
+    #[test]
+    fn added_particles_go_on_the_end_unbonded() {
+        let settings = Settings::new();
+        let mut state = particles(2);
+        bond(&mut state, &[&[1], &[0]]);
+        state.add_particle(&settings, (0.5, -0.5), (1.0, 0.0), 0.01, 0);
+        assert_eq!(state.p_count, 3);
+        assert_eq!(&state.pos[4..6], &[0.5, -0.5]);
+        assert_eq!(&state.vel[4..6], &[1.0, 0.0]);
+        assert_eq!(state.radii[2], 0.01);
+        assert_eq!(partners(&state, 2), Vec::<i32>::new());
+        assert_eq!(partners(&state, 0), vec![1]);
+    }
+
+    #[test]
+    fn loaded_clumps_are_renumbered_and_singles_freed() {
+        let settings = Settings::new();
+        let mut state = particles(5);
+        state.clump_pointers = vec![5, -1, 5, 7, -1];
+        state.save();
+        state.clump_pointers = vec![];
+        state.load();
+        assert_eq!(state.clump_pointers, vec![0, -1, 0, -1, -1]);
+        let (clumps, members) = state.clump_buffers(&settings);
+        assert_eq!(clumps, vec![0, 2]);
+        assert_eq!(members.len(), 8);
+    }
+
+    #[test]
+    fn clump_buffers_skip_unused_numbers() {
+        let settings = Settings::new();
+        let mut state = particles(3);
+        state.clump_pointers = vec![1, -1, 1];
+        let (clumps, members) = state.clump_buffers(&settings);
+        assert_eq!(clumps, vec![0, 0, 0, 2]);
+        assert_eq!((members[0], members[4]), (0, 2));
+    }
+
+    #[test]
+    fn old_bonds_are_widened_with_zero_forces() {
+        let mut state = particles(3);
+        // 0 bonded to 1 and 2, 1 to 0, 2 has none, each bond index, angle and length
+        state.bond_info = vec![0, 2, 2, 1, -1, 0];
+        state.bonds = vec![1, 10, 11, 2, 20, 21, 0, 30, 31];
+        state.widen_bonds();
+        assert_eq!(state.bonds.len(), 3*BOND_SIZE);
+        for (k, old) in [[1, 10, 11], [2, 20, 21], [0, 30, 31]].iter().enumerate() {
+            assert_eq!(&state.bonds[k*BOND_SIZE..k*BOND_SIZE + 3], old);
+            assert!(state.bonds[k*BOND_SIZE + 3..(k + 1)*BOND_SIZE].iter().all(|&f| f == 0));
+        }
+        // already wide, and never bonded, are left alone
+        let wide = state.bonds.clone();
+        state.widen_bonds();
+        assert_eq!(state.bonds, wide);
+        let mut state = particles(2);
+        state.bonds = vec![0];
+        state.widen_bonds();
+        assert_eq!(state.bonds, vec![0]);
+    }
+}
@@ -1,7 +1,6 @@
 use std::fmt::DebugTuple;
 
 use bytemuck::bytes_of;
-use image::EncodableLayout;
 use rand::Rng;
 use wgpu::Device;
 use crate::settings;
@@ -13,6 +12,9 @@ use crate::wgpu_structs::*;
 use crate::wgpu_config::*;
 use crate::setup::*;
 use crate::state::*;
+use crate::barnes_hut;
+use crate::fluid;
+use crate::emitter::Emitters;
 
 extern crate flatbuffers;
 use wgpu::util::DeviceExt;
@@ -38,6 +40,8 @@ pub struct WGPUProg {
     pub render_pipeline2: wgpu::RenderPipeline,
     pub render_pipeline3: wgpu::RenderPipeline,
     pub render_pipeline4: wgpu::RenderPipeline,
+    pub wall_render_pipeline: wgpu::RenderPipeline,
+    pub fluid_render_pipeline: wgpu::RenderPipeline,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub clear_color: wgpu::Color,
@@ -311,6 +315,117 @@ impl WGPUProg {
             }
         );
 
+        // Walls only need the dimensions and the wall buffer, the big layout above is already at the bind group limit
+        let wall_shader = config.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Wall Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/2D_Render_Walls.wgsl").into()),
+        });
+        let wall_render_pipeline_layout =
+        config.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Wall Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &dim_uniform.bind_group_layout,
+                &shader_prog.buffers.wall_buffer.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let wall_render_pipeline = config.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Wall Render Pipeline"),
+            layout: Some(&wall_render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &wall_shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &wall_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                format: DepthBuffer::DEPTH_FORMAT,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+              }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        // The fluid overlay goes over everything, so it skips the depth test
+        let fluid_shader = config.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fluid Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/2D_Render_Fluid.wgsl").into()),
+        });
+        let fluid_render_pipeline_layout =
+        config.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &dim_uniform.bind_group_layout,
+                &shader_prog.buffers.fluid_buffer.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let fluid_render_pipeline = config.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fluid Render Pipeline"),
+            layout: Some(&fluid_render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &fluid_shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fluid_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                format: DepthBuffer::DEPTH_FORMAT,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+              }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
         
         
         Self{
@@ -320,6 +435,8 @@ impl WGPUProg {
             render_pipeline2,
             render_pipeline3,
             render_pipeline4,
+            wall_render_pipeline,
+            fluid_render_pipeline,
             vertex_buffer,
             index_buffer,
             clear_color,
@@ -390,8 +507,10 @@ pub struct BufferContainer {
     pub drag_input: Uniform,
     pub set_prop_input: Uniform,
     pub selections: BufferUniform,
-    pub data_buffer: BufferUniform,
-    pub material_buffer: BufferUniform,
+    pub data_buffers: BufferGroup,
+    pub material_buffer: BufferGroup,
+    pub wall_buffer: BufferUniform,
+    pub fluid_buffer: BufferUniform,
 }
 
 impl BufferContainer {
@@ -408,8 +527,10 @@ impl BufferContainer {
         drag_input: Uniform,
         set_prop_input: Uniform,
         selections: BufferUniform,
-        data_buffer: BufferUniform,
-        material_buffer: BufferUniform,
+        data_buffers: BufferGroup,
+        material_buffer: BufferGroup,
+        wall_buffer: BufferUniform,
+        fluid_buffer: BufferUniform,
         ) -> Self {
         
         Self {
@@ -425,8 +546,10 @@ impl BufferContainer {
             drag_input,
             set_prop_input,
             selections,
-            data_buffer,
+            data_buffers,
             material_buffer,
+            wall_buffer,
+            fluid_buffer,
         }
 
         
@@ -438,6 +561,18 @@ pub struct WGPUComputeProg {
     pub buffers: BufferContainer,
     pub compute_pipeline: wgpu::ComputePipeline,
     pub compute_pipeline2: wgpu::ComputePipeline,
+    pub platen_pipeline: wgpu::ComputePipeline,
+    pub energy_pipeline: wgpu::ComputePipeline,
+    pub lees_edwards_pipeline: wgpu::ComputePipeline,
+    pub clump_pipeline: wgpu::ComputePipeline,
+    pub clump_count: usize,
+    // arrows in the fluid overlay, one per fluid cell
+    pub fluid_cells: usize,
+    pub emitters: Emitters,
+    pub grid_clear_pipeline: wgpu::ComputePipeline,
+    pub grid_count_pipeline: wgpu::ComputePipeline,
+    pub grid_scan_pipeline: wgpu::ComputePipeline,
+    pub grid_sort_pipeline: wgpu::ComputePipeline,
     pub click_compute_shader: wgpu::ShaderModule,
     pub click_compute_pipeline: wgpu::ComputePipeline,
     pub selectangle_compute_shader: wgpu::ShaderModule,
@@ -458,9 +593,17 @@ impl WGPUComputeProg {
         let state = State::new(config);
 
         let p_count = setup::p_count(&mut config.prog_settings);
-        let mut contacts = vec![bytemuck::cast::<i32, f32>(-1); 4*config.prog_settings.max_contacts*p_count];
+        let mut contacts = vec![bytemuck::cast::<i32, f32>(-1); CONTACT_SIZE*config.prog_settings.max_contacts*p_count];
         let mut contact_pointers = vec![-1; config.prog_settings.max_contacts*p_count];
         let mut cilck_info = vec![0; 4];
+        config.prog_settings.set_coll_grid(state.largest_radius());
+        config.prog_settings.critical_delta_time = state.critical_delta_time(&config.prog_settings);
+        let cell_counts = vec![0 as u32; config.prog_settings.coll_cells()];
+        let cell_starts = vec![0 as u32; config.prog_settings.coll_cells() + 1];
+        let sorted_particles = vec![0 as u32; p_count];
+        let bond_log = State::bond_log();
+        let (clumps, clump_members) = state.clump_buffers(&config.prog_settings);
+        let clump_count = state.clump_count();
 
         // Convert arrays to GPU buffers
         let pos_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&state.pos), "Position Buffer".to_string(), 0);
@@ -473,6 +616,11 @@ impl WGPUComputeProg {
             bytemuck::cast_slice(&state.acc),
             bytemuck::cast_slice(&state.fixity),
             bytemuck::cast_slice(&state.forces),
+            bytemuck::cast_slice(&[state.shear_offset]),
+            bytemuck::cast_slice(&vec![0.0 as f32; p_count*3]),
+            bytemuck::cast_slice(&state.temperature),
+            bytemuck::cast_slice(&state.temperature),
+            bytemuck::cast_slice(&vec![0.0 as f32; p_count*3]),
         ], "Movement Buffer".to_string() );
         let radii_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&state.radii), "Radii Buffer".to_string(), 0);
         let mut contact_buffers = BufferGroup::new(&config.device, vec![
@@ -481,11 +629,26 @@ impl WGPUComputeProg {
             bytemuck::cast_slice(&contacts),
             bytemuck::cast_slice(&contact_pointers),
             bytemuck::cast_slice(&state.material_pointers),
+            bytemuck::cast_slice(&cell_counts),
+            bytemuck::cast_slice(&cell_starts),
+            bytemuck::cast_slice(&sorted_particles),
+            bytemuck::cast_slice(&bond_log),
+            bytemuck::cast_slice(&state.clump_pointers),
+            bytemuck::cast_slice(&clumps),
+            bytemuck::cast_slice(&clump_members),
+            bytemuck::cast_slice(&state.polygon_info),
+            bytemuck::cast_slice(&state.polygon_vertex_buffer()),
             ], "Contact Buffers".to_string() );
         // let contact_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&contacts), "Contact Buffer".to_string(), 0);
         // let bond_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&bonds), "Bond Buffer".to_string(), 0);
         // let bond_info_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&state.bond_info), "Bond Info Buffer".to_string(), 0);
-        let material_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&config.prog_settings.materials), "Materials".to_string(), 0);
+        // materials, then the material pair table (see Settings::derive_pair)
+        let material_buffer = BufferGroup::new(&config.device, vec![
+            bytemuck::cast_slice(&config.prog_settings.materials),
+            bytemuck::cast_slice(&config.prog_settings.material_pairs),
+        ], "Materials".to_string());
+        let wall_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&config.prog_settings.wall_buffer()), "Walls".to_string(), 0);
+        let fluid_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&[0.0 as f32; 4]), "Fluid Overlay".to_string(), 0);
         let collision_settings = Uniform::new(&config.device, bytemuck::cast_slice(&config.prog_settings.collison_settings()), "Collision Settings".to_string(), 0);
         
         let click_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&cilck_info), "Color Buffer".to_string(), 0);
@@ -494,9 +657,15 @@ impl WGPUComputeProg {
         let selectangle_input = Uniform::new(&config.device, bytemuck::cast_slice(&[0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32]), "Selectangle Data".to_string(), 0);
         let release_input = Uniform::new(&config.device, bytemuck::cast_slice(&[0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32]), "Release Data".to_string(), 0);
         let drag_input = Uniform::new(&config.device, bytemuck::cast_slice(&[0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32]), "Drag Data".to_string(), 0);
-        let set_prop_input = Uniform::new(&config.device, bytemuck::cast_slice(&[0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32]), "Drag Data".to_string(), 0);
+        let set_prop_input = Uniform::new(&config.device, bytemuck::cast_slice(&[0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32, 0.0 as f32]), "Drag Data".to_string(), 0);
         let selections = BufferUniform::new(&config.device, bytemuck::cast_slice(&state.selections), "Selection Buffer".to_string(), 0);
-        let data_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&state.data), "Selection Buffer".to_string(), 0);
+        let particle_energy = vec![0.0 as f32; ENERGY_SIZE*p_count];
+        let data_buffers = BufferGroup::new(&config.device, vec![
+            bytemuck::cast_slice(&state.data),
+            bytemuck::cast_slice(&particle_energy),
+            bytemuck::cast_slice(&state.energy),
+            bytemuck::cast_slice(&[0 as u32; 4]),
+            ], "Data Buffers".to_string() );
         let hit_tex = Texture::new_from_dimensions(&config, dimensions, 0, wgpu::TextureFormat::Bgra8Unorm);
         
         let buffers = BufferContainer::new(
@@ -512,8 +681,10 @@ impl WGPUComputeProg {
             drag_input,
             set_prop_input,
             selections,
-            data_buffer,
-            material_buffer
+            data_buffers,
+            material_buffer,
+            wall_buffer,
+            fluid_buffer
         );
         // let col_buffer = BufferUniform::new(&config.device, bytemuck::cast_slice(&col_sec), "Collision Buffer".to_string(), 0);
 
@@ -527,7 +698,12 @@ impl WGPUComputeProg {
 
         let compute_shader2 = config.device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/2D_Simulation.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(WGPUComputeProg::simulation_shader(&config.prog_settings).into()),
+        });
+
+        let grid_compute_shader = config.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/Grid.wgsl").into()),
         });
 
         let selectangle_compute_shader = config.device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -574,10 +750,16 @@ impl WGPUComputeProg {
 
         let compute_pipeline_layout2 = config.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Collision compute"),
-            bind_group_layouts: &[&buffers.pos_buffer.bind_group_layout, &buffers.mov_buffers.bind_group_layout, &buffers.radii_buffer.bind_group_layout, &buffers.contact_buffers.bind_group_layout, &buffers.collision_settings.bind_group_layout, &buffers.material_buffer.bind_group_layout, &buffers.data_buffer.bind_group_layout],
+            bind_group_layouts: &[&buffers.pos_buffer.bind_group_layout, &buffers.mov_buffers.bind_group_layout, &buffers.radii_buffer.bind_group_layout, &buffers.contact_buffers.bind_group_layout, &buffers.collision_settings.bind_group_layout, &buffers.material_buffer.bind_group_layout, &buffers.data_buffers.bind_group_layout, &buffers.wall_buffer.bind_group_layout],
             push_constant_ranges: &[]
         });
         
+        let grid_compute_pipeline_layout = config.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid compute"),
+            bind_group_layouts: &[&buffers.pos_buffer.bind_group_layout, &buffers.contact_buffers.bind_group_layout, &buffers.collision_settings.bind_group_layout],
+            push_constant_ranges: &[]
+        });
+
         let drag_compute_pipeline_layout = config.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Translate compute"),
             bind_group_layouts: &[&buffers.drag_input.bind_group_layout, &buffers.selections.bind_group_layout, &buffers.pos_buffer.bind_group_layout, &buffers.mov_buffers.bind_group_layout, &buffers.click_buffer.bind_group_layout],
@@ -634,6 +816,62 @@ impl WGPUComputeProg {
             module: &compute_shader2,
             entry_point: "main",
         });
+
+        let platen_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout2),
+            module: &compute_shader2,
+            entry_point: "platens",
+        });
+
+        let energy_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout2),
+            module: &compute_shader2,
+            entry_point: "energy_budget",
+        });
+
+        let lees_edwards_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout2),
+            module: &compute_shader2,
+            entry_point: "lees_edwards",
+        });
+
+        let clump_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout2),
+            module: &compute_shader2,
+            entry_point: "clump",
+        });
+
+        let grid_clear_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&grid_compute_pipeline_layout),
+            module: &grid_compute_shader,
+            entry_point: "clear",
+        });
+
+        let grid_count_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&grid_compute_pipeline_layout),
+            module: &grid_compute_shader,
+            entry_point: "count",
+        });
+
+        let grid_scan_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&grid_compute_pipeline_layout),
+            module: &grid_compute_shader,
+            entry_point: "scan",
+        });
+
+        let grid_sort_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&grid_compute_pipeline_layout),
+            module: &grid_compute_shader,
+            entry_point: "sort",
+        });
         
         let drag_compute_pipeline = config.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: None,
@@ -689,6 +927,17 @@ impl WGPUComputeProg {
             buffers,
             compute_pipeline,
             compute_pipeline2,
+            platen_pipeline,
+            energy_pipeline,
+            lees_edwards_pipeline,
+            clump_pipeline,
+            clump_count,
+            fluid_cells: 0,
+            emitters: Emitters::new(),
+            grid_clear_pipeline,
+            grid_count_pipeline,
+            grid_scan_pipeline,
+            grid_sort_pipeline,
             click_compute_shader,
             click_compute_pipeline,
             selectangle_compute_shader,
@@ -770,23 +1019,229 @@ impl WGPUComputeProg {
 
     }
 
+    // Puts the saved State back on the GPU, either one just loaded from a file or the one a bond or clump change saved.
+    // The particle count can be anything, every buffer sized by it is made again and the contacts start over.
     pub fn restore(&mut self, config: &mut WGPUConfig) {
         self.state.load();
         println!("{}", self.state.p_count);
-        config.prog_settings.set_particles(self.state.p_count);
-        self.buffers.pos_buffer.updateUniform(&config.device, self.state.pos.as_bytes());
-        self.buffers.radii_buffer.updateUniform(&config.device, self.state.radii.as_bytes());
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.vel.as_bytes(), 0);
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.vel.as_bytes(), 1);
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.rot.as_bytes(), 2);
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.rot_vel.as_bytes(), 3);
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.rot_vel.as_bytes(), 4);
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.acc.as_bytes(), 5);
-        self.buffers.mov_buffers.updateBuffer(&config.device, bytemuck::cast_slice(self.state.fixity.as_slice()), 6);
-        self.buffers.mov_buffers.updateBuffer(&config.device, self.state.forces.as_bytes(), 7);
-        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(self.state.bonds.as_slice()), 0);
-        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(self.state.bond_info.as_slice()), 1);
-        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(self.state.material_pointers.as_slice()), 4);
+        config.prog_settings.walls = self.state.walls.clone();
+        config.prog_settings.set_damping(self.state.damping_model, self.state.local_damping, self.state.contact_restitution);
+        config.prog_settings.set_materials(&self.state.materials, &self.state.material_pairs, &self.state.material_names, self.state.material_pointers.iter().copied().max().unwrap_or(-1));
+        self.state.copy_materials(&config.prog_settings);
+        // there may be a different number of them, so they go up before anything looks a particle's material up
+        self.buffers.material_buffer.updateBuffer(&config.device, bytemuck::cast_slice(&config.prog_settings.materials), 0);
+        self.buffers.material_buffer.updateBuffer(&config.device, bytemuck::cast_slice(&config.prog_settings.material_pairs), 1);
+        self.buffers.wall_buffer.updateUniform(&config.device, bytemuck::cast_slice(&config.prog_settings.wall_buffer()));
+        self.buffers.mov_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&[self.state.shear_offset]), 8);
+        self.buffers.data_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&self.state.energy), 2);
+        let contacts = vec![-1; CONTACT_SIZE*config.prog_settings.max_contacts*self.state.p_count];
+        // the particles the emitters last saw aren't these ones
+        self.emitters = Emitters::new();
+        let (vel, rot_vel, temperature) = (self.state.vel.clone(), self.state.rot_vel.clone(), self.state.temperature.clone());
+        self.upload_particles(config, &vel, &rot_vel, &temperature, &contacts, true);
+    }
+
+    // Uploads the clump membership and rebuilds each clump's body frame from where its discs are now.
+    pub fn update_clumps(&mut self, config: &mut WGPUConfig) {
+        let (clumps, clump_members) = self.state.clump_buffers(&config.prog_settings);
+        self.clump_count = self.state.clump_count();
+        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(self.state.clump_pointers.as_slice()), 9);
+        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&clumps), 10);
+        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&clump_members), 11);
+    }
+
+    pub fn update_polygons(&mut self, config: &mut WGPUConfig) {
+        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(self.state.polygon_info.as_slice()), 12);
+        self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&self.state.polygon_vertex_buffer()), 13);
+    }
+
+    // Moves the particles of materials taken out in the Materials window over, in the order they went
+    pub fn remove_materials(&mut self, config: &mut WGPUConfig, removed: &[usize]) {
+        State::update_i32(config, &mut self.state.material_pointers, &mut self.buffers.contact_buffers.buffers[4]);
+        for &material in removed {
+            self.state.remove_material(material);
+        }
+        config.queue.write_buffer(&self.buffers.contact_buffers.buffers[4], 0, bytemuck::cast_slice(&self.state.material_pointers));
+    }
+
+    // Reads the positions (and whatever sets a mass) back, rebuilds the Barnes-Hut tree on the CPU and uploads the field.
+    // Returns how many generations the field can be held for, the frame is computed in runs of that many with a rebuild between.
+    pub fn update_self_gravity(&mut self, config: &mut WGPUConfig) -> i32 {
+        State::update_f32(config, &mut self.state.pos, &mut self.buffers.pos_buffer.buffer);
+        State::update_f32(config, &mut self.state.radii, &mut self.buffers.radii_buffer.buffer);
+        State::update_i32(config, &mut self.state.material_pointers, &mut self.buffers.contact_buffers.buffers[4]);
+        let settings = &config.prog_settings;
+        let masses = self.state.masses(settings);
+        let gravity = barnes_hut::self_gravity(&self.state.pos, &masses, settings.gravitational_constant, settings.barnes_hut_theta, settings.gravity_softening);
+        config.queue.write_buffer(&self.buffers.mov_buffers.buffers[9], 0, bytemuck::cast_slice(&gravity));
+        return barnes_hut::hold_steps(&gravity, &self.state.radii, settings.gravity_softening, settings.timestep()).min(i32::MAX as u32) as i32;
+    }
+
+    // Same idea as update_self_gravity, the drag is worked out on the CPU from the read back positions and velocities once a frame.
+    // The overlay arrows come along with it.
+    pub fn update_fluid(&mut self, config: &mut WGPUConfig) {
+        State::update_f32(config, &mut self.state.pos, &mut self.buffers.pos_buffer.buffer);
+        State::update_f32(config, &mut self.state.vel, &mut self.buffers.mov_buffers.buffers[0]);
+        State::update_f32(config, &mut self.state.radii, &mut self.buffers.radii_buffer.buffer);
+        State::update_i32(config, &mut self.state.material_pointers, &mut self.buffers.contact_buffers.buffers[4]);
+        let settings = &config.prog_settings;
+        let drag = self.state.fluid_drag(settings);
+        config.queue.write_buffer(&self.buffers.mov_buffers.buffers[12], 0, bytemuck::cast_slice(&drag));
+        if settings.fluid.overlay {
+            let grid = fluid::FluidGrid::new(&settings.fluid, (settings.hor_bound, settings.vert_bound), &self.state.pos, &self.state.areas());
+            self.fluid_cells = grid.width*grid.height;
+            self.buffers.fluid_buffer.updateUniform(&config.device, bytemuck::cast_slice(&grid.overlay()));
+        }
+    }
+
+    // Emitters, sinks and outflow walls, once a frame before compute with elapsed the time the last frame covered.
+    // Only the positions and radii come back unless the particle count changes, then everything does and goes back up through upload_particles.
+    pub fn update_emitters(&mut self, config: &mut WGPUConfig, elapsed: f32) {
+        State::update_f32(config, &mut self.state.pos, &mut self.buffers.pos_buffer.buffer);
+        State::update_f32(config, &mut self.state.radii, &mut self.buffers.radii_buffer.buffer);
+        let keep = match self.emitters.pending(&config.prog_settings, &self.state, elapsed) {
+            Some(keep) => keep,
+            None => return,
+        };
+        self.update_state(config);
+        let old_count = self.state.p_count;
+        let max_contacts = config.prog_settings.max_contacts;
+        let mut vel_buf = vec![0.0; old_count*2];
+        let mut rot_vel_buf = vec![0.0; old_count];
+        let mut temperature_buf = vec![0.0; old_count];
+        let mut contacts = vec![0; CONTACT_SIZE*max_contacts*old_count];
+        State::update_f32(config, &mut vel_buf, &mut self.buffers.mov_buffers.buffers[1]);
+        State::update_f32(config, &mut rot_vel_buf, &mut self.buffers.mov_buffers.buffers[4]);
+        State::update_f32(config, &mut temperature_buf, &mut self.buffers.mov_buffers.buffers[11]);
+        State::update_i32(config, &mut contacts, &mut self.buffers.contact_buffers.buffers[2]);
+
+        let map = self.emitters.apply(&config.prog_settings, &mut self.state, &keep);
+        let count = self.state.p_count;
+        // each particle's run of contacts moves with it, a contact with a particle that's gone is emptied (all -1)
+        let mut moved_contacts = vec![-1; CONTACT_SIZE*max_contacts*count];
+        for old in 0..old_count {
+            if map[old] == -1 {
+                continue;
+            }
+            let new = map[old] as usize;
+            for k in 0..max_contacts {
+                let (from, to) = ((old*max_contacts + k)*CONTACT_SIZE, (new*max_contacts + k)*CONTACT_SIZE);
+                let b = contacts[from + 1];
+                if b < 0 || b as usize >= old_count || map[b as usize] == -1 {
+                    continue;
+                }
+                moved_contacts[to..to + CONTACT_SIZE].copy_from_slice(&contacts[from..from + CONTACT_SIZE]);
+                moved_contacts[to] = new as i32;
+                moved_contacts[to + 1] = map[b as usize];
+            }
+        }
+        let moved = |values: &[f32], stride: usize, fresh: &[f32]| {
+            let mut remapped = fresh.to_vec();
+            for old in 0..old_count {
+                if map[old] != -1 {
+                    let new = map[old] as usize;
+                    remapped[new*stride..(new + 1)*stride].copy_from_slice(&values[old*stride..(old + 1)*stride]);
+                }
+            }
+            return remapped;
+        };
+        let vel_buf = moved(&vel_buf, 2, &self.state.vel);
+        let rot_vel_buf = moved(&rot_vel_buf, 1, &self.state.rot_vel);
+        let temperature_buf = moved(&temperature_buf, 1, &self.state.temperature);
+        self.upload_particles(config, &vel_buf, &rot_vel_buf, &temperature_buf, &moved_contacts, false);
+    }
+
+    // Puts every per particle buffer back from the State after the count changed, with the velocity and temperature buffers and contacts passed in.
+    // Buffers are written in place while they have room, past that they're swapped for ones twice the size so a steady feed doesn't reallocate every frame.
+    // The shaders only look at the first settings.particles, anything after that is spare capacity. rebuild makes them all again at the exact size.
+    pub fn upload_particles(&mut self, config: &mut WGPUConfig, vel_buf: &[f32], rot_vel_buf: &[f32], temperature_buf: &[f32], contacts: &[i32], rebuild: bool) {
+        let count = self.state.p_count;
+        let max_contacts = config.prog_settings.max_contacts;
+        config.prog_settings.set_particles(count);
+        let state = &self.state;
+        WGPUComputeProg::fill(config, &mut self.buffers.pos_buffer, bytemuck::cast_slice(&state.pos), rebuild);
+        WGPUComputeProg::fill(config, &mut self.buffers.radii_buffer, bytemuck::cast_slice(&state.radii), rebuild);
+        WGPUComputeProg::fill(config, &mut self.buffers.selections, bytemuck::cast_slice(&state.selections), rebuild);
+        for (index, contents) in [
+            (0, bytemuck::cast_slice(&state.vel)),
+            (1, bytemuck::cast_slice(vel_buf)),
+            (2, bytemuck::cast_slice(&state.rot)),
+            (3, bytemuck::cast_slice(&state.rot_vel)),
+            (4, bytemuck::cast_slice(rot_vel_buf)),
+            (5, bytemuck::cast_slice(&state.acc)),
+            (6, bytemuck::cast_slice(&state.fixity)),
+            (7, bytemuck::cast_slice(&state.forces)),
+            (9, bytemuck::cast_slice(&vec![0.0 as f32; count*3])),
+            (10, bytemuck::cast_slice(&state.temperature)),
+            (11, bytemuck::cast_slice(temperature_buf)),
+            (12, bytemuck::cast_slice(&vec![0.0 as f32; count*3])),
+        ] {
+            WGPUComputeProg::fill_group(config, &mut self.buffers.mov_buffers, index, contents, rebuild);
+        }
+        for (index, contents) in [
+            (0, bytemuck::cast_slice(&state.bonds)),
+            (1, bytemuck::cast_slice(&state.bond_info)),
+            (2, bytemuck::cast_slice(contacts)),
+            (3, bytemuck::cast_slice(&vec![-1 as i32; max_contacts*count])),
+            (4, bytemuck::cast_slice(&state.material_pointers)),
+            (7, bytemuck::cast_slice(&vec![0 as u32; count])),
+        ] {
+            WGPUComputeProg::fill_group(config, &mut self.buffers.contact_buffers, index, contents, rebuild);
+        }
+        WGPUComputeProg::fill_group(config, &mut self.buffers.data_buffers, 0, bytemuck::cast_slice(&state.data), rebuild);
+        WGPUComputeProg::fill_group(config, &mut self.buffers.data_buffers, 1, bytemuck::cast_slice(&vec![0.0 as f32; ENERGY_SIZE*count]), rebuild);
+        self.update_clumps(config);
+        self.update_polygons(config);
+        self.update_collision_settings(config);
+    }
+
+    fn fill(config: &mut WGPUConfig, buffer: &mut BufferUniform, contents: &[u8], rebuild: bool) {
+        if rebuild {
+            buffer.updateUniform(&config.device, contents);
+        } else if buffer.buffer.size() >= contents.len() as u64 {
+            config.queue.write_buffer(&buffer.buffer, 0, contents);
+        } else {
+            buffer.updateUniform(&config.device, &[contents, &vec![0; contents.len()]].concat());
+        }
+    }
+
+    fn fill_group(config: &mut WGPUConfig, group: &mut BufferGroup, index: usize, contents: &[u8], rebuild: bool) {
+        if rebuild {
+            group.updateBuffer(&config.device, contents, index);
+        } else if group.buffers[index].size() >= contents.len() as u64 {
+            config.queue.write_buffer(&group.buffers[index], 0, contents);
+        } else {
+            group.updateBuffer(&config.device, &[contents, &vec![0; contents.len()]].concat(), index);
+        }
+    }
+
+    // 2D_Simulation.wgsl with its contact and bond limits set from the settings, they size arrays so they can't go in the uniform.
+    // Changing either needs a new pipeline, and the contact buffers are sized by max_contacts too, so the Setup sliders reset.
+    fn simulation_shader(settings: &settings::Settings) -> String {
+        let mut source = include_str!("./shaders/2D_Simulation.wgsl").to_string();
+        // the shader keeps the defaults, if one of these lines changes there the limit would quietly stop following the slider
+        for (name, default, value) in [("MAX_CONTACTS", 8, settings.max_contacts), ("MAX_BONDS", 6, settings.max_bonds)] {
+            let line = format!("const {}: u32 = {}u;", name, default);
+            assert!(source.contains(&line), "2D_Simulation.wgsl no longer has `{}`", line);
+            source = source.replacen(&line, &format!("const {}: u32 = {}u;", name, value), 1);
+        }
+        return source;
+    }
+
+    // Resizes the broadphase grid to the current walls and radii, re-estimates the critical timestep, then pushes the collision settings.
+    // Call after anything that moves the walls or changes a radius or material.
+    pub fn update_collision_settings(&mut self, config: &mut WGPUConfig) {
+        config.prog_settings.set_coll_grid(self.state.largest_radius());
+        config.prog_settings.critical_delta_time = self.state.critical_delta_time(&config.prog_settings);
+        let cells = config.prog_settings.coll_cells();
+        if self.buffers.contact_buffers.buffers[5].size() != (cells*4) as u64 {
+            self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&vec![0 as u32; cells]), 5);
+            self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&vec![0 as u32; cells + 1]), 6);
+        }
+        if self.buffers.contact_buffers.buffers[7].size() < (self.state.p_count*4) as u64 {
+            self.buffers.contact_buffers.updateBuffer(&config.device, bytemuck::cast_slice(&vec![0 as u32; self.state.p_count]), 7);
+        }
+        self.buffers.collision_settings.updateUniform(&config.device, bytemuck::cast_slice(&config.prog_settings.collison_settings()));
     }
 
     // fn save_state(&self , state: &State) {
@@ -953,14 +1408,14 @@ impl WGPUComputeProg {
         config.queue.submit(Some(encoder.finish()));
     }
 
-    pub fn compute(&mut self, config: &mut WGPUConfig){
+    pub fn compute(&mut self, config: &mut WGPUConfig, generations: i32){
         
 
         let mut encoder = config.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
         let mut compute_pass_descriptor = wgpu::ComputePassDescriptor::default();
 
-        for i in 0..config.prog_settings.genPerFrame {
+        for i in 0..generations {
             // LAWS OF MOTION
             {
                 let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
@@ -977,6 +1432,43 @@ impl WGPUComputeProg {
 
             }
 
+            // RIGID CLUMPS
+            if self.clump_count > 0 {
+                let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
+
+                compute_pass.set_pipeline(&self.clump_pipeline);
+
+                compute_pass.set_bind_group(0, &self.buffers.pos_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.buffers.mov_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.buffers.radii_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(3, &self.buffers.contact_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(4, &self.buffers.collision_settings.bind_group, &[]);
+                compute_pass.set_bind_group(5, &self.buffers.material_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(6, &self.buffers.data_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(7, &self.buffers.wall_buffer.bind_group, &[]);
+
+                compute_pass.dispatch_workgroups((self.clump_count as f32/256.0).ceil() as u32, 1, 1);
+            }
+
+            // BROADPHASE GRID
+            let cell_workgroups = (config.prog_settings.coll_cells() as f32/256.0).ceil() as u32;
+            for (pipeline, workgroups) in [
+                (&self.grid_clear_pipeline, cell_workgroups),
+                (&self.grid_count_pipeline, config.prog_settings.workgroups as u32),
+                (&self.grid_scan_pipeline, 1),
+                (&self.grid_sort_pipeline, config.prog_settings.workgroups as u32),
+            ] {
+                let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
+
+                compute_pass.set_pipeline(pipeline);
+
+                compute_pass.set_bind_group(0, &self.buffers.pos_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.buffers.contact_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.buffers.collision_settings.bind_group, &[]);
+
+                compute_pass.dispatch_workgroups(workgroups, 1, 1);
+            }
+
             // SIMULATION/COLLISIONS/BONDS
 
             {
@@ -990,11 +1482,66 @@ impl WGPUComputeProg {
                 compute_pass.set_bind_group(3, &self.buffers.contact_buffers.bind_group, &[]);         
                 compute_pass.set_bind_group(4, &self.buffers.collision_settings.bind_group, &[]);  
                 compute_pass.set_bind_group(5, &self.buffers.material_buffer.bind_group, &[]);
-                compute_pass.set_bind_group(6, &self.buffers.data_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(6, &self.buffers.data_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(7, &self.buffers.wall_buffer.bind_group, &[]);
 
                 compute_pass.dispatch_workgroups(config.prog_settings.workgroups as u32, 1, 1);
 
             }
+
+            // PLATENS/WALL REACTIONS
+            if config.prog_settings.wall_count() > 0 {
+                let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
+
+                compute_pass.set_pipeline(&self.platen_pipeline);
+
+                compute_pass.set_bind_group(0, &self.buffers.pos_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.buffers.mov_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.buffers.radii_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(3, &self.buffers.contact_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(4, &self.buffers.collision_settings.bind_group, &[]);
+                compute_pass.set_bind_group(5, &self.buffers.material_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(6, &self.buffers.data_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(7, &self.buffers.wall_buffer.bind_group, &[]);
+
+                compute_pass.dispatch_workgroups(config.prog_settings.wall_count() as u32, 1, 1);
+            }
+
+            // ENERGY BUDGET
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
+
+                compute_pass.set_pipeline(&self.energy_pipeline);
+
+                compute_pass.set_bind_group(0, &self.buffers.pos_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.buffers.mov_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.buffers.radii_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(3, &self.buffers.contact_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(4, &self.buffers.collision_settings.bind_group, &[]);
+                compute_pass.set_bind_group(5, &self.buffers.material_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(6, &self.buffers.data_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(7, &self.buffers.wall_buffer.bind_group, &[]);
+
+                compute_pass.dispatch_workgroups(1, 1, 1);
+            }
+
+            // LEES-EDWARDS OFFSET
+            if config.prog_settings.boundary_y == 2 {
+                let mut compute_pass = encoder.begin_compute_pass(&compute_pass_descriptor);
+
+                compute_pass.set_pipeline(&self.lees_edwards_pipeline);
+
+                compute_pass.set_bind_group(0, &self.buffers.pos_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.buffers.mov_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.buffers.radii_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(3, &self.buffers.contact_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(4, &self.buffers.collision_settings.bind_group, &[]);
+                compute_pass.set_bind_group(5, &self.buffers.material_buffer.bind_group, &[]);
+                compute_pass.set_bind_group(6, &self.buffers.data_buffers.bind_group, &[]);
+                compute_pass.set_bind_group(7, &self.buffers.wall_buffer.bind_group, &[]);
+
+                compute_pass.dispatch_workgroups(1, 1, 1);
+            }
         }
 
         config.queue.submit(Some(encoder.finish()));
@@ -1010,4 +1557,19 @@ impl WGPUComputeProg {
                     ]",
                         pos[i*2], pos[i*2+1], vel[i*2], vel[i*2+1], radii[i], 255.0*color[i*3], 255.0*color[i*3+1], 255.0*color[i*3+2]);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulation_shader_takes_the_limits() {
+        let mut settings = settings::Settings::new();
+        settings.max_contacts = 12;
+        settings.max_bonds = 3;
+        let source = WGPUComputeProg::simulation_shader(&settings);
+        assert!(source.contains("const MAX_CONTACTS: u32 = 12u;"));
+        assert!(source.contains("const MAX_BONDS: u32 = 3u;"));
+    }
 }
\ No newline at end of file
@@ -51,7 +51,7 @@ impl Vertex {
 
 pub struct Uniform{
     label: String,
-    buffer: wgpu::Buffer,
+    pub buffer: wgpu::Buffer,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
     binding: u32